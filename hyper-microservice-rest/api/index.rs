@@ -1,115 +1,87 @@
-use std::sync::{Arc, Mutex};
-use slab::Slab;
-use std::fmt;
-use vercel_runtime::{run, Body as VercelBody, Error, Request as VercelRequest, Response as VercelResponse, StatusCode};
-use http::Method;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use vercel_runtime::{run, Body as VercelBody, Error, Request as VercelRequest, Response as VercelResponse};
+use hyper_microservice_rest::auth::Authenticator;
+use hyper_microservice_rest::keys::Limits;
+use hyper_microservice_rest::router::Router;
+use hyper_microservice_rest::server::{self, DEFAULT_ADDR};
+use hyper_microservice_rest::store::StoreConfig;
+use hyper_microservice_rest::vercel;
+use hyper_microservice_rest::webhook::Retry;
 
-const INDEX: &str = r#"
-<!doctype html>
-<html>
-    <head>
-        <title>Rust REST Microservice - Hyper 1.7.0 on Vercel</title>
-    </head>
-    <body>
-        <h3>Rust REST Microservice - Hyper 1.7.0 on Vercel</h3>
-        <p>This microservice demonstrates:</p>
-        <ul>
-            <li><strong>hyper 1.7.0</strong> - HTTP library</li>
-            <li><strong>Vercel Runtime</strong> - Serverless deployment</li>
-            <li><strong>REST API</strong> - Full CRUD operations</li>
-            <li><strong>User management</strong> - Create, read, update, delete users</li>
-        </ul>
-        <h4>API Endpoints:</h4>
-        <ul>
-            <li><strong>GET /</strong> - This page</li>
-            <li><strong>GET /user/{id}</strong> - Get user by ID</li>
-            <li><strong>POST /user/</strong> - Create new user</li>
-            <li><strong>PUT /user/{id}</strong> - Update user</li>
-            <li><strong>DELETE /user/{id}</strong> - Delete user</li>
-        </ul>
-        <p>Deployed on Vercel as a serverless function!</p>
-    </body>
-</html>
-"#;
+const USAGE: &str = "usage: index [--listen [ADDR]]";
 
-type UserId = u64;
+/// How often, under `--listen`, new changes are sent to webhooks and failed
+/// deliveries retried.
+const WEBHOOK_INTERVAL: Duration = Duration::from_millis(500);
 
-struct UserData;
+async fn handler(req: VercelRequest, router: Arc<Router>) -> Result<VercelResponse<VercelBody>, Error> {
+    // The router blocks on the store and, for the cron job, on webhook
+    // receivers, so it runs off the async runtime.
+    Ok(tokio::task::spawn_blocking(move || vercel::handle(&router, req)).await?)
+}
 
-impl fmt::Display for UserData {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str("{}")
+/// `--listen [ADDR]` serves on a local socket instead of the Vercel runtime.
+fn listen_addr(mut args: impl Iterator<Item = String>) -> Result<Option<SocketAddr>, Error> {
+    let addr = match args.next().as_deref() {
+        None => return Ok(None),
+        Some("--listen") => args.next().unwrap_or_else(|| DEFAULT_ADDR.to_string()),
+        Some(_) => return Err(USAGE.into()),
+    };
+    if args.next().is_some() {
+        return Err(USAGE.into());
     }
+    Ok(Some(addr.parse()?))
 }
 
-type UserDb = Arc<Mutex<Slab<UserData>>>;
-
-const USER_PATH: &str = "/user/";
-
-async fn handler(req: VercelRequest) -> Result<VercelResponse<VercelBody>, Error> {
-    let path = req.uri().path();
-    let user_db = Arc::new(Mutex::new(Slab::<UserData>::new())); // In production, this would be shared state
-    
-    let response = match (req.method(), path) {
-        (&Method::GET, "/") => {
-            VercelResponse::new(VercelBody::from(INDEX))
-        },
-        (method, path) if path.starts_with(USER_PATH) => {
-            let user_id = path.trim_start_matches(USER_PATH)
-                .parse::<UserId>()
-                .ok()
-                .map(|x| x as usize);
-            let mut users = user_db.lock().unwrap();
-            
-            match (method, user_id) {
-                (&Method::GET, Some(id)) => {
-                    if let Some(data) = users.get(id) {
-                        VercelResponse::new(VercelBody::from(data.to_string()))
-                    } else {
-                        response_with_code(StatusCode::NOT_FOUND)
-                    }
-                },
-                (&Method::POST, None) => {
-                    let id = users.insert(UserData);
-                    VercelResponse::new(VercelBody::from(id.to_string()))
-                },
-                (&Method::POST, Some(_)) => {
-                    response_with_code(StatusCode::BAD_REQUEST)
-                },
-                (&Method::PUT, Some(id)) => {
-                    if let Some(user) = users.get_mut(id) {
-                        *user = UserData;
-                        response_with_code(StatusCode::OK)
-                    } else {
-                        response_with_code(StatusCode::NOT_FOUND)
-                    }
-                },
-                (&Method::DELETE, Some(id)) => {
-                    if users.contains(id) {
-                        users.remove(id);
-                        response_with_code(StatusCode::OK)
-                    } else {
-                        response_with_code(StatusCode::NOT_FOUND)
-                    }
-                },
-                _ => {
-                    response_with_code(StatusCode::METHOD_NOT_ALLOWED)
-                },
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Opened once so every request sees the same users.
+    let store = StoreConfig::from_env()?;
+    let users = store.open()?;
+    let webhooks = Arc::new(store.open_webhooks(Arc::clone(&users), Retry::default())?);
+    let router = Router::new(users).with_webhooks(Arc::clone(&webhooks));
+    let auth = Authenticator::from_env()?;
+    let router = match env::var("API_KEYS").as_deref() {
+        Err(_) | Ok("off") => router,
+        Ok("on") => {
+            // The /keys endpoints are only for admins, and without
+            // authentication there is no telling who is one.
+            if auth.is_none() {
+                return Err("API_KEYS is on but no AUTH_JWT_* key is set; the /keys endpoints need authentication".into());
             }
-        },
-        _ => {
-            response_with_code(StatusCode::NOT_FOUND)
-        },
+            router.with_api_keys(store.open_keys(Limits::from_env()?)?)
+        }
+        Ok(other) => return Err(format!("unknown API_KEYS {:?}, expected \"on\" or \"off\"", other).into()),
     };
-    
-    Ok(response)
-}
+    let router = match auth {
+        Some(auth) => router.with_auth(auth),
+        None => {
+            eprintln!("No AUTH_JWT_* key is set; user endpoints are open to anyone");
+            router
+        }
+    };
+    // Vercel Cron sends this as a bearer token; it is a plain shared
+    // secret, not a JWT.
+    let router = match env::var("CRON_SECRET") {
+        Ok(secret) if !secret.is_empty() => router.with_cron_secret(secret),
+        _ => router,
+    };
+    let router = Arc::new(router);
 
-fn response_with_code(status_code: StatusCode) -> VercelResponse<VercelBody> {
-    VercelResponse::new(VercelBody::from(""))
+    match listen_addr(env::args().skip(1))? {
+        Some(addr) => {
+            Arc::clone(&webhooks).spawn(WEBHOOK_INTERVAL);
+            let listener = TcpListener::bind(addr).await?;
+            println!("Listening on http://{}", listener.local_addr()?);
+            server::serve(listener, router).await?;
+        }
+        // A frozen function instance cannot keep time, so the cron job in
+        // vercel.json calls /webhooks/cron instead.
+        None => run(|req| handler(req, Arc::clone(&router))).await?,
+    }
+    Ok(())
 }
-
-#[tokio::main]
-async fn main() -> Result<(), Error> {
-    run(handler).await
-} 
\ No newline at end of file
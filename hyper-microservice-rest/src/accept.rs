@@ -0,0 +1,53 @@
+//! Content negotiation with the `Accept` header (RFC 9110, section 12.5.1).
+
+/// Picks the type from `offered` that `accept` rates highest. Without an
+/// `Accept` header the first offered type wins, as it does between types
+/// rated the same. `None` means the client takes none of them.
+pub fn preferred<'a>(accept: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return offered.first().copied(),
+    };
+    let ranges: Vec<(&str, f32)> = accept.split(',').filter_map(range).collect();
+
+    let mut best = None;
+    for &media_type in offered {
+        let q = quality(&ranges, media_type);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((media_type, q));
+        }
+    }
+    best.map(|(media_type, _)| media_type)
+}
+
+/// Splits one element of an `Accept` header into its range and weight.
+fn range(element: &str) -> Option<(&str, f32)> {
+    let mut parts = element.split(';').map(str::trim);
+    let range = parts.next().filter(|range| !range.is_empty())?;
+    let q = parts
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+        .map_or(Some(1.0), |(_, q)| q.trim().parse().ok())?;
+    Some((range, q))
+}
+
+/// The weight of the most specific range that matches `media_type`.
+fn quality(ranges: &[(&str, f32)], media_type: &str) -> f32 {
+    let main = media_type.split('/').next().unwrap_or("");
+    let specificity = |range: &str| {
+        if range.eq_ignore_ascii_case(media_type) {
+            Some(2)
+        } else if range.strip_suffix("/*").is_some_and(|r| r.eq_ignore_ascii_case(main)) {
+            Some(1)
+        } else if range == "*/*" {
+            Some(0)
+        } else {
+            None
+        }
+    };
+    ranges
+        .iter()
+        .filter_map(|(range, q)| specificity(range).map(|s| (s, *q)))
+        .max_by_key(|(s, _)| *s)
+        .map_or(0.0, |(_, q)| q)
+}
@@ -0,0 +1,212 @@
+//! Bearer-token authentication with JWTs verified against local keys.
+//!
+//! Tokens are signed with HS256 (a shared secret) or RS256 (an RSA key pair
+//! whose public half is configured here). Nothing is fetched from an
+//! identity provider: a token is valid if its signature checks out against
+//! a configured key and its claims hold.
+
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+
+/// The shortest `AUTH_JWT_HS256_SECRET` accepted: 256 bits, the size of
+/// the HMAC-SHA256 output, as RFC 7518 requires for HS256 keys.
+pub const MIN_HS256_SECRET: usize = 32;
+
+/// What a caller may do. Each role can do everything the ones before it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// May read users.
+    ReadOnly,
+    /// May also create and change users.
+    Editor,
+    /// May also delete users.
+    Admin,
+}
+
+impl Role {
+    const ALL: [Role; 3] = [Role::ReadOnly, Role::Editor, Role::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::ReadOnly => "read-only",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Role> {
+        Role::ALL.iter().copied().find(|role| role.as_str() == s)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Who made a request, according to their token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// The `sub` claim, if the token had one.
+    pub subject: Option<String>,
+    /// The highest role the token grants.
+    pub role: Role,
+}
+
+/// Why a request could not be authenticated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    /// No usable token: 401.
+    Unauthenticated(String),
+    /// A valid token that lacks the rights for the request: 403.
+    Forbidden(String),
+}
+
+/// The claims a token is read for. Others are ignored.
+#[derive(Deserialize)]
+struct Claims {
+    sub: Option<String>,
+    /// Role names; unknown ones are ignored.
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+/// Checks bearer tokens against the configured keys.
+#[derive(Clone)]
+pub struct Authenticator {
+    keys: Vec<(Algorithm, DecodingKey)>,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl Authenticator {
+    /// An authenticator that accepts no tokens until keys are added.
+    pub fn new() -> Authenticator {
+        Authenticator {
+            keys: Vec::new(),
+            issuer: None,
+            audience: None,
+        }
+    }
+
+    /// Accepts HS256 tokens signed with `secret`.
+    pub fn hs256(mut self, secret: &[u8]) -> Authenticator {
+        self.keys.push((Algorithm::HS256, DecodingKey::from_secret(secret)));
+        self
+    }
+
+    /// Accepts RS256 tokens signed by the private half of the PEM-encoded
+    /// RSA public key `pem`.
+    pub fn rs256(mut self, pem: &[u8]) -> Result<Authenticator, String> {
+        let key = DecodingKey::from_rsa_pem(pem).map_err(|e| format!("bad RS256 public key: {}", e))?;
+        self.keys.push((Algorithm::RS256, key));
+        Ok(self)
+    }
+
+    /// Requires tokens to carry this `iss` claim.
+    pub fn issuer(mut self, issuer: impl Into<String>) -> Authenticator {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Requires tokens to carry this `aud` claim.
+    pub fn audience(mut self, audience: impl Into<String>) -> Authenticator {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    /// Reads `AUTH_JWT_HS256_SECRET` and `AUTH_JWT_RS256_PUBLIC_KEY` (PEM),
+    /// plus the optional `AUTH_JWT_ISSUER` and `AUTH_JWT_AUDIENCE`. Returns
+    /// `None` if neither key is set, which leaves the service open. An
+    /// HS256 secret shorter than [`MIN_HS256_SECRET`] bytes is an error:
+    /// anyone could guess it and sign their own admin token.
+    pub fn from_env() -> Result<Option<Authenticator>, String> {
+        let mut auth = Authenticator::new();
+        if let Ok(secret) = env::var("AUTH_JWT_HS256_SECRET") {
+            if secret.len() < MIN_HS256_SECRET {
+                return Err(format!(
+                    "AUTH_JWT_HS256_SECRET is {} bytes long; it must be at least {}",
+                    secret.len(),
+                    MIN_HS256_SECRET
+                ));
+            }
+            auth = auth.hs256(secret.as_bytes());
+        }
+        if let Ok(pem) = env::var("AUTH_JWT_RS256_PUBLIC_KEY") {
+            auth = auth.rs256(pem.as_bytes())?;
+        }
+        if auth.keys.is_empty() {
+            return Ok(None);
+        }
+        if let Ok(issuer) = env::var("AUTH_JWT_ISSUER") {
+            auth = auth.issuer(issuer);
+        }
+        if let Ok(audience) = env::var("AUTH_JWT_AUDIENCE") {
+            auth = auth.audience(audience);
+        }
+        Ok(Some(auth))
+    }
+
+    /// Checks the value of an `Authorization` header, if there was one.
+    pub fn authenticate(&self, authorization: Option<&str>) -> Result<Principal, AuthError> {
+        let unauthenticated = |detail: &str| AuthError::Unauthenticated(detail.to_string());
+        let authorization = authorization.ok_or_else(|| unauthenticated("a bearer token is required"))?;
+        let token = match authorization.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+            _ => return Err(unauthenticated("the Authorization header must hold a bearer token")),
+        };
+
+        let invalid = |e: jsonwebtoken::errors::Error| unauthenticated(&format!("invalid token: {}", e));
+        let header = jsonwebtoken::decode_header(token).map_err(invalid)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|(algorithm, _)| *algorithm == header.alg)
+            .map(|(_, key)| key)
+            .ok_or_else(|| unauthenticated(&format!("tokens signed with {:?} are not accepted", header.alg)))?;
+
+        let mut validation = Validation::new(header.alg);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let claims = jsonwebtoken::decode::<Claims>(token, key, &validation)
+            .map_err(invalid)?
+            .claims;
+
+        let role = claims
+            .roles
+            .iter()
+            .filter_map(|name| Role::parse(name))
+            .max()
+            .ok_or_else(|| AuthError::Forbidden("the token grants no role".to_string()))?;
+        Ok(Principal {
+            subject: claims.sub,
+            role,
+        })
+    }
+
+    /// Authenticates and then checks the caller has at least `required`.
+    pub fn authorize(&self, authorization: Option<&str>, required: Role) -> Result<Principal, AuthError> {
+        let principal = self.authenticate(authorization)?;
+        if principal.role < required {
+            return Err(AuthError::Forbidden(format!(
+                "this needs the {} role, the token grants {}",
+                required, principal.role
+            )));
+        }
+        Ok(principal)
+    }
+}
+
+impl Default for Authenticator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
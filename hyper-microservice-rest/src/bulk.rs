@@ -0,0 +1,384 @@
+//! `GET /users/export` and `POST /users/import`: every user at once, as
+//! newline-delimited JSON or CSV.
+//!
+//! Both formats carry the same columns. An export can be edited and
+//! imported again: rows with an `id` update that user, rows without one
+//! create a new user, and `created_at` and `updated_at` are ignored.
+
+use bytes::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::body::{Body, Frame};
+use schemars::JsonSchema;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::store::{Batch, Change, Outcome, StoreError, UserStore};
+use crate::user::{User, UserData, UserId, UserInput};
+
+pub const EXPORT_PATH: &str = "/users/export";
+pub const IMPORT_PATH: &str = "/users/import";
+
+pub const NDJSON: &str = "application/x-ndjson";
+pub const CSV: &str = "text/csv";
+/// Every format, the default first.
+pub const FORMATS: &[&str] = &[NDJSON, CSV];
+
+const COLUMNS: [&str; 6] = ["id", "name", "email", "created_at", "updated_at", "version"];
+
+/// How many users an export reads from the store at a time.
+const EXPORT_PAGE: usize = 100;
+
+/// Every user in `format`, one per line or record, in id order. The users
+/// are read a page at a time as the body is sent, and each row is a frame
+/// of its own, after the CSV header if there is one.
+#[derive(Clone)]
+pub struct Export {
+    users: Arc<dyn UserStore>,
+    format: &'static str,
+    /// The CSV header, until it is sent.
+    header: bool,
+    /// The rest of the page last read.
+    page: VecDeque<(UserId, UserData)>,
+    /// The last user read, where the next page starts.
+    after: Option<UserId>,
+    done: bool,
+}
+
+impl Export {
+    pub fn new(users: Arc<dyn UserStore>, format: &'static str) -> Export {
+        Export {
+            users,
+            format,
+            header: format == CSV,
+            page: VecDeque::new(),
+            after: None,
+            done: false,
+        }
+    }
+
+    fn row(&self, id: UserId, user: &UserData) -> Vec<u8> {
+        if self.format == CSV {
+            let time = |t: &DateTime<Utc>| t.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+            csv_record(&[
+                id.to_string(),
+                user.name.clone(),
+                user.email.clone(),
+                time(&user.created_at),
+                time(&user.updated_at),
+                user.version.to_string(),
+            ])
+        } else {
+            let mut ndjson = serde_json::to_vec(&User { id, data: user }).expect("users always serialize");
+            ndjson.push(b'\n');
+            ndjson
+        }
+    }
+}
+
+impl Iterator for Export {
+    type Item = Result<Vec<u8>, StoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.header {
+            self.header = false;
+            return Some(Ok(csv_record(&COLUMNS)));
+        }
+        if self.page.is_empty() && !self.done {
+            match self.users.list_after(self.after, EXPORT_PAGE) {
+                Ok(page) => {
+                    self.done = page.len() < EXPORT_PAGE;
+                    self.after = page.last().map(|(id, _)| *id);
+                    self.page = page.into();
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let (id, user) = self.page.pop_front()?;
+        Some(Ok(self.row(id, &user)))
+    }
+}
+
+impl Body for Export {
+    type Data = Bytes;
+    type Error = StoreError;
+
+    fn poll_frame(self: Pin<&mut Self>, _: &mut Context) -> Poll<Option<Result<Frame<Bytes>, StoreError>>> {
+        Poll::Ready(self.get_mut().next().map(|row| row.map(|row| Frame::data(Bytes::from(row)))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done && self.page.is_empty() && !self.header
+    }
+}
+
+/// One CSV record, with its line ending.
+fn csv_record<S: AsRef<[u8]>>(fields: &[S]) -> Vec<u8> {
+    let mut csv = csv::Writer::from_writer(Vec::new());
+    csv.write_record(fields).expect("writing to memory cannot fail");
+    csv.into_inner().expect("writing to memory cannot fail")
+}
+
+/// One row of an import, before it is checked against the store.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Row {
+    /// The user to update. Without it, the row creates a user.
+    id: Option<UserId>,
+    /// If given, the row only applies to the user at this version.
+    version: Option<u64>,
+    name: String,
+    email: String,
+    #[serde(default, rename = "created_at")]
+    _created_at: Option<IgnoredAny>,
+    #[serde(default, rename = "updated_at")]
+    _updated_at: Option<IgnoredAny>,
+}
+
+/// A row with the line it started on, or why it could not be read.
+type Line = (usize, Result<Row, String>);
+
+/// A parsed import.
+pub struct Import {
+    rows: Vec<Line>,
+}
+
+impl Import {
+    /// Splits `body` into rows. Rows that do not parse are kept, to be
+    /// reported; only a CSV header without the needed columns fails the
+    /// whole import.
+    pub fn parse(format: &str, body: &[u8]) -> Result<Import, String> {
+        let rows = if format == CSV {
+            parse_csv(body)?
+        } else {
+            parse_ndjson(body)
+        };
+        Ok(Import { rows })
+    }
+}
+
+fn parse_ndjson(body: &[u8]) -> Vec<Line> {
+    body.split(|&b| b == b'\n')
+        .enumerate()
+        .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+        .map(|(i, line)| (i + 1, serde_json::from_slice(line).map_err(|e| e.to_string())))
+        .collect()
+}
+
+fn parse_csv(body: &[u8]) -> Result<Vec<Line>, String> {
+    let mut reader = csv::Reader::from_reader(body);
+    let headers = reader.headers().map_err(|e| format!("bad CSV header: {}", e))?.clone();
+    if let Some(unknown) = headers.iter().find(|h| !COLUMNS.contains(h)) {
+        return Err(format!("unknown CSV column `{}`, expected some of {}", unknown, COLUMNS.join(", ")));
+    }
+    for required in ["name", "email"].iter() {
+        if !headers.iter().any(|h| h == *required) {
+            return Err(format!("the CSV header has no `{}` column", required));
+        }
+    }
+
+    Ok(reader
+        .records()
+        .map(|record| match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |p| p.line() as usize);
+                (line, record.deserialize(Some(&headers)).map_err(|e| e.to_string()))
+            }
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line() as usize);
+                (line, Err(e.to_string()))
+            }
+        })
+        .collect())
+}
+
+/// What happened to one row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RowResult {
+    Created,
+    Updated,
+    /// The row matched the user as stored, so nothing was written.
+    Unchanged,
+    Rejected,
+    /// The row was fine, but an atomic import rejected another row.
+    Skipped,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RowReport {
+    pub line: usize,
+    pub result: RowResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<UserId>,
+    /// Why the row was rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The response to an import.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Report {
+    /// Whether one rejected row kept every row from being applied.
+    pub atomic: bool,
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub rejected: usize,
+    pub rows: Vec<RowReport>,
+}
+
+impl Report {
+    fn new(atomic: bool, rows: Vec<RowReport>) -> Report {
+        let count = |result| rows.iter().filter(|row| row.result == result).count();
+        Report {
+            atomic,
+            created: count(RowResult::Created),
+            updated: count(RowResult::Updated),
+            unchanged: count(RowResult::Unchanged),
+            rejected: count(RowResult::Rejected),
+            rows,
+        }
+    }
+}
+
+/// What a row turned out to ask for, once checked against the store.
+enum Plan {
+    Write(Change),
+    Unchanged(UserId),
+    Rejected(String),
+}
+
+/// Checks every row and applies the good ones. An atomic import applies
+/// them only if there are no bad ones, and all in one write.
+pub fn import(users: &dyn UserStore, import: Import, atomic: bool, actor: &str) -> Result<Report, StoreError> {
+    let now = Utc::now();
+    let mut seen = HashMap::new();
+    let mut plans = Vec::with_capacity(import.rows.len());
+    for (line, row) in import.rows {
+        let plan = match row {
+            Ok(row) => plan(users, &mut seen, line, row, now)?,
+            Err(e) => Plan::Rejected(e),
+        };
+        plans.push((line, plan));
+    }
+
+    let report = |line, result, id, error| RowReport { line, result, id, error };
+    let rejected = |line, error| report(line, RowResult::Rejected, None, Some(error));
+    let done = |line, change: &Change, id| {
+        let result = match change {
+            Change::Insert(_) => RowResult::Created,
+            Change::Update { .. } => RowResult::Updated,
+        };
+        report(line, result, Some(id), None)
+    };
+    let changed_meanwhile = |outcome| match outcome {
+        Outcome::Missing => "the user was deleted by another request".to_string(),
+        _ => "the user was changed by another request".to_string(),
+    };
+
+    let any_rejected = plans.iter().any(|(_, plan)| matches!(plan, Plan::Rejected(_)));
+    let mut rows = Vec::with_capacity(plans.len());
+    if !atomic {
+        for (line, plan) in plans {
+            rows.push(match plan {
+                Plan::Write(change) => match users.apply_all(vec![change.clone()], actor)? {
+                    Batch::Done(ids) => done(line, &change, ids[0]),
+                    Batch::Refused { outcome, .. } => rejected(line, changed_meanwhile(outcome)),
+                },
+                Plan::Unchanged(id) => report(line, RowResult::Unchanged, Some(id), None),
+                Plan::Rejected(e) => rejected(line, e),
+            });
+        }
+        return Ok(Report::new(false, rows));
+    }
+
+    let batch = if any_rejected {
+        None
+    } else {
+        let changes = plans
+            .iter()
+            .filter_map(|(_, plan)| match plan {
+                Plan::Write(change) => Some(change.clone()),
+                _ => None,
+            })
+            .collect();
+        Some(users.apply_all(changes, actor)?)
+    };
+
+    let mut written = 0;
+    for (line, plan) in plans {
+        rows.push(match (plan, &batch) {
+            (Plan::Rejected(e), _) => rejected(line, e),
+            (Plan::Write(change), Some(Batch::Done(ids))) => {
+                written += 1;
+                done(line, &change, ids[written - 1])
+            }
+            (Plan::Write(_), Some(Batch::Refused { index, outcome })) if *index == written => {
+                written += 1;
+                rejected(line, changed_meanwhile(*outcome))
+            }
+            (Plan::Write(_), _) => {
+                written += 1;
+                report(line, RowResult::Skipped, None, None)
+            }
+            (Plan::Unchanged(id), Some(Batch::Done(_))) => report(line, RowResult::Unchanged, Some(id), None),
+            (Plan::Unchanged(id), _) => report(line, RowResult::Skipped, Some(id), None),
+        });
+    }
+    Ok(Report::new(true, rows))
+}
+
+/// Checks one row against the store. `seen` maps ids to the line that
+/// already claimed them, so a file cannot update one user twice.
+fn plan(
+    users: &dyn UserStore,
+    seen: &mut HashMap<UserId, usize>,
+    line: usize,
+    row: Row,
+    now: DateTime<Utc>,
+) -> Result<Plan, StoreError> {
+    let input = UserInput {
+        name: row.name,
+        email: row.email,
+    };
+    if let Err(e) = input.validate() {
+        return Ok(Plan::Rejected(e));
+    }
+
+    let id = match (row.id, row.version) {
+        (Some(id), _) => id,
+        (None, Some(_)) => return Ok(Plan::Rejected("`version` needs an `id`".to_string())),
+        (None, None) => return Ok(Plan::Write(Change::Insert(UserData::new(input, now)))),
+    };
+    if let Some(first) = seen.insert(id, line) {
+        return Ok(Plan::Rejected(format!("user {} is already on line {}", id, first)));
+    }
+    let existing = match users.get(id)? {
+        Some(existing) => existing,
+        None if users.deleted(id)? => return Ok(Plan::Rejected(format!("user {} was deleted", id))),
+        None => return Ok(Plan::Rejected(format!("there is no user with id {}", id))),
+    };
+    if let Some(version) = row.version.filter(|v| *v != existing.version) {
+        return Ok(Plan::Rejected(format!(
+            "user {} is at version {}, not {}",
+            id, existing.version, version
+        )));
+    }
+
+    let user = existing.replaced(input, now);
+    if user.name == existing.name && user.email == existing.email {
+        return Ok(Plan::Unchanged(id));
+    }
+    Ok(Plan::Write(Change::Update {
+        id,
+        expected: existing.version,
+        user,
+    }))
+}
@@ -0,0 +1,95 @@
+//! The audit log: every create, update and delete, in the order the store
+//! made them.
+//!
+//! `GET /user/{id}/history` shows one user's changes and `GET /changes`
+//! pages through everyone's. Each change has a sequence number, and the
+//! feed's cursor is simply the last number a client has seen, so polling
+//! with `since` picks up where the previous page stopped.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::list;
+use crate::user::{UserData, UserId};
+
+pub const CHANGES_PATH: &str = "/changes";
+pub const HISTORY_SUFFIX: &str = "/history";
+
+pub const DEFAULT_LIMIT: usize = 100;
+pub const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// One change to one user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ChangeRecord {
+    /// Where the change falls among all changes, counting from 1.
+    #[schemars(range(min = 1))]
+    pub seq: u64,
+    pub id: UserId,
+    pub kind: ChangeKind,
+    pub at: DateTime<Utc>,
+    /// The `sub` of the token that made the change, or `anonymous`.
+    pub actor: String,
+    /// The user before the change; absent for creates.
+    pub before: Option<UserData>,
+    /// The user after the change; absent for deletes.
+    pub after: Option<UserData>,
+}
+
+/// A page of the feed.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ChangeFeed {
+    pub changes: Vec<ChangeRecord>,
+    /// The `since` to ask for next. Equal to the one asked for if there was
+    /// nothing new.
+    pub next: u64,
+}
+
+impl ChangeFeed {
+    pub fn new(changes: Vec<ChangeRecord>, since: u64) -> ChangeFeed {
+        let next = changes.last().map_or(since, |change| change.seq);
+        ChangeFeed { changes, next }
+    }
+}
+
+/// `since` and `limit` from a `GET /changes` query string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeedQuery {
+    pub since: u64,
+    pub limit: usize,
+}
+
+impl FeedQuery {
+    pub fn parse(query: Option<&str>) -> Result<FeedQuery, String> {
+        let mut feed = FeedQuery {
+            since: 0,
+            limit: DEFAULT_LIMIT,
+        };
+        for (name, value) in list::parse_query(query.unwrap_or(""))? {
+            match name.as_str() {
+                "since" => {
+                    feed.since = value
+                        .parse()
+                        .map_err(|_| format!("`since` must be a change number, got `{}`", value))?;
+                }
+                "limit" => {
+                    feed.limit = value
+                        .parse()
+                        .ok()
+                        .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                        .ok_or_else(|| format!("`limit` must be a number from 1 to {}", MAX_LIMIT))?;
+                }
+                _ => return Err(format!("unknown query parameter `{}`", name)),
+            }
+        }
+        Ok(feed)
+    }
+}
@@ -0,0 +1,23 @@
+//! Entity tags and the `If-Match` / `If-None-Match` headers that carry them
+//! (RFC 9110, section 13).
+
+/// The strong entity tag of a user at `version`.
+pub fn etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Whether an `If-Match` header value admits `etag`. `If-Match` uses strong
+/// comparison, so weak tags never match.
+pub fn if_match(header: &str, etag: &str) -> bool {
+    tags(header).any(|tag| tag == "*" || tag == etag)
+}
+
+/// Whether an `If-None-Match` header value lists `etag`, so the client
+/// already has it. `If-None-Match` uses weak comparison.
+pub fn if_none_match(header: &str, etag: &str) -> bool {
+    tags(header).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn tags(header: &str) -> impl Iterator<Item = &str> {
+    header.split(',').map(str::trim).filter(|tag| !tag.is_empty())
+}
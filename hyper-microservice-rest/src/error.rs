@@ -0,0 +1,192 @@
+//! Failed requests, reported as RFC 7807 `application/problem+json`.
+
+use http::header::{ALLOW, CONTENT_TYPE, RETRY_AFTER, WWW_AUTHENTICATE};
+use http::{Method, Response, StatusCode};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fmt;
+
+use crate::auth::AuthError;
+use crate::store::StoreError;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request carried no valid bearer token. Says why.
+    Unauthorized(String),
+    /// The token is valid but does not allow the request. Says why.
+    Forbidden(String),
+    /// Nothing lives at the requested path.
+    NotFound(String),
+    /// Something lived at the requested path but was deleted.
+    Gone(String),
+    /// The path exists but does not take this method; carries what it does take.
+    MethodNotAllowed(Vec<Method>),
+    /// The request body was not acceptable. Says why.
+    InvalidBody(String),
+    /// The query string was not acceptable. Says why.
+    InvalidQuery(String),
+    UnsupportedMediaType(String),
+    /// None of the types the client accepts can be produced; carries the ones that can.
+    NotAcceptable(&'static [&'static str]),
+    /// A well-formed body that cannot be applied, or that would leave the
+    /// user invalid. Says why.
+    Unprocessable(String),
+    /// The request conflicts with the state of what it targets. Says why.
+    Conflict(String),
+    /// An `If-Match` precondition did not hold. Says why.
+    PreconditionFailed(String),
+    /// The request body is larger than the limit it carries, in bytes.
+    PayloadTooLarge(usize),
+    /// The API key has no quota left. Says which, and how many seconds
+    /// until it has.
+    TooManyRequests { detail: String, retry_after: u64 },
+    /// Something broke on our side. The message is logged, never sent.
+    Internal(String),
+}
+
+/// The body of an error response.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    pub detail: String,
+    pub instance: String,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Gone(_) => StatusCode::GONE,
+            ApiError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::InvalidBody(_) | ApiError::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The problem type URI and its title. Types are relative to the service.
+    fn kind(&self) -> (&'static str, &'static str) {
+        match self {
+            ApiError::Unauthorized(_) => ("/problems/unauthorized", "Authentication required"),
+            ApiError::Forbidden(_) => ("/problems/forbidden", "Forbidden"),
+            ApiError::NotFound(_) => ("/problems/not-found", "Resource not found"),
+            ApiError::Gone(_) => ("/problems/gone", "Resource deleted"),
+            ApiError::MethodNotAllowed(_) => ("/problems/method-not-allowed", "Method not allowed"),
+            ApiError::InvalidBody(_) => ("/problems/invalid-body", "Invalid request body"),
+            ApiError::InvalidQuery(_) => ("/problems/invalid-query", "Invalid query string"),
+            ApiError::UnsupportedMediaType(_) => {
+                ("/problems/unsupported-media-type", "Unsupported media type")
+            }
+            ApiError::Unprocessable(_) => ("/problems/unprocessable", "Unprocessable content"),
+            ApiError::Conflict(_) => ("/problems/conflict", "Conflict"),
+            ApiError::PreconditionFailed(_) => ("/problems/precondition-failed", "Precondition failed"),
+            ApiError::NotAcceptable(_) => ("/problems/not-acceptable", "Not acceptable"),
+            ApiError::PayloadTooLarge(_) => ("/problems/payload-too-large", "Payload too large"),
+            ApiError::TooManyRequests { .. } => ("/problems/too-many-requests", "Too many requests"),
+            ApiError::Internal(_) => ("/problems/internal", "Internal server error"),
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            ApiError::Unauthorized(detail)
+            | ApiError::Forbidden(detail)
+            | ApiError::NotFound(detail)
+            | ApiError::Gone(detail)
+            | ApiError::InvalidBody(detail)
+            | ApiError::InvalidQuery(detail)
+            | ApiError::UnsupportedMediaType(detail)
+            | ApiError::Unprocessable(detail)
+            | ApiError::Conflict(detail)
+            | ApiError::PreconditionFailed(detail)
+            | ApiError::TooManyRequests { detail, .. } => detail.clone(),
+            ApiError::MethodNotAllowed(allow) => {
+                format!("allowed methods are {}", allow_header(allow))
+            }
+            ApiError::NotAcceptable(offered) => {
+                format!("available types are {}", offered.join(", "))
+            }
+            ApiError::PayloadTooLarge(limit) => {
+                format!("request bodies are limited to {} bytes", limit)
+            }
+            ApiError::Internal(_) => "the request could not be completed".to_string(),
+        }
+    }
+
+    /// `instance` is the path of the request that failed.
+    pub fn to_problem(&self, instance: &str) -> Problem {
+        let (kind, title) = self.kind();
+        Problem {
+            kind,
+            title,
+            status: self.status().as_u16(),
+            detail: self.detail(),
+            instance: instance.to_string(),
+        }
+    }
+
+    pub fn into_response(self, instance: &str) -> Response<String> {
+        if let ApiError::Internal(message) = &self {
+            eprintln!("{}: {}", instance, message);
+        }
+
+        let body = serde_json::to_string(&self.to_problem(instance)).expect("problems always serialize");
+        let mut builder = Response::builder()
+            .status(self.status())
+            .header(CONTENT_TYPE, PROBLEM_JSON);
+        match &self {
+            ApiError::MethodNotAllowed(allow) => builder = builder.header(ALLOW, allow_header(allow)),
+            ApiError::Unauthorized(_) => builder = builder.header(WWW_AUTHENTICATE, "Bearer"),
+            ApiError::TooManyRequests { retry_after, .. } => builder = builder.header(RETRY_AFTER, *retry_after),
+            _ => {}
+        }
+        builder.body(body).unwrap()
+    }
+}
+
+fn allow_header(methods: &[Method]) -> String {
+    methods
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::Internal(message) => write!(f, "{}: {}", self.status(), message),
+            _ => write!(f, "{}: {}", self.status(), self.detail()),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<AuthError> for ApiError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::Unauthenticated(detail) => ApiError::Unauthorized(detail),
+            AuthError::Forbidden(detail) => ApiError::Forbidden(detail),
+        }
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
@@ -0,0 +1,472 @@
+//! API keys, each with a quota of requests per minute and per day.
+//!
+//! Clients send their key in the [`KEY_HEADER`] header. Quotas count
+//! requests in fixed UTC windows: the minute and the day a request falls
+//! in. A request is only counted if both windows have room for it, so
+//! refused requests do not use up quota.
+//!
+//! Keys are stored as SHA-256 hashes; the key itself is shown once, when it
+//! is issued. Like users, keys and their usage live in memory or, with the
+//! file backend, in a log next to the user log that is compacted on open.
+
+use chrono::{DateTime, TimeZone, Utc};
+use http::header::{HeaderMap, HeaderName};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::store::{self, StoreError};
+
+pub const KEYS_PATH: &str = "/keys";
+pub const KEY_HEADER: &str = "x-api-key";
+
+pub const MAX_NAME_LEN: usize = 100;
+
+/// Every key starts with this, so they are easy to spot in configs and logs.
+const KEY_PREFIX: &str = "uk_";
+const KEY_BYTES: usize = 24;
+/// How much of a key is kept in the clear, to tell keys apart.
+const SHOWN_LEN: usize = KEY_PREFIX.len() + 8;
+
+const MINUTE: i64 = 60;
+const DAY: i64 = 86_400;
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+const RATELIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
+
+pub type KeyId = u64;
+
+/// How many requests a key may make.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Limits {
+    pub per_minute: u64,
+    pub per_day: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            per_minute: 60,
+            per_day: 10_000,
+        }
+    }
+}
+
+impl Limits {
+    /// Reads `API_KEY_PER_MINUTE` and `API_KEY_PER_DAY`, the limits of keys
+    /// issued without their own. Either falls back to [`Limits::default`].
+    pub fn from_env() -> Result<Limits, String> {
+        let defaults = Limits::default();
+        let read = |name: &str, default: u64| match env::var(name) {
+            Err(_) => Ok(default),
+            Ok(value) => match value.parse() {
+                Ok(limit) if limit > 0 => Ok(limit),
+                _ => Err(format!("{} must be a positive integer, got {:?}", name, value)),
+            },
+        };
+        Ok(Limits {
+            per_minute: read("API_KEY_PER_MINUTE", defaults.per_minute)?,
+            per_day: read("API_KEY_PER_DAY", defaults.per_day)?,
+        })
+    }
+}
+
+/// The body of `POST /keys`.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct KeyInput {
+    /// Who or what the key is for.
+    #[schemars(length(min = 1, max = MAX_NAME_LEN))]
+    pub name: String,
+    /// The server's default if left out.
+    #[schemars(range(min = 1))]
+    pub per_minute: Option<u64>,
+    /// The server's default if left out.
+    #[schemars(range(min = 1))]
+    pub per_day: Option<u64>,
+}
+
+impl KeyInput {
+    pub fn validate(&self) -> Result<(), String> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            return Err(format!("the name must be 1 to {} characters", MAX_NAME_LEN));
+        }
+        if self.per_minute == Some(0) || self.per_day == Some(0) {
+            return Err("limits must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    fn limits(&self, defaults: Limits) -> Limits {
+        Limits {
+            per_minute: self.per_minute.unwrap_or(defaults.per_minute),
+            per_day: self.per_day.unwrap_or(defaults.per_day),
+        }
+    }
+}
+
+/// An API key, as shown to clients: everything but the key itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ApiKey {
+    pub id: KeyId,
+    pub name: String,
+    /// The start of the key.
+    pub prefix: String,
+    #[serde(flatten)]
+    pub limits: Limits,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A key just issued, with the key itself.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct IssuedKey {
+    #[serde(flatten)]
+    pub info: ApiKey,
+    /// The value for the `X-API-Key` header. Never shown again.
+    pub key: String,
+}
+
+/// One quota window of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct WindowUsage {
+    pub limit: u64,
+    pub used: u64,
+    pub remaining: u64,
+    pub resets_at: DateTime<Utc>,
+}
+
+/// How much of its quotas a key has used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct KeyUsage {
+    pub key: KeyId,
+    pub minute: WindowUsage,
+    pub day: WindowUsage,
+}
+
+impl KeyUsage {
+    /// The window nearest its limit. Ties go to the day, which never resets
+    /// sooner: with both used up, waiting for the minute would not help.
+    fn binding(&self) -> &WindowUsage {
+        if self.day.remaining <= self.minute.remaining {
+            &self.day
+        } else {
+            &self.minute
+        }
+    }
+
+    /// Whole seconds from `now` until the binding window resets.
+    pub fn reset_in(&self, now: DateTime<Utc>) -> u64 {
+        let millis = (self.binding().resets_at - now).num_milliseconds().max(0) as u64;
+        millis.div_ceil(1000)
+    }
+
+    /// Adds the `RateLimit-*` headers.
+    pub fn write_headers(&self, now: DateTime<Utc>, headers: &mut HeaderMap) {
+        let window = self.binding();
+        let policy = format!("{};w={}, {};w={}", self.minute.limit, MINUTE, self.day.limit, DAY);
+        let mut set = |name: HeaderName, value: String| {
+            headers.insert(name, value.parse().expect("numbers are valid header values"));
+        };
+        set(RATELIMIT_LIMIT, window.limit.to_string());
+        set(RATELIMIT_REMAINING, window.remaining.to_string());
+        set(RATELIMIT_RESET, self.reset_in(now).to_string());
+        set(RATELIMIT_POLICY, policy);
+    }
+}
+
+/// What [`ApiKeys::check`] made of a request's key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Check {
+    /// No key was sent.
+    Missing,
+    /// No key like it was issued, or it was revoked.
+    Unknown,
+    /// Counted; this is the usage with the request in it.
+    Allowed(KeyUsage),
+    /// Over quota, so not counted.
+    Limited(KeyUsage),
+}
+
+/// Requests counted in one window, which started at a Unix time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Window {
+    start: i64,
+    count: u64,
+}
+
+impl Window {
+    /// The count at `now`, in a window `len` seconds long.
+    fn used(&self, now: i64, len: i64) -> u64 {
+        if self.start == window_start(now, len) {
+            self.count
+        } else {
+            0
+        }
+    }
+
+    fn record(&mut self, at: i64, len: i64) {
+        let start = window_start(at, len);
+        if self.start != start {
+            *self = Window { start, count: 0 };
+        }
+        self.count += 1;
+    }
+}
+
+fn window_start(at: i64, len: i64) -> i64 {
+    at - at.rem_euclid(len)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Usage {
+    minute: Window,
+    day: Window,
+}
+
+/// A key as stored.
+struct Record {
+    key: ApiKey,
+    hash: String,
+    usage: Usage,
+}
+
+impl Record {
+    fn usage(&self, now: i64) -> KeyUsage {
+        let window = |window: &Window, len: i64, limit: u64| {
+            let used = window.used(now, len);
+            WindowUsage {
+                limit,
+                used,
+                remaining: limit.saturating_sub(used),
+                resets_at: Utc.timestamp_opt(window_start(now, len) + len, 0).unwrap(),
+            }
+        };
+        KeyUsage {
+            key: self.key.id,
+            minute: window(&self.usage.minute, MINUTE, self.key.limits.per_minute),
+            day: window(&self.usage.day, DAY, self.key.limits.per_day),
+        }
+    }
+}
+
+/// One line of the key log.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Entry {
+    Issue { key: ApiKey, hash: String },
+    /// Also retires the id, even one that is not live, so it is never
+    /// issued again.
+    Revoke { id: KeyId },
+    /// A request counted against the key, at a Unix time.
+    Hit { id: KeyId, at: i64 },
+    /// Usage set outright, by a reset or when the log is compacted.
+    Usage { id: KeyId, usage: Usage },
+}
+
+#[derive(Default)]
+struct Keys {
+    live: BTreeMap<KeyId, Record>,
+    by_hash: HashMap<String, KeyId>,
+    next_id: KeyId,
+}
+
+impl Keys {
+    fn apply(&mut self, entry: Entry) -> Result<(), String> {
+        match entry {
+            Entry::Issue { key, hash } => {
+                if key.id < self.next_id {
+                    return Err(format!("key {} was issued already", key.id));
+                }
+                self.next_id = key.id + 1;
+                self.by_hash.insert(hash.clone(), key.id);
+                let usage = Usage::default();
+                self.live.insert(key.id, Record { key, hash, usage });
+            }
+            Entry::Revoke { id } => {
+                if let Some(record) = self.live.remove(&id) {
+                    self.by_hash.remove(&record.hash);
+                }
+                self.next_id = self.next_id.max(id + 1);
+            }
+            Entry::Hit { id, at } => {
+                let usage = &mut self.record(id)?.usage;
+                usage.minute.record(at, MINUTE);
+                usage.day.record(at, DAY);
+            }
+            Entry::Usage { id, usage } => self.record(id)?.usage = usage,
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, id: KeyId) -> Result<&mut Record, String> {
+        self.live.get_mut(&id).ok_or_else(|| format!("there is no key {}", id))
+    }
+
+    /// The entries that rebuild these keys, one per key and one per key
+    /// with usage, plus one to keep the next id.
+    fn compacted(&self) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        for record in self.live.values() {
+            entries.push(Entry::Issue {
+                key: record.key.clone(),
+                hash: record.hash.clone(),
+            });
+            if record.usage != Usage::default() {
+                entries.push(Entry::Usage {
+                    id: record.key.id,
+                    usage: record.usage,
+                });
+            }
+        }
+        if self.next_id > 0 && !self.live.contains_key(&(self.next_id - 1)) {
+            entries.push(Entry::Revoke { id: self.next_id - 1 });
+        }
+        entries
+    }
+}
+
+/// The issued keys, with their usage, and the limits new keys get.
+pub struct ApiKeys {
+    defaults: Limits,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    keys: Keys,
+    /// Where entries are appended, for the file backend.
+    log: Option<File>,
+}
+
+impl Inner {
+    fn commit(&mut self, entry: Entry) -> Result<(), StoreError> {
+        if let Some(log) = &mut self.log {
+            // Losing the last few hits in a crash only hands out a little
+            // extra quota, which is not worth a sync on every request.
+            let sync = !matches!(entry, Entry::Hit { .. });
+            store::append_entries(log, std::slice::from_ref(&entry), sync)?;
+        }
+        self.keys.apply(entry).expect("entry was checked against the keys");
+        Ok(())
+    }
+}
+
+impl ApiKeys {
+    /// Keys kept for as long as the process runs.
+    pub fn in_memory(defaults: Limits) -> ApiKeys {
+        ApiKeys {
+            defaults,
+            inner: Mutex::new(Inner {
+                keys: Keys::default(),
+                log: None,
+            }),
+        }
+    }
+
+    /// Keys kept in the log at `path`, which is created if needed, replayed,
+    /// and rewritten with only what is still needed.
+    pub fn open(path: impl AsRef<Path>, defaults: Limits) -> Result<ApiKeys, StoreError> {
+        let mut keys = Keys::default();
+        let log = store::open_compacting(path.as_ref(), &mut keys, Keys::apply, Keys::compacted)?;
+        Ok(ApiKeys {
+            defaults,
+            inner: Mutex::new(Inner { keys, log: Some(log) }),
+        })
+    }
+
+    pub fn defaults(&self) -> Limits {
+        self.defaults
+    }
+
+    /// Issues a key. The returned value is the only place the key appears.
+    pub fn issue(&self, input: KeyInput, now: DateTime<Utc>) -> Result<IssuedKey, StoreError> {
+        let mut bytes = [0; KEY_BYTES];
+        getrandom::getrandom(&mut bytes).map_err(|e| io::Error::other(e.to_string()))?;
+        let key = format!("{}{}", KEY_PREFIX, hex(&bytes));
+
+        let mut inner = self.inner.lock().unwrap();
+        let info = ApiKey {
+            id: inner.keys.next_id,
+            name: input.name.trim().to_string(),
+            prefix: key[..SHOWN_LEN].to_string(),
+            limits: input.limits(self.defaults),
+            created_at: now,
+        };
+        inner.commit(Entry::Issue {
+            key: info.clone(),
+            hash: hash(&key),
+        })?;
+        Ok(IssuedKey { info, key })
+    }
+
+    /// Every live key, in id order.
+    pub fn list(&self) -> Vec<ApiKey> {
+        let inner = self.inner.lock().unwrap();
+        inner.keys.live.values().map(|record| record.key.clone()).collect()
+    }
+
+    /// Revokes a key. `false` if there was no such key.
+    pub fn revoke(&self, id: KeyId) -> Result<bool, StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.keys.live.contains_key(&id) {
+            return Ok(false);
+        }
+        inner.commit(Entry::Revoke { id })?;
+        Ok(true)
+    }
+
+    /// Counts a request made at `now` with `key`, if its quotas allow it.
+    pub fn check(&self, key: Option<&str>, now: DateTime<Utc>) -> Result<Check, StoreError> {
+        let key = match key {
+            Some(key) => key,
+            None => return Ok(Check::Missing),
+        };
+        let mut inner = self.inner.lock().unwrap();
+        let id = match inner.keys.by_hash.get(&hash(key)) {
+            Some(id) => *id,
+            None => return Ok(Check::Unknown),
+        };
+        let at = now.timestamp();
+        let usage = inner.keys.live[&id].usage(at);
+        if usage.minute.remaining == 0 || usage.day.remaining == 0 {
+            return Ok(Check::Limited(usage));
+        }
+        inner.commit(Entry::Hit { id, at })?;
+        Ok(Check::Allowed(inner.keys.live[&id].usage(at)))
+    }
+
+    /// A key's usage at `now`, or `None` if there is no such key.
+    pub fn usage(&self, id: KeyId, now: DateTime<Utc>) -> Option<KeyUsage> {
+        let inner = self.inner.lock().unwrap();
+        inner.keys.live.get(&id).map(|record| record.usage(now.timestamp()))
+    }
+
+    /// Clears a key's usage. `false` if there was no such key.
+    pub fn reset(&self, id: KeyId) -> Result<bool, StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.keys.live.contains_key(&id) {
+            return Ok(false);
+        }
+        inner.commit(Entry::Usage {
+            id,
+            usage: Usage::default(),
+        })?;
+        Ok(true)
+    }
+}
+
+fn hash(key: &str) -> String {
+    hex(&Sha256::digest(key.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,19 @@
+//! The user service behind `api/index.rs`.
+
+pub mod accept;
+pub mod auth;
+pub mod bulk;
+pub mod changes;
+pub mod conditional;
+pub mod error;
+pub mod keys;
+pub mod list;
+pub mod openapi;
+pub mod patch;
+pub mod router;
+pub mod server;
+pub mod store;
+pub mod user;
+pub mod vercel;
+pub mod version;
+pub mod webhook;
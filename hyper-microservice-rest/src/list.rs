@@ -0,0 +1,359 @@
+//! `GET /user/`: filtering, sorting and cursor pagination.
+//!
+//! Pages are cut by keyset: a cursor remembers the sort key and id of the
+//! user at the edge of the page it came from, so inserts and deletes
+//! elsewhere in the list do not shift what the next page shows.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+use crate::user::{User, UserData, UserId};
+
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Id,
+    Name,
+    Email,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl SortField {
+    pub const ALL: [SortField; 5] = [
+        SortField::Id,
+        SortField::Name,
+        SortField::Email,
+        SortField::CreatedAt,
+        SortField::UpdatedAt,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortField::Id => "id",
+            SortField::Name => "name",
+            SortField::Email => "email",
+            SortField::CreatedAt => "created_at",
+            SortField::UpdatedAt => "updated_at",
+        }
+    }
+
+    /// A string that orders users the way this field does.
+    fn key(self, id: UserId, user: &UserData) -> String {
+        let time = |t: &DateTime<Utc>| t.to_rfc3339_opts(SecondsFormat::Nanos, true);
+        match self {
+            SortField::Id => format!("{:020}", id),
+            SortField::Name => user.name.clone(),
+            SortField::Email => user.email.clone(),
+            SortField::CreatedAt => time(&user.created_at),
+            SortField::UpdatedAt => time(&user.updated_at),
+        }
+    }
+}
+
+/// A field, optionally descending. Written `name` or `-name`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sort {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+impl Sort {
+    fn parse(s: &str) -> Result<Sort, String> {
+        let (descending, name) = match s.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, s),
+        };
+        let field = SortField::ALL
+            .iter()
+            .copied()
+            .find(|f| f.as_str() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = SortField::ALL.iter().map(|f| f.as_str()).collect();
+                format!("cannot sort by `{}`, expected one of {}", name, names.join(", "))
+            })?;
+        Ok(Sort { field, descending })
+    }
+
+    fn cmp(&self, a: &(String, UserId), b: &(String, UserId)) -> Ordering {
+        let ordering = a.cmp(b);
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+impl Default for Sort {
+    fn default() -> Sort {
+        Sort {
+            field: SortField::Id,
+            descending: false,
+        }
+    }
+}
+
+impl std::fmt::Display for Sort {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.descending {
+            f.write_str("-")?;
+        }
+        f.write_str(self.field.as_str())
+    }
+}
+
+/// Conditions a user has to meet to be listed. All of them apply.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    /// Case-insensitive substring of the name.
+    pub name: Option<String>,
+    /// Case-insensitive substring of the email.
+    pub email: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl Filter {
+    pub fn matches(&self, user: &UserData) -> bool {
+        let contains = |haystack: &str, needle: &Option<String>| {
+            needle
+                .as_ref()
+                .is_none_or(|n| haystack.to_lowercase().contains(&n.to_lowercase()))
+        };
+        contains(&user.name, &self.name)
+            && contains(&user.email, &self.email)
+            && self.created_after.is_none_or(|t| user.created_at > t)
+            && self.created_before.is_none_or(|t| user.created_at < t)
+    }
+}
+
+/// Where a page starts, relative to the user at the edge of another page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    key: String,
+    id: UserId,
+    /// `true` for the page before the edge, `false` for the one after it.
+    before: bool,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursors always serialize"))
+    }
+
+    fn decode(s: &str) -> Option<Cursor> {
+        let bytes = URL_SAFE_NO_PAD.decode(s).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+/// The query string of a list request.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListQuery {
+    pub filter: Filter,
+    pub sort: Sort,
+    pub limit: Option<usize>,
+    cursor: Option<Cursor>,
+}
+
+impl ListQuery {
+    /// Parses `query`. Unknown parameters are an error, so typos do not
+    /// silently return everything.
+    pub fn parse(query: Option<&str>) -> Result<ListQuery, String> {
+        let mut list = ListQuery::default();
+        let mut cursor = None;
+        for (name, value) in parse_query(query.unwrap_or(""))? {
+            let time = |value: &str| {
+                DateTime::parse_from_rfc3339(value)
+                    .map(|t| t.with_timezone(&Utc))
+                    .map_err(|e| format!("`{}` is not an RFC 3339 time: {}", name, e))
+            };
+            match name.as_str() {
+                "name" => list.filter.name = Some(value),
+                "email" => list.filter.email = Some(value),
+                "created_after" => list.filter.created_after = Some(time(&value)?),
+                "created_before" => list.filter.created_before = Some(time(&value)?),
+                "sort" => list.sort = Sort::parse(&value)?,
+                "limit" => {
+                    let limit = value
+                        .parse()
+                        .ok()
+                        .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                        .ok_or_else(|| format!("`limit` must be a number from 1 to {}", MAX_LIMIT))?;
+                    list.limit = Some(limit);
+                }
+                "cursor" => cursor = Some(value),
+                _ => return Err(format!("unknown query parameter `{}`", name)),
+            }
+        }
+
+        if let Some(cursor) = cursor {
+            let cursor = Cursor::decode(&cursor).ok_or("`cursor` is not a cursor from this service")?;
+            if cursor.sort != list.sort.to_string() {
+                return Err(format!(
+                    "`cursor` belongs to a list sorted by `{}`, not `{}`",
+                    cursor.sort, list.sort
+                ));
+            }
+            list.cursor = Some(cursor);
+        }
+        Ok(list)
+    }
+
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT)
+    }
+
+    /// The query string for the same list at another cursor.
+    fn with_cursor(&self, cursor: &str) -> String {
+        let mut params = Vec::new();
+        let mut push = |name: &str, value: &str| params.push(format!("{}={}", name, encode(value)));
+        let time = |t: &DateTime<Utc>| t.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        if let Some(name) = &self.filter.name {
+            push("name", name);
+        }
+        if let Some(email) = &self.filter.email {
+            push("email", email);
+        }
+        if let Some(t) = &self.filter.created_after {
+            push("created_after", &time(t));
+        }
+        if let Some(t) = &self.filter.created_before {
+            push("created_before", &time(t));
+        }
+        if self.sort != Sort::default() {
+            push("sort", &self.sort.to_string());
+        }
+        if let Some(limit) = self.limit {
+            push("limit", &limit.to_string());
+        }
+        push("cursor", cursor);
+        params.join("&")
+    }
+}
+
+/// One page of a list.
+#[derive(Debug)]
+pub struct Page {
+    pub users: Vec<(UserId, UserData)>,
+    /// Query string of the following page, if there is one.
+    pub next: Option<String>,
+    /// Query string of the preceding page, if there is one.
+    pub prev: Option<String>,
+}
+
+/// The body of a list response.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct UserList<'a> {
+    pub users: Vec<User<'a>>,
+    /// URL of the following page, if there is one.
+    pub next: Option<String>,
+    /// URL of the preceding page, if there is one.
+    pub prev: Option<String>,
+}
+
+/// Picks the page `query` asks for out of `users`.
+pub fn paginate(users: Vec<(UserId, UserData)>, query: &ListQuery) -> Page {
+    let sort = query.sort;
+    let mut rows: Vec<_> = users
+        .into_iter()
+        .filter(|(_, user)| query.filter.matches(user))
+        .map(|(id, user)| ((sort.field.key(id, &user), id), user))
+        .collect();
+    rows.sort_by(|a, b| sort.cmp(&a.0, &b.0));
+
+    let limit = query.limit();
+    let (start, end) = match &query.cursor {
+        None => (0, limit.min(rows.len())),
+        Some(cursor) => {
+            let edge = (cursor.key.clone(), cursor.id);
+            if cursor.before {
+                let end = rows.partition_point(|row| sort.cmp(&row.0, &edge) == Ordering::Less);
+                (end.saturating_sub(limit), end)
+            } else {
+                let start = rows.partition_point(|row| sort.cmp(&row.0, &edge) != Ordering::Greater);
+                (start, (start + limit).min(rows.len()))
+            }
+        }
+    };
+
+    let link = |row: &((String, UserId), UserData), before: bool| {
+        let cursor = Cursor {
+            sort: sort.to_string(),
+            key: (row.0).0.clone(),
+            id: (row.0).1,
+            before,
+        };
+        query.with_cursor(&cursor.encode())
+    };
+    let next = if end < rows.len() && end > start {
+        Some(link(&rows[end - 1], false))
+    } else {
+        None
+    };
+    let prev = if start > 0 && start < rows.len() {
+        Some(link(&rows[start], true))
+    } else {
+        None
+    };
+
+    let users = rows
+        .drain(start..end)
+        .map(|((_, id), user)| (id, user))
+        .collect();
+    Page { users, next, prev }
+}
+
+pub(crate) fn parse_query(query: &str) -> Result<Vec<(String, String)>, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((decode(name)?, decode(value)?))
+        })
+        .collect()
+}
+
+/// Undoes `application/x-www-form-urlencoded` escaping.
+fn decode(s: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest
+                    .get(..2)
+                    .and_then(|h| std::str::from_utf8(h).ok())
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| format!("bad percent-escape in query string near {:?}", s))?;
+                bytes.push(hex);
+                rest = &rest[2..];
+            }
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| "query string is not UTF-8".to_string())
+}
+
+fn encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for &b in s.as_bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
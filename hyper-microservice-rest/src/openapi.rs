@@ -0,0 +1,662 @@
+//! The OpenAPI 3 description of the service, served at `/openapi.json`.
+//!
+//! [`ENDPOINTS`] is the list of operations the router serves. The router
+//! takes allowed methods and required roles from it, the document is built
+//! from it with schemas derived from the request and response types, and
+//! the index page is rendered from the document.
+
+use http::Method;
+use schemars::generate::{SchemaGenerator, SchemaSettings};
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+
+use crate::auth::Role;
+use crate::bulk::{self, Report, EXPORT_PATH, IMPORT_PATH};
+use crate::changes::{self, ChangeFeed, ChangeRecord, CHANGES_PATH};
+use crate::error::{Problem, PROBLEM_JSON};
+use crate::keys::{ApiKey, IssuedKey, KeyInput, KeyUsage, KEYS_PATH};
+use crate::list::{SortField, UserList, DEFAULT_LIMIT, MAX_LIMIT};
+use crate::patch::{self, Operation};
+use crate::user::{User, UserInput};
+use crate::webhook::{Delivery, Run, Webhook, WebhookInput, CRON_PATH, DELIVERIES_PATH, RUN_PATH, WEBHOOKS_PATH};
+
+pub const OPENAPI_PATH: &str = "/openapi.json";
+
+/// One operation of the API.
+#[derive(Debug)]
+pub struct Endpoint {
+    pub method: Method,
+    /// The path as an OpenAPI template, like `/user/{id}`.
+    pub path: &'static str,
+    pub summary: &'static str,
+    /// The least role that may call it when authentication is on.
+    pub role: Option<Role>,
+}
+
+pub const ENDPOINTS: &[Endpoint] = &[
+    Endpoint {
+        method: Method::GET,
+        path: "/",
+        summary: "This page",
+        role: None,
+    },
+    Endpoint {
+        method: Method::GET,
+        path: OPENAPI_PATH,
+        summary: "This API as an OpenAPI 3 document",
+        role: None,
+    },
+    Endpoint {
+        method: Method::GET,
+        path: "/user/",
+        summary: "List users, filtered, sorted and a page at a time",
+        role: Some(Role::ReadOnly),
+    },
+    Endpoint {
+        method: Method::POST,
+        path: "/user/",
+        summary: "Create a user",
+        role: Some(Role::Editor),
+    },
+    Endpoint {
+        method: Method::GET,
+        path: EXPORT_PATH,
+        summary: "Export every user as NDJSON or CSV",
+        role: Some(Role::ReadOnly),
+    },
+    Endpoint {
+        method: Method::POST,
+        path: IMPORT_PATH,
+        summary: "Create and update users in bulk from NDJSON or CSV",
+        role: Some(Role::Editor),
+    },
+    Endpoint {
+        method: Method::GET,
+        path: CHANGES_PATH,
+        summary: "Every change to any user, oldest first",
+        role: Some(Role::ReadOnly),
+    },
+    Endpoint {
+        method: Method::GET,
+        path: "/user/{id}",
+        summary: "Get a user by id",
+        role: Some(Role::ReadOnly),
+    },
+    Endpoint {
+        method: Method::PUT,
+        path: "/user/{id}",
+        summary: "Replace a user's name and email",
+        role: Some(Role::Editor),
+    },
+    Endpoint {
+        method: Method::PATCH,
+        path: "/user/{id}",
+        summary: "Change some of a user's fields",
+        role: Some(Role::Editor),
+    },
+    Endpoint {
+        method: Method::DELETE,
+        path: "/user/{id}",
+        summary: "Delete a user",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::GET,
+        path: "/user/{id}/history",
+        summary: "Every change to a user, oldest first",
+        role: Some(Role::ReadOnly),
+    },
+    Endpoint {
+        method: Method::GET,
+        path: WEBHOOKS_PATH,
+        summary: "List webhooks",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::POST,
+        path: WEBHOOKS_PATH,
+        summary: "Register a webhook to be sent every change to a user",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::DELETE,
+        path: "/webhooks/{id}",
+        summary: "Remove a webhook and its deliveries",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::GET,
+        path: DELIVERIES_PATH,
+        summary: "List webhook deliveries; the dead ones are the dead-letter queue",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::GET,
+        path: "/webhooks/deliveries/{id}",
+        summary: "Get the status of a webhook delivery",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::POST,
+        path: "/webhooks/deliveries/{id}/retry",
+        summary: "Send a delivery that has not gone through again",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::POST,
+        path: RUN_PATH,
+        summary: "Make the webhook deliveries that are due",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::GET,
+        path: CRON_PATH,
+        summary: "Make the webhook deliveries that are due, for Vercel Cron",
+        role: None,
+    },
+    Endpoint {
+        method: Method::GET,
+        path: KEYS_PATH,
+        summary: "List API keys",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::POST,
+        path: KEYS_PATH,
+        summary: "Issue an API key with per-minute and per-day quotas",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::DELETE,
+        path: "/keys/{id}",
+        summary: "Revoke an API key",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::GET,
+        path: "/keys/{id}/usage",
+        summary: "Show how much of its quotas an API key has used",
+        role: Some(Role::Admin),
+    },
+    Endpoint {
+        method: Method::DELETE,
+        path: "/keys/{id}/usage",
+        summary: "Reset an API key's usage",
+        role: Some(Role::Admin),
+    },
+];
+
+/// The methods served at `path`, a template from [`ENDPOINTS`].
+pub fn methods(path: &str) -> Vec<Method> {
+    ENDPOINTS
+        .iter()
+        .filter(|endpoint| endpoint.path == path)
+        .map(|endpoint| endpoint.method.clone())
+        .collect()
+}
+
+/// The least role that may call `method` on `path`. For a method `path`
+/// does not serve, that is the least role of any method it does serve, so
+/// only callers who could use the path learn which methods it takes.
+pub fn role(method: &Method, path: &str) -> Option<Role> {
+    let endpoints = ENDPOINTS.iter().filter(|endpoint| endpoint.path == path);
+    match endpoints.clone().find(|endpoint| endpoint.method == *method) {
+        Some(endpoint) => endpoint.role,
+        None => {
+            let roles: Option<Vec<Role>> = endpoints.map(|endpoint| endpoint.role).collect();
+            roles?.into_iter().min()
+        }
+    }
+}
+
+/// The document, built on first use.
+pub fn document() -> &'static Value {
+    static DOCUMENT: OnceLock<Value> = OnceLock::new();
+    DOCUMENT.get_or_init(build)
+}
+
+fn build() -> Value {
+    let mut schemas = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+    for endpoint in ENDPOINTS {
+        let operation = operation(endpoint, &mut schemas);
+        let path = paths.entry(endpoint.path).or_insert_with(|| json!({}));
+        path[endpoint.method.as_str().to_ascii_lowercase()] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Users",
+            "description": "A REST service for user records. This document describes version 1 of the `/user` paths, which is deprecated. Version 2, asked for with a `/v2` path prefix or `Accept: application/vnd.users+json; version=2`, calls `name` `display_name`.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas.take_definitions(true),
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "bearerFormat": "JWT",
+                    "description": "HS256 or RS256 JWT with a `roles` claim. Only checked when the service is configured with a key.",
+                },
+                "apiKey": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": "X-API-Key",
+                    "description": "A key from `POST /keys`. Only checked when the service has API keys turned on.",
+                },
+            },
+        },
+    })
+}
+
+fn operation(endpoint: &Endpoint, schemas: &mut SchemaGenerator) -> Value {
+    let mut operation = json!({ "summary": endpoint.summary });
+    let mut responses = Map::new();
+    let mut parameters = Vec::new();
+    let problem = schemas.subschema_for::<Problem>();
+    let error = |description: &str| {
+        json!({
+            "description": description,
+            "content": { PROBLEM_JSON: { "schema": problem } },
+        })
+    };
+
+    if let Some(role) = endpoint.role {
+        operation["description"] = json!(format!("Needs the `{}` role.", role));
+        operation["security"] = json!([{ "bearer": [] }]);
+        responses.insert("401".into(), error("No valid bearer token"));
+        responses.insert("403".into(), error("The token's role does not allow this"));
+        if !endpoint.path.starts_with(KEYS_PATH) {
+            operation["security"] = json!([{ "bearer": [], "apiKey": [] }]);
+            responses.insert("401".into(), error("No valid bearer token or API key"));
+            responses.insert("429".into(), json!({
+                "description": "The API key has used up a quota",
+                "headers": rate_limit_headers(),
+                "content": { PROBLEM_JSON: { "schema": problem } },
+            }));
+        }
+    }
+    if endpoint.path.contains("{id}") {
+        parameters.push(json!({
+            "name": "id",
+            "in": "path",
+            "required": true,
+            "schema": { "type": "integer", "format": "int64", "minimum": 0 },
+        }));
+    }
+    if endpoint.path.starts_with("/user/{id}") {
+        responses.insert("404".into(), error("No user ever had this id"));
+    }
+    if endpoint.path == "/user/{id}" {
+        responses.insert("410".into(), error("The user was deleted"));
+    }
+    if endpoint.path == RUN_PATH {
+        operation["description"] = json!("Needs the `admin` role, or the service's `CRON_SECRET` as the bearer token.");
+    }
+    if endpoint.path == CRON_PATH {
+        operation["description"] = json!("Needs the service's `CRON_SECRET` as the bearer token; JWTs are not accepted.");
+        responses.insert("401".into(), error("The bearer token is not the cron secret"));
+        responses.insert("404".into(), error("The service has no cron secret"));
+    }
+    let if_match = json!({
+        "name": "If-Match",
+        "in": "header",
+        "description": "Only go ahead if the user is still at this entity tag",
+        "schema": { "type": "string" },
+    });
+
+    let user = schemas.subschema_for::<User>();
+    let etag = json!({ "ETag": { "description": "The user's version", "schema": { "type": "string" } } });
+    let user_response = |description: &str| {
+        json!({
+            "description": description,
+            "headers": etag,
+            "content": { "application/json": { "schema": user } },
+        })
+    };
+    let input = json!({
+        "required": true,
+        "content": { "application/json": { "schema": schemas.subschema_for::<UserInput>() } },
+    });
+
+    match (&endpoint.method, endpoint.path) {
+        (&Method::GET, "/") => {
+            responses.insert("200".into(), json!({
+                "description": "An HTML page listing the endpoints",
+                "content": { "text/html": { "schema": { "type": "string" } } },
+            }));
+        }
+        (&Method::GET, OPENAPI_PATH) => {
+            responses.insert("200".into(), json!({
+                "description": "This document",
+                "content": { "application/json": { "schema": { "type": "object" } } },
+            }));
+        }
+        (&Method::GET, "/user/") => {
+            parameters.extend(list_parameters());
+            responses.insert("200".into(), json!({
+                "description": "A page of users",
+                "headers": { "Link": { "description": "`next` and `prev` page URLs", "schema": { "type": "string" } } },
+                "content": { "application/json": { "schema": schemas.subschema_for::<UserList>() } },
+            }));
+            responses.insert("400".into(), error("The query string is invalid"));
+        }
+        (&Method::POST, "/user/") => {
+            operation["requestBody"] = input;
+            let mut created = user_response("The new user");
+            created["headers"]["Location"] = json!({ "description": "The new user's URL", "schema": { "type": "string" } });
+            responses.insert("201".into(), created);
+            responses.insert("400".into(), error("The body is not a valid user"));
+            responses.insert("415".into(), error("The body is not JSON"));
+        }
+        (&Method::GET, EXPORT_PATH) => {
+            let rows = bulk_content("One user per line or record, with the columns of `User`");
+            responses.insert("200".into(), json!({ "description": "Every user, in id order", "content": rows }));
+            responses.insert("406".into(), error("The `Accept` header allows neither format"));
+        }
+        (&Method::POST, IMPORT_PATH) => {
+            parameters.push(json!({
+                "name": "atomic",
+                "in": "query",
+                "description": "Whether one rejected row keeps every row from being applied",
+                "schema": { "type": "boolean", "default": true },
+            }));
+            let rows = bulk_content("Rows with `name`, `email` and optionally `id` and `version`. Rows with an `id` update that user, others create one.");
+            operation["requestBody"] = json!({ "required": true, "content": rows });
+            let report = json!({ "application/json": { "schema": schemas.subschema_for::<Report>() } });
+            responses.insert("200".into(), json!({ "description": "What became of each row", "content": report }));
+            responses.insert("400".into(), error("The query string or CSV header is invalid"));
+            responses.insert("415".into(), error("The body is neither NDJSON nor CSV"));
+            responses.insert("422".into(), json!({
+                "description": "An atomic import had rejected rows, so none were applied",
+                "content": report,
+            }));
+        }
+        (&Method::GET, CHANGES_PATH) => {
+            let query = |name: &str, description: &str, schema: Value| {
+                json!({ "name": name, "in": "query", "description": description, "schema": schema })
+            };
+            parameters.push(query(
+                "since",
+                "Only changes after this one; the `next` of the previous page",
+                json!({ "type": "integer", "format": "int64", "minimum": 0, "default": 0 }),
+            ));
+            parameters.push(query(
+                "limit",
+                "Changes per page",
+                json!({ "type": "integer", "minimum": 1, "maximum": changes::MAX_LIMIT, "default": changes::DEFAULT_LIMIT }),
+            ));
+            responses.insert("200".into(), json!({
+                "description": "A page of changes",
+                "content": { "application/json": { "schema": schemas.subschema_for::<ChangeFeed>() } },
+            }));
+            responses.insert("400".into(), error("The query string is invalid"));
+        }
+        (&Method::GET, "/user/{id}") => {
+            parameters.push(json!({
+                "name": "If-None-Match",
+                "in": "header",
+                "description": "Entity tags the client already has",
+                "schema": { "type": "string" },
+            }));
+            responses.insert("200".into(), user_response("The user"));
+            responses.insert("304".into(), json!({ "description": "The user still matches `If-None-Match`", "headers": etag }));
+        }
+        (&Method::PUT, "/user/{id}") => {
+            parameters.push(if_match);
+            operation["requestBody"] = input;
+            responses.insert("200".into(), user_response("The replaced user"));
+            responses.insert("400".into(), error("The body is not a valid user"));
+            responses.insert("412".into(), error("The user is not at the `If-Match` version"));
+            responses.insert("415".into(), error("The body is not JSON"));
+        }
+        (&Method::PATCH, "/user/{id}") => {
+            parameters.push(if_match);
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": {
+                    patch::MERGE_PATCH_JSON: { "schema": {
+                        "type": "object",
+                        "description": "RFC 7396 merge patch of `name` and `email`",
+                    } },
+                    patch::JSON_PATCH_JSON: { "schema": {
+                        "type": "array",
+                        "description": "RFC 6902 operations on `/name` and `/email`",
+                        "items": schemas.subschema_for::<Operation>(),
+                    } },
+                },
+            });
+            responses.insert("200".into(), user_response("The patched user"));
+            responses.insert("400".into(), error("The body is not a patch document"));
+            responses.insert("412".into(), error("The user is not at the `If-Match` version"));
+            responses.insert("415".into(), error("The body is neither patch format"));
+            responses.insert("422".into(), error("The patch does not apply or leaves the user invalid"));
+        }
+        (&Method::DELETE, "/user/{id}") => {
+            parameters.push(if_match);
+            responses.insert("204".into(), json!({ "description": "The user was deleted" }));
+            responses.insert("412".into(), error("The user is not at the `If-Match` version"));
+        }
+        (&Method::GET, "/user/{id}/history") => {
+            responses.insert("200".into(), json!({
+                "description": "The user's changes, including its deletion",
+                "content": { "application/json": { "schema": {
+                    "type": "array",
+                    "items": schemas.subschema_for::<ChangeRecord>(),
+                } } },
+            }));
+        }
+        (&Method::GET, WEBHOOKS_PATH) => {
+            responses.insert("200".into(), json!({
+                "description": "Every webhook",
+                "content": { "application/json": { "schema": {
+                    "type": "array",
+                    "items": schemas.subschema_for::<Webhook>(),
+                } } },
+            }));
+        }
+        (&Method::POST, WEBHOOKS_PATH) => {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": schemas.subschema_for::<WebhookInput>() } },
+            });
+            responses.insert("201".into(), json!({
+                "description": "The new webhook, without its secret",
+                "headers": { "Location": { "description": "The new webhook's URL", "schema": { "type": "string" } } },
+                "content": { "application/json": { "schema": schemas.subschema_for::<Webhook>() } },
+            }));
+            responses.insert("400".into(), error("The body is not a webhook"));
+            responses.insert("415".into(), error("The body is not JSON"));
+            responses.insert("422".into(), error("The URL is not http or https, or the secret is too short"));
+        }
+        (&Method::DELETE, "/webhooks/{id}") => {
+            responses.insert("204".into(), json!({ "description": "The webhook was removed" }));
+            responses.insert("404".into(), error("There is no such webhook"));
+        }
+        (&Method::GET, DELIVERIES_PATH) => {
+            parameters.push(json!({
+                "name": "webhook",
+                "in": "query",
+                "description": "Only this webhook's deliveries",
+                "schema": { "type": "integer", "format": "int64", "minimum": 0 },
+            }));
+            parameters.push(json!({
+                "name": "status",
+                "in": "query",
+                "description": "Only deliveries in this status",
+                "schema": { "type": "string", "enum": ["pending", "delivered", "dead"] },
+            }));
+            responses.insert("200".into(), json!({
+                "description": "Deliveries, oldest first",
+                "content": { "application/json": { "schema": {
+                    "type": "array",
+                    "items": schemas.subschema_for::<Delivery>(),
+                } } },
+            }));
+            responses.insert("400".into(), error("The query string is invalid"));
+        }
+        (&Method::GET, "/webhooks/deliveries/{id}") => {
+            responses.insert("200".into(), json!({
+                "description": "The delivery",
+                "content": { "application/json": { "schema": schemas.subschema_for::<Delivery>() } },
+            }));
+            responses.insert("404".into(), error("There is no such delivery"));
+        }
+        (&Method::POST, "/webhooks/deliveries/{id}/retry") => {
+            responses.insert("202".into(), json!({
+                "description": "The delivery is queued, with a fresh set of retries",
+                "content": { "application/json": { "schema": schemas.subschema_for::<Delivery>() } },
+            }));
+            responses.insert("404".into(), error("There is no such delivery"));
+            responses.insert("409".into(), error("The delivery went through already"));
+        }
+        (&Method::POST, RUN_PATH) | (&Method::GET, CRON_PATH) => {
+            responses.insert("200".into(), json!({
+                "description": "How many attempts were made",
+                "content": { "application/json": { "schema": schemas.subschema_for::<Run>() } },
+            }));
+        }
+        (&Method::GET, KEYS_PATH) => {
+            responses.insert("200".into(), json!({
+                "description": "Every live key, without the keys themselves",
+                "content": { "application/json": { "schema": {
+                    "type": "array",
+                    "items": schemas.subschema_for::<ApiKey>(),
+                } } },
+            }));
+        }
+        (&Method::POST, KEYS_PATH) => {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": schemas.subschema_for::<KeyInput>() } },
+            });
+            responses.insert("201".into(), json!({
+                "description": "The new key, the only time it is shown",
+                "headers": { "Location": { "description": "The new key's URL", "schema": { "type": "string" } } },
+                "content": { "application/json": { "schema": schemas.subschema_for::<IssuedKey>() } },
+            }));
+            responses.insert("400".into(), error("The body is not a key"));
+            responses.insert("415".into(), error("The body is not JSON"));
+            responses.insert("422".into(), error("The name is empty or too long, or a limit is 0"));
+        }
+        (&Method::DELETE, "/keys/{id}") => {
+            responses.insert("204".into(), json!({ "description": "The key was revoked" }));
+            responses.insert("404".into(), error("There is no such key"));
+        }
+        (&Method::GET, "/keys/{id}/usage") => {
+            responses.insert("200".into(), json!({
+                "description": "The key's usage in the current minute and day",
+                "content": { "application/json": { "schema": schemas.subschema_for::<KeyUsage>() } },
+            }));
+            responses.insert("404".into(), error("There is no such key"));
+        }
+        (&Method::DELETE, "/keys/{id}/usage") => {
+            responses.insert("204".into(), json!({ "description": "The key has its full quotas again" }));
+            responses.insert("404".into(), error("There is no such key"));
+        }
+        _ => unreachable!("{} {} has no OpenAPI description", endpoint.method, endpoint.path),
+    }
+
+    if !parameters.is_empty() {
+        operation["parameters"] = json!(parameters);
+    }
+    operation["responses"] = Value::Object(responses);
+    operation
+}
+
+/// The headers of responses to requests made with an API key.
+fn rate_limit_headers() -> Value {
+    let header = |description: &str| json!({ "description": description, "schema": { "type": "string" } });
+    json!({
+        "RateLimit-Limit": header("The limit of the quota nearest to running out"),
+        "RateLimit-Remaining": header("Requests left in that quota"),
+        "RateLimit-Reset": header("Seconds until that quota resets"),
+        "RateLimit-Policy": header("Both quotas, as `limit;w=seconds`"),
+        "Retry-After": header("Seconds to wait, when a quota is used up"),
+    })
+}
+
+/// Content of an export or import body, in either format.
+fn bulk_content(description: &str) -> Value {
+    let schema = json!({ "type": "string", "description": description });
+    json!({ bulk::NDJSON: { "schema": schema }, bulk::CSV: { "schema": schema } })
+}
+
+fn list_parameters() -> Vec<Value> {
+    let sorts: Vec<String> = SortField::ALL
+        .iter()
+        .flat_map(|field| vec![field.as_str().to_string(), format!("-{}", field.as_str())])
+        .collect();
+    let query = |name: &str, description: &str, schema: Value| {
+        json!({ "name": name, "in": "query", "description": description, "schema": schema })
+    };
+    vec![
+        query("name", "Case-insensitive substring of the name", json!({ "type": "string" })),
+        query("email", "Case-insensitive substring of the email", json!({ "type": "string" })),
+        query("created_after", "Only users created after this time", json!({ "type": "string", "format": "date-time" })),
+        query("created_before", "Only users created before this time", json!({ "type": "string", "format": "date-time" })),
+        query("sort", "Field to sort by; `-` sorts descending", json!({ "type": "string", "enum": sorts, "default": "id" })),
+        query(
+            "limit",
+            "Users per page",
+            json!({ "type": "integer", "minimum": 1, "maximum": MAX_LIMIT, "default": DEFAULT_LIMIT }),
+        ),
+        query("cursor", "Where the page starts, from a `next` or `prev` URL", json!({ "type": "string" })),
+    ]
+}
+
+/// The index page: every operation in the document, with its summary.
+pub fn index_html() -> &'static str {
+    static INDEX: OnceLock<String> = OnceLock::new();
+    INDEX.get_or_init(|| render_index(document()))
+}
+
+fn render_index(document: &Value) -> String {
+    let mut items = String::new();
+    for (path, operations) in document["paths"].as_object().into_iter().flatten() {
+        for method in ["get", "post", "put", "patch", "delete"].iter() {
+            if let Some(summary) = operations[*method]["summary"].as_str() {
+                items.push_str(&format!(
+                    "            <li><strong>{} {}</strong> - {}</li>\n",
+                    method.to_ascii_uppercase(),
+                    escape(path),
+                    escape(summary)
+                ));
+            }
+        }
+    }
+
+    format!(
+        r#"
+<!doctype html>
+<html>
+    <head>
+        <title>Rust REST Microservice - Hyper 1.7.0 on Vercel</title>
+    </head>
+    <body>
+        <h3>Rust REST Microservice - Hyper 1.7.0 on Vercel</h3>
+        <p>This microservice demonstrates:</p>
+        <ul>
+            <li><strong>hyper 1.7.0</strong> - HTTP library</li>
+            <li><strong>Vercel Runtime</strong> - Serverless deployment</li>
+            <li><strong>REST API</strong> - Full CRUD operations</li>
+            <li><strong>User management</strong> - Create, read, update, delete users</li>
+        </ul>
+        <h4>API Endpoints:</h4>
+        <ul>
+{}        </ul>
+        <p>The full description, with parameters and schemas, is at <a href="{}">{}</a>.</p>
+        <p>Deployed on Vercel as a serverless function!</p>
+    </body>
+</html>
+"#,
+        items, OPENAPI_PATH, OPENAPI_PATH
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
@@ -0,0 +1,192 @@
+//! Partial updates: JSON Merge Patch (RFC 7396) and JSON Patch (RFC 6902).
+//!
+//! Both work on plain JSON values. The router turns the result back into a
+//! [`UserInput`](crate::user::UserInput), so a patch can only produce a user
+//! that a PUT could have produced too.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+pub const MERGE_PATCH_JSON: &str = "application/merge-patch+json";
+pub const JSON_PATCH_JSON: &str = "application/json-patch+json";
+
+/// A patch document in either format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Patch {
+    Merge(Value),
+    Json(Vec<Operation>),
+}
+
+impl Patch {
+    /// Applies the patch to `target`. The error says which part of the patch
+    /// could not be applied.
+    pub fn apply(&self, target: &mut Value) -> Result<(), String> {
+        match self {
+            Patch::Merge(patch) => {
+                merge(target, patch);
+                Ok(())
+            }
+            Patch::Json(operations) => apply(target, operations),
+        }
+    }
+}
+
+/// Applies a merge patch: objects merge member by member, `null` removes a
+/// member and anything else replaces what was there.
+pub fn merge(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        other => {
+            *target = other.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (name, value) in patch {
+        if value.is_null() {
+            target.remove(name);
+        } else {
+            merge(target.entry(name.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// One step of a JSON Patch document.
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+#[schemars(rename = "PatchOperation")]
+pub enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+/// Applies `operations` in order. If one fails, `target` may be left half
+/// patched; callers apply patches to a copy.
+pub fn apply(target: &mut Value, operations: &[Operation]) -> Result<(), String> {
+    for (i, operation) in operations.iter().enumerate() {
+        apply_one(target, operation).map_err(|e| format!("operation {}: {}", i, e))?;
+    }
+    Ok(())
+}
+
+fn apply_one(target: &mut Value, operation: &Operation) -> Result<(), String> {
+    match operation {
+        Operation::Add { path, value } => add(target, &pointer(path)?, value.clone()),
+        Operation::Remove { path } => remove(target, &pointer(path)?).map(drop),
+        Operation::Replace { path, value } => {
+            *lookup(target, &pointer(path)?)? = value.clone();
+            Ok(())
+        }
+        Operation::Move { from, path } => {
+            let (from, to) = (pointer(from)?, pointer(path)?);
+            if to.len() > from.len() && to.starts_with(&from) {
+                return Err(format!("cannot move `{}` into itself", render(&from)));
+            }
+            let value = remove(target, &from)?;
+            add(target, &to, value)
+        }
+        Operation::Copy { from, path } => {
+            let value = lookup(target, &pointer(from)?)?.clone();
+            add(target, &pointer(path)?, value)
+        }
+        Operation::Test { path, value } => {
+            let path = pointer(path)?;
+            if lookup(target, &path)? != value {
+                return Err(format!("`{}` is not {}", render(&path), value));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Splits an RFC 6901 JSON pointer into unescaped reference tokens.
+fn pointer(s: &str) -> Result<Vec<String>, String> {
+    if s.is_empty() {
+        return Ok(Vec::new());
+    }
+    let tokens = s
+        .strip_prefix('/')
+        .ok_or_else(|| format!("`{}` is not a JSON pointer", s))?;
+    Ok(tokens
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+fn render(tokens: &[String]) -> String {
+    tokens
+        .iter()
+        .map(|token| format!("/{}", token.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+fn lookup<'a>(target: &'a mut Value, tokens: &[String]) -> Result<&'a mut Value, String> {
+    let mut value = target;
+    for (depth, token) in tokens.iter().enumerate() {
+        let missing = || format!("`{}` does not exist", render(&tokens[..=depth]));
+        value = match value {
+            Value::Object(members) => members.get_mut(token).ok_or_else(missing)?,
+            Value::Array(items) => {
+                let len = items.len();
+                items.get_mut(index(token, len)?).ok_or_else(missing)?
+            }
+            _ => return Err(missing()),
+        };
+    }
+    Ok(value)
+}
+
+fn add(target: &mut Value, tokens: &[String], value: Value) -> Result<(), String> {
+    let (last, parent) = match tokens.split_last() {
+        Some(split) => split,
+        None => {
+            *target = value;
+            return Ok(());
+        }
+    };
+    match lookup(target, parent)? {
+        Value::Object(members) => {
+            members.insert(last.clone(), value);
+        }
+        Value::Array(items) => {
+            let at = if last == "-" { items.len() } else { index(last, items.len() + 1)? };
+            items.insert(at, value);
+        }
+        _ => return Err(format!("`{}` is not an object or array", render(parent))),
+    }
+    Ok(())
+}
+
+fn remove(target: &mut Value, tokens: &[String]) -> Result<Value, String> {
+    let (last, parent) = tokens
+        .split_last()
+        .ok_or("cannot remove the whole document")?;
+    let missing = || format!("`{}` does not exist", render(tokens));
+    match lookup(target, parent)? {
+        Value::Object(members) => members.remove(last).ok_or_else(missing),
+        Value::Array(items) => {
+            let at = index(last, items.len())?;
+            Ok(items.remove(at))
+        }
+        _ => Err(missing()),
+    }
+}
+
+/// An array index below `len`, spelled without leading zeros.
+fn index(token: &str, len: usize) -> Result<usize, String> {
+    let canonical = !token.is_empty()
+        && token.bytes().all(|b| b.is_ascii_digit())
+        && (token == "0" || !token.starts_with('0'));
+    match token.parse() {
+        Ok(i) if canonical && i < len => Ok(i),
+        _ => Err(format!("`{}` is not an index below {}", token, len)),
+    }
+}
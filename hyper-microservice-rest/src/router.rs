@@ -0,0 +1,672 @@
+//! Maps requests to responses. Both the Vercel function and the local
+//! server hand every request to a [`Router`], so they cannot disagree.
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use http::header::{HeaderName, ACCEPT, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LINK, LOCATION};
+use http::{Method, Request, Response, StatusCode};
+use http_body_util::{Either, Full};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::accept;
+use crate::auth::{Authenticator, Role};
+use crate::bulk::{self, Export, Import, EXPORT_PATH, IMPORT_PATH};
+use crate::changes::{ChangeFeed, FeedQuery, CHANGES_PATH, HISTORY_SUFFIX};
+use crate::conditional;
+use crate::error::ApiError;
+use crate::keys::{ApiKeys, Check, KeyInput, KeyUsage, KEYS_PATH, KEY_HEADER};
+use crate::list::{self, ListQuery, UserList};
+use crate::openapi::{self, OPENAPI_PATH};
+use crate::patch::{self, Patch};
+use crate::store::{Outcome, UserStore};
+use crate::user::{self, User, UserData, UserId, UserInput};
+use crate::version;
+use crate::webhook::{DeliveryStatus, Redelivery, Run, WebhookInput, Webhooks, CRON_PATH, DELIVERIES_PATH, RUN_PATH, WEBHOOKS_PATH};
+
+const USER_PATH: &str = "/user/";
+
+/// The actor recorded for changes made without a token, or with one that
+/// has no `sub`.
+const ANONYMOUS: &str = "anonymous";
+
+/// The actor for requests made with the cron secret.
+const CRON: &str = "cron";
+
+/// The body of a response from [`Router::handle_streaming`].
+pub type StreamingBody = Either<Full<Bytes>, Export>;
+
+pub struct Router {
+    users: Arc<dyn UserStore>,
+    auth: Option<Authenticator>,
+    webhooks: Option<Arc<Webhooks>>,
+    api_keys: Option<ApiKeys>,
+    cron_secret: Option<String>,
+}
+
+impl Router {
+    /// A router that lets anyone do anything.
+    pub fn new(users: Arc<dyn UserStore>) -> Router {
+        Router {
+            users,
+            auth: None,
+            webhooks: None,
+            api_keys: None,
+            cron_secret: None,
+        }
+    }
+
+    /// Requires a bearer token that `auth` accepts for every user endpoint.
+    pub fn with_auth(mut self, auth: Authenticator) -> Router {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Serves the `/webhooks` endpoints from `webhooks`. Without it they
+    /// answer 404.
+    pub fn with_webhooks(mut self, webhooks: Arc<Webhooks>) -> Router {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    /// Requires an API key from `api_keys`, within its quotas, for every
+    /// endpoint but the index, the OpenAPI document and the `/keys` ones,
+    /// which manage the keys. Without it the `/keys` endpoints answer 404.
+    pub fn with_api_keys(mut self, api_keys: ApiKeys) -> Router {
+        self.api_keys = Some(api_keys);
+        self
+    }
+
+    /// Lets a request with `Authorization: Bearer <secret>` make the
+    /// webhook deliveries that are due, at [`CRON_PATH`] or [`RUN_PATH`],
+    /// without a token. Without it [`CRON_PATH`] answers 404.
+    pub fn with_cron_secret(mut self, secret: String) -> Router {
+        self.cron_secret = Some(secret);
+        self
+    }
+
+    /// Handles a request whose body has been read in full, and answers
+    /// with a body built in full.
+    pub fn handle(&self, req: Request<Vec<u8>>) -> Response<Vec<u8>> {
+        let instance = req.uri().path().to_string();
+        let mut response = self.respond(req);
+        match response.extensions_mut().remove::<Export>() {
+            None => response,
+            Some(export) => match export.collect::<Result<Vec<_>, _>>() {
+                Ok(rows) => response.map(|_| rows.concat()),
+                Err(e) => ApiError::from(e).into_response(&instance).map(String::into_bytes),
+            },
+        }
+    }
+
+    /// Like [`Router::handle`], but an export is read from the store as it
+    /// is sent rather than all at once.
+    pub fn handle_streaming(&self, req: Request<Vec<u8>>) -> Response<StreamingBody> {
+        let mut response = self.respond(req);
+        match response.extensions_mut().remove::<Export>() {
+            None => response.map(|body| Either::Left(Full::from(body))),
+            Some(export) => response.map(|_| Either::Right(export)),
+        }
+    }
+
+    /// Answers `req`. An export comes back with an empty body and the
+    /// [`Export`] to send in its place in the extensions.
+    fn respond(&self, req: Request<Vec<u8>>) -> Response<Vec<u8>> {
+        let instance = req.uri().path().to_string();
+        let (selection, req) = match version::negotiate(req) {
+            Ok(negotiated) => negotiated,
+            Err(e) => return e.into_response(&instance).map(String::into_bytes),
+        };
+        let now = Utc::now();
+        let mut usage = None;
+        let webhooks = self.webhooks.as_deref();
+        let api_keys = self.api_keys.as_ref();
+        let routed = self.authorize(&req).and_then(|actor| {
+            self.meter(&req, now, &mut usage)?;
+            route(&req, &self.users, webhooks, api_keys, &actor)
+        });
+        let mut response = routed.unwrap_or_else(|e| e.into_response(&instance).map(String::into_bytes));
+        if let Some(usage) = usage {
+            usage.write_headers(now, response.headers_mut());
+        }
+        version::present(selection, &req, response)
+    }
+
+    /// Checks the caller may make `req` and returns who they are.
+    fn authorize(&self, req: &Request<Vec<u8>>) -> Result<String, ApiError> {
+        let path = req.uri().path();
+        if (path == RUN_PATH || path == CRON_PATH) && self.is_cron(req) {
+            return Ok(CRON.to_string());
+        }
+        // Unlike the JWT-checked endpoints, this one takes nothing but the
+        // secret, and does not exist without one.
+        if path == CRON_PATH && req.method() == Method::GET {
+            return match self.cron_secret {
+                Some(_) => Err(ApiError::Unauthorized("send the cron secret as a bearer token".to_string())),
+                None => Err(not_found(path)),
+            };
+        }
+        match (&self.auth, required_role(req)) {
+            (Some(auth), Some(role)) => {
+                let principal = auth.authorize(header(req, AUTHORIZATION), role)?;
+                Ok(principal.subject.unwrap_or_else(|| ANONYMOUS.to_string()))
+            }
+            _ => Ok(ANONYMOUS.to_string()),
+        }
+    }
+
+    /// Whether `req` carries the cron secret as its bearer token.
+    fn is_cron(&self, req: &Request<Vec<u8>>) -> bool {
+        let token = header(req, AUTHORIZATION).and_then(|value| value.strip_prefix("Bearer "));
+        match (&self.cron_secret, token) {
+            (Some(secret), Some(token)) => same_secret(secret.as_bytes(), token.as_bytes()),
+            _ => false,
+        }
+    }
+
+    /// Counts `req` against its API key, if keys are on and the path needs
+    /// one. The key's usage is left in `usage` for the response headers,
+    /// whether or not the request was within quota.
+    fn meter(&self, req: &Request<Vec<u8>>, now: DateTime<Utc>, usage: &mut Option<KeyUsage>) -> Result<(), ApiError> {
+        let api_keys = match &self.api_keys {
+            Some(api_keys) if metered(req.uri().path()) => api_keys,
+            _ => return Ok(()),
+        };
+        match api_keys.check(header(req, HeaderName::from_static(KEY_HEADER)), now)? {
+            Check::Missing => Err(ApiError::Unauthorized("send an API key in the X-API-Key header".to_string())),
+            Check::Unknown => Err(ApiError::Unauthorized("the API key is not valid".to_string())),
+            Check::Allowed(checked) => {
+                *usage = Some(checked);
+                Ok(())
+            }
+            Check::Limited(checked) => {
+                *usage = Some(checked);
+                let (window, limit) = if checked.day.remaining == 0 {
+                    ("day", checked.day.limit)
+                } else {
+                    ("minute", checked.minute.limit)
+                };
+                Err(ApiError::TooManyRequests {
+                    detail: format!("key {} has made its {} requests for this {}", checked.key, limit, window),
+                    retry_after: checked.reset_in(now),
+                })
+            }
+        }
+    }
+}
+
+/// Whether requests to `path` need an API key when keys are on. A cron
+/// job calling [`RUN_PATH`] or [`CRON_PATH`] has only a token.
+fn metered(path: &str) -> bool {
+    !(path == "/"
+        || path == OPENAPI_PATH
+        || path == KEYS_PATH
+        || path.starts_with("/keys/")
+        || path == RUN_PATH
+        || path == CRON_PATH)
+}
+
+/// Compares secrets in time that depends only on their lengths.
+fn same_secret(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The least role that may make `req`, or `None` if anyone may.
+fn required_role(req: &Request<Vec<u8>>) -> Option<Role> {
+    openapi::role(req.method(), template(req.uri().path())?)
+}
+
+/// The [`openapi::ENDPOINTS`] path that `path` falls under, if any.
+fn template(path: &str) -> Option<&'static str> {
+    match path {
+        "/" => Some("/"),
+        OPENAPI_PATH => Some(OPENAPI_PATH),
+        EXPORT_PATH => Some(EXPORT_PATH),
+        IMPORT_PATH => Some(IMPORT_PATH),
+        CHANGES_PATH => Some(CHANGES_PATH),
+        _ if path == WEBHOOKS_PATH || path.starts_with("/webhooks/") => webhook_template(path),
+        _ if path == KEYS_PATH || path.starts_with("/keys/") => key_template(path),
+        USER_PATH => Some(USER_PATH),
+        _ if history_of(path).is_some() => Some("/user/{id}/history"),
+        _ if path.starts_with(USER_PATH) => Some("/user/{id}"),
+        _ => None,
+    }
+}
+
+/// The `{id}` in a `/user/{id}/history` path.
+fn history_of(path: &str) -> Option<&str> {
+    path.strip_prefix(USER_PATH)?.strip_suffix(HISTORY_SUFFIX)
+}
+
+/// Like [`template`], for paths under `/webhooks`, but only for ids that
+/// parse.
+fn webhook_template(path: &str) -> Option<&'static str> {
+    let segments: Vec<&str> = path.split('/').skip(2).collect();
+    let id = |s: &str| user::parse_id(s).is_some();
+    match segments.as_slice() {
+        [] => Some(WEBHOOKS_PATH),
+        ["deliveries"] => Some(DELIVERIES_PATH),
+        ["run"] => Some(RUN_PATH),
+        ["cron"] => Some(CRON_PATH),
+        ["deliveries", delivery] if id(delivery) => Some("/webhooks/deliveries/{id}"),
+        ["deliveries", delivery, "retry"] if id(delivery) => Some("/webhooks/deliveries/{id}/retry"),
+        [hook] if id(hook) => Some("/webhooks/{id}"),
+        _ => None,
+    }
+}
+
+/// Like [`template`], for paths under `/keys`, but only for ids that parse.
+fn key_template(path: &str) -> Option<&'static str> {
+    let segments: Vec<&str> = path.split('/').skip(2).collect();
+    match segments.as_slice() {
+        [] => Some(KEYS_PATH),
+        [key] if user::parse_id(key).is_some() => Some("/keys/{id}"),
+        [key, "usage"] if user::parse_id(key).is_some() => Some("/keys/{id}/usage"),
+        _ => None,
+    }
+}
+
+fn route(
+    req: &Request<Vec<u8>>,
+    shared: &Arc<dyn UserStore>,
+    webhooks: Option<&Webhooks>,
+    api_keys: Option<&ApiKeys>,
+    actor: &str,
+) -> Result<Response<Vec<u8>>, ApiError> {
+    let path = req.uri().path();
+    let users = shared.as_ref();
+
+    match (req.method(), path) {
+        (&Method::GET, "/") => {
+            Ok(Response::builder()
+                .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                .body(openapi::index_html().as_bytes().to_vec())
+                .unwrap())
+        },
+        (&Method::GET, OPENAPI_PATH) => Ok(json_response(StatusCode::OK, openapi::document())),
+        (&Method::GET, EXPORT_PATH) => export_users(req, shared),
+        (&Method::POST, IMPORT_PATH) => import_users(req, users, actor),
+        (&Method::GET, CHANGES_PATH) => {
+            let query = FeedQuery::parse(req.uri().query()).map_err(ApiError::InvalidQuery)?;
+            let changes = users.changes(query.since, query.limit)?;
+            Ok(json_response(StatusCode::OK, &ChangeFeed::new(changes, query.since)))
+        },
+        (_, "/") | (_, OPENAPI_PATH) | (_, EXPORT_PATH) | (_, IMPORT_PATH) | (_, CHANGES_PATH) => {
+            Err(method_not_allowed(path))
+        },
+        (_, path) if path == WEBHOOKS_PATH || path.starts_with("/webhooks/") => match webhooks {
+            Some(webhooks) => route_webhooks(req, webhooks),
+            None => Err(ApiError::NotFound("webhooks are not enabled on this server".to_string())),
+        },
+        (_, path) if path == KEYS_PATH || path.starts_with("/keys/") => match api_keys {
+            Some(api_keys) => route_keys(req, api_keys),
+            None => Err(ApiError::NotFound("API keys are not enabled on this server".to_string())),
+        },
+        (method, path) if history_of(path).is_some() => {
+            let id = history_of(path).and_then(user::parse_id).ok_or_else(|| not_found(path))?;
+            if method != Method::GET {
+                return Err(method_not_allowed(path));
+            }
+            let history = users.history(id)?;
+            if history.is_empty() {
+                return Err(ApiError::NotFound(format!("there is no user with id {}", id)));
+            }
+            Ok(json_response(StatusCode::OK, &history))
+        },
+        (method, path) if path.starts_with(USER_PATH) => {
+            let user_id = match path.trim_start_matches(USER_PATH) {
+                "" => None,
+                id => Some(user::parse_id(id).ok_or_else(|| not_found(path))?),
+            };
+
+            match (method, user_id) {
+                (&Method::GET, None) => list_users(req, users),
+                (&Method::GET, Some(id)) => {
+                    let data = match users.get(id)? {
+                        Some(data) => data,
+                        None => return Err(no_such_user(users, id)),
+                    };
+                    let etag = conditional::etag(data.version);
+                    if header(req, IF_NONE_MATCH).is_some_and(|tags| conditional::if_none_match(tags, &etag)) {
+                        let mut response = response_with_code(StatusCode::NOT_MODIFIED);
+                        response.headers_mut().insert(ETAG, etag.parse().unwrap());
+                        return Ok(response);
+                    }
+                    Ok(user_response(StatusCode::OK, id, &data))
+                },
+                (&Method::POST, None) => {
+                    let data = UserData::new(read_input(req)?, Utc::now());
+                    let id = users.insert(data.clone(), actor)?;
+                    let mut response = user_response(StatusCode::CREATED, id, &data);
+                    let location = format!("{}{}", USER_PATH, id).parse().unwrap();
+                    response.headers_mut().insert(LOCATION, location);
+                    Ok(response)
+                },
+                (&Method::PUT, Some(id)) => {
+                    let input = read_input(req)?;
+                    let existing = match users.get(id)? {
+                        Some(existing) => existing,
+                        None => return Err(no_such_user(users, id)),
+                    };
+                    check_if_match(req, id, existing.version)?;
+                    save(users, id, &existing, input, actor)
+                },
+                (&Method::PATCH, Some(id)) => {
+                    let patch = read_patch(req)?;
+                    let existing = match users.get(id)? {
+                        Some(existing) => existing,
+                        None => return Err(no_such_user(users, id)),
+                    };
+                    check_if_match(req, id, existing.version)?;
+                    let mut document = serde_json::to_value(existing.input()).expect("users always serialize");
+                    patch.apply(&mut document).map_err(ApiError::Unprocessable)?;
+                    let input = UserInput::from_value(document)
+                        .map_err(|e| ApiError::Unprocessable(format!("patched user is invalid: {}", e)))?;
+                    save(users, id, &existing, input, actor)
+                },
+                (&Method::DELETE, Some(id)) => {
+                    let expected = match header(req, IF_MATCH) {
+                        Some(_) => {
+                            let existing = match users.get(id)? {
+                                Some(existing) => existing,
+                                None => return Err(no_such_user(users, id)),
+                            };
+                            check_if_match(req, id, existing.version)?;
+                            Some(existing.version)
+                        },
+                        None => None,
+                    };
+                    match users.remove(id, expected, actor)? {
+                        Outcome::Done => Ok(response_with_code(StatusCode::NO_CONTENT)),
+                        Outcome::Missing => Err(no_such_user(users, id)),
+                        Outcome::Conflict(_) => Err(changed_meanwhile(id)),
+                    }
+                },
+                _ => Err(method_not_allowed(path)),
+            }
+        },
+        _ => Err(not_found(path)),
+    }
+}
+
+fn list_users(req: &Request<Vec<u8>>, users: &dyn UserStore) -> Result<Response<Vec<u8>>, ApiError> {
+    let query = ListQuery::parse(req.uri().query()).map_err(ApiError::InvalidQuery)?;
+    let page = list::paginate(users.list()?, &query);
+
+    let url = |query: &String| format!("{}?{}", USER_PATH, query);
+    let body = UserList {
+        users: page.users.iter().map(|(id, data)| User { id: *id, data }).collect(),
+        next: page.next.as_ref().map(url),
+        prev: page.prev.as_ref().map(url),
+    };
+    let mut response = json_response(StatusCode::OK, &body);
+
+    let links: Vec<String> = [("next", &body.next), ("prev", &body.prev)]
+        .iter()
+        .filter_map(|(rel, url)| url.as_ref().map(|url| format!("<{}>; rel=\"{}\"", url, rel)))
+        .collect();
+    if !links.is_empty() {
+        response.headers_mut().insert(LINK, links.join(", ").parse().unwrap());
+    }
+    Ok(response)
+}
+
+fn export_users(req: &Request<Vec<u8>>, users: &Arc<dyn UserStore>) -> Result<Response<Vec<u8>>, ApiError> {
+    let format = accept::preferred(header(req, ACCEPT), bulk::FORMATS).ok_or(ApiError::NotAcceptable(bulk::FORMATS))?;
+    let mut response = Response::builder().header(CONTENT_TYPE, format).body(Vec::new()).unwrap();
+    response.extensions_mut().insert(Export::new(Arc::clone(users), format));
+    Ok(response)
+}
+
+/// Imports are atomic unless the query says `atomic=false`.
+fn import_users(req: &Request<Vec<u8>>, users: &dyn UserStore, actor: &str) -> Result<Response<Vec<u8>>, ApiError> {
+    let mut atomic = true;
+    for (name, value) in list::parse_query(req.uri().query().unwrap_or("")).map_err(ApiError::InvalidQuery)? {
+        atomic = match (name.as_str(), value.as_str()) {
+            ("atomic", "true") => true,
+            ("atomic", "false") => false,
+            ("atomic", _) => return Err(ApiError::InvalidQuery("`atomic` must be true or false".to_string())),
+            _ => return Err(ApiError::InvalidQuery(format!("unknown query parameter `{}`", name))),
+        };
+    }
+
+    let format = match media_type(req) {
+        Some(mime) if bulk::FORMATS.contains(&mime.as_str()) => mime,
+        mime => {
+            return Err(ApiError::UnsupportedMediaType(format!(
+                "expected {}, got {}",
+                bulk::FORMATS.join(" or "),
+                mime.as_deref().unwrap_or("no Content-Type")
+            )))
+        }
+    };
+    let import = Import::parse(&format, req.body()).map_err(ApiError::InvalidBody)?;
+    let report = bulk::import(users, import, atomic, actor)?;
+    let status = if report.atomic && report.rejected > 0 {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::OK
+    };
+    Ok(json_response(status, &report))
+}
+
+fn route_webhooks(req: &Request<Vec<u8>>, webhooks: &Webhooks) -> Result<Response<Vec<u8>>, ApiError> {
+    let path = req.uri().path();
+    let id = |s: &str| user::parse_id(s).ok_or_else(|| not_found(path));
+    let segments: Vec<&str> = path.split('/').skip(2).collect();
+
+    match (req.method(), segments.as_slice()) {
+        (&Method::GET, []) => Ok(json_response(StatusCode::OK, &webhooks.list())),
+        (&Method::POST, []) => {
+            let input: WebhookInput = read_json(req)?;
+            input.validate().map_err(ApiError::Unprocessable)?;
+            let hook = webhooks.register(input, Utc::now())?;
+            let mut response = json_response(StatusCode::CREATED, &hook);
+            let location = format!("{}/{}", WEBHOOKS_PATH, hook.id).parse().unwrap();
+            response.headers_mut().insert(LOCATION, location);
+            Ok(response)
+        },
+        (&Method::GET, ["deliveries"]) => {
+            let (mut webhook, mut status) = (None, None);
+            for (name, value) in list::parse_query(req.uri().query().unwrap_or("")).map_err(ApiError::InvalidQuery)? {
+                match name.as_str() {
+                    "webhook" => {
+                        let id = user::parse_id(&value).ok_or_else(|| {
+                            ApiError::InvalidQuery(format!("`webhook` must be a webhook id, got `{}`", value))
+                        })?;
+                        webhook = Some(id);
+                    }
+                    "status" => {
+                        let parsed = DeliveryStatus::parse(&value).ok_or_else(|| {
+                            ApiError::InvalidQuery("`status` must be pending, delivered or dead".to_string())
+                        })?;
+                        status = Some(parsed);
+                    }
+                    _ => return Err(ApiError::InvalidQuery(format!("unknown query parameter `{}`", name))),
+                }
+            }
+            Ok(json_response(StatusCode::OK, &webhooks.deliveries(webhook, status)))
+        },
+        (&Method::GET, ["deliveries", delivery]) => match webhooks.delivery(id(delivery)?) {
+            Some(delivery) => Ok(json_response(StatusCode::OK, &delivery)),
+            None => Err(ApiError::NotFound(format!("there is no delivery with id {}", delivery))),
+        },
+        // `Router::authorize` lets `GET` on the cron path through only with
+        // the cron secret.
+        (&Method::POST, ["run"]) | (&Method::GET, ["cron"]) => {
+            let attempts = webhooks.run(Utc::now())?;
+            Ok(json_response(StatusCode::OK, &Run { attempts }))
+        },
+        (&Method::POST, ["deliveries", delivery, "retry"]) => match webhooks.redeliver(id(delivery)?, Utc::now())? {
+            Redelivery::Queued(delivery) => Ok(json_response(StatusCode::ACCEPTED, &delivery)),
+            Redelivery::Missing => Err(ApiError::NotFound(format!("there is no delivery with id {}", delivery))),
+            Redelivery::Delivered => Err(ApiError::Conflict(format!("delivery {} went through already", delivery))),
+        },
+        (&Method::DELETE, [hook]) if !matches!(*hook, "deliveries" | "run" | "cron") => {
+            if webhooks.remove(id(hook)?)? {
+                Ok(response_with_code(StatusCode::NO_CONTENT))
+            } else {
+                Err(ApiError::NotFound(format!("there is no webhook with id {}", hook)))
+            }
+        },
+        _ if webhook_template(path).is_some() => Err(method_not_allowed(path)),
+        _ => Err(not_found(path)),
+    }
+}
+
+fn route_keys(req: &Request<Vec<u8>>, api_keys: &ApiKeys) -> Result<Response<Vec<u8>>, ApiError> {
+    let path = req.uri().path();
+    let id = |s: &str| user::parse_id(s).ok_or_else(|| not_found(path));
+    let no_such_key = |key: &str| ApiError::NotFound(format!("there is no key with id {}", key));
+    let segments: Vec<&str> = path.split('/').skip(2).collect();
+
+    match (req.method(), segments.as_slice()) {
+        (&Method::GET, []) => Ok(json_response(StatusCode::OK, &api_keys.list())),
+        (&Method::POST, []) => {
+            let input: KeyInput = read_json(req)?;
+            input.validate().map_err(ApiError::Unprocessable)?;
+            let issued = api_keys.issue(input, Utc::now())?;
+            let mut response = json_response(StatusCode::CREATED, &issued);
+            let location = format!("{}/{}", KEYS_PATH, issued.info.id).parse().unwrap();
+            response.headers_mut().insert(LOCATION, location);
+            Ok(response)
+        },
+        (&Method::DELETE, [key]) => match api_keys.revoke(id(key)?)? {
+            true => Ok(response_with_code(StatusCode::NO_CONTENT)),
+            false => Err(no_such_key(key)),
+        },
+        (&Method::GET, [key, "usage"]) => match api_keys.usage(id(key)?, Utc::now()) {
+            Some(usage) => Ok(json_response(StatusCode::OK, &usage)),
+            None => Err(no_such_key(key)),
+        },
+        (&Method::DELETE, [key, "usage"]) => match api_keys.reset(id(key)?)? {
+            true => Ok(response_with_code(StatusCode::NO_CONTENT)),
+            false => Err(no_such_key(key)),
+        },
+        _ if key_template(path).is_some() => Err(method_not_allowed(path)),
+        _ => Err(not_found(path)),
+    }
+}
+
+fn method_not_allowed(path: &str) -> ApiError {
+    let methods = template(path).map(openapi::methods).unwrap_or_default();
+    ApiError::MethodNotAllowed(methods)
+}
+
+fn not_found(path: &str) -> ApiError {
+    ApiError::NotFound(format!("nothing lives at {}", path))
+}
+
+/// 410 for ids that belonged to a deleted user, 404 for ones never used.
+fn no_such_user(users: &dyn UserStore, id: UserId) -> ApiError {
+    match users.deleted(id) {
+        Ok(true) => ApiError::Gone(format!("user {} was deleted", id)),
+        Ok(false) => ApiError::NotFound(format!("there is no user with id {}", id)),
+        Err(e) => e.into(),
+    }
+}
+
+fn header(req: &Request<Vec<u8>>, name: http::header::HeaderName) -> Option<&str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Fails with 412 unless `If-Match`, if sent, names the user's current version.
+fn check_if_match(req: &Request<Vec<u8>>, id: UserId, version: u64) -> Result<(), ApiError> {
+    match header(req, IF_MATCH) {
+        Some(tags) if !conditional::if_match(tags, &conditional::etag(version)) => {
+            Err(ApiError::PreconditionFailed(format!(
+                "user {} is at version {}, not one of {}",
+                id, version, tags
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Replaces `existing` with `input`, unless another request got there first.
+fn save(
+    users: &dyn UserStore,
+    id: UserId,
+    existing: &UserData,
+    input: UserInput,
+    actor: &str,
+) -> Result<Response<Vec<u8>>, ApiError> {
+    let data = existing.replaced(input, Utc::now());
+    match users.update(id, existing.version, data.clone(), actor)? {
+        Outcome::Done => Ok(user_response(StatusCode::OK, id, &data)),
+        Outcome::Missing => Err(no_such_user(users, id)),
+        Outcome::Conflict(_) => Err(changed_meanwhile(id)),
+    }
+}
+
+/// Another request changed the user between reading and writing it.
+fn changed_meanwhile(id: UserId) -> ApiError {
+    ApiError::PreconditionFailed(format!("user {} was changed by another request", id))
+}
+
+/// The media type of the body, without parameters, in lower case.
+fn media_type(req: &Request<Vec<u8>>) -> Option<String> {
+    let content_type = header(req, CONTENT_TYPE)?;
+    Some(content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+}
+
+/// Reads a user from a JSON request body.
+fn read_input(req: &Request<Vec<u8>>) -> Result<UserInput, ApiError> {
+    if let Some(mime) = media_type(req) {
+        if mime != "application/json" {
+            let detail = format!("expected application/json, got {}", mime);
+            return Err(ApiError::UnsupportedMediaType(detail));
+        }
+    }
+    UserInput::from_json(req.body()).map_err(ApiError::InvalidBody)
+}
+
+/// Reads a JSON request body that is not a user.
+fn read_json<T: DeserializeOwned>(req: &Request<Vec<u8>>) -> Result<T, ApiError> {
+    if let Some(mime) = media_type(req) {
+        if mime != "application/json" {
+            return Err(ApiError::UnsupportedMediaType(format!("expected application/json, got {}", mime)));
+        }
+    }
+    serde_json::from_slice(req.body()).map_err(|e| ApiError::InvalidBody(e.to_string()))
+}
+
+/// Reads a patch, whose format the `Content-Type` has to name.
+fn read_patch(req: &Request<Vec<u8>>) -> Result<Patch, ApiError> {
+    let invalid = |e: serde_json::Error| ApiError::InvalidBody(format!("invalid patch document: {}", e));
+    match media_type(req).as_deref() {
+        Some(patch::MERGE_PATCH_JSON) => serde_json::from_slice(req.body()).map(Patch::Merge).map_err(invalid),
+        Some(patch::JSON_PATCH_JSON) => serde_json::from_slice(req.body()).map(Patch::Json).map_err(invalid),
+        mime => Err(ApiError::UnsupportedMediaType(format!(
+            "expected {} or {}, got {}",
+            patch::MERGE_PATCH_JSON,
+            patch::JSON_PATCH_JSON,
+            mime.unwrap_or("no Content-Type")
+        ))),
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Vec<u8>> {
+    let body = serde_json::to_string(value).expect("responses always serialize");
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.into_bytes())
+        .unwrap()
+}
+
+/// A single user, tagged with its version.
+fn user_response(status: StatusCode, id: UserId, data: &UserData) -> Response<Vec<u8>> {
+    let mut response = json_response(status, &User { id, data });
+    let etag = conditional::etag(data.version).parse().unwrap();
+    response.headers_mut().insert(ETAG, etag);
+    response
+}
+
+fn response_with_code(status_code: StatusCode) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status_code)
+        .body(Vec::new())
+        .unwrap()
+}
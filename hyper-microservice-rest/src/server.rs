@@ -0,0 +1,73 @@
+//! Serving the [`Router`] with plain hyper, for running the service
+//! without Vercel.
+
+use http::{Request, Response};
+use http_body_util::{BodyExt, Either, Full, Limited};
+use hyper::body::{Body, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::io;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::error::ApiError;
+use crate::router::{Router, StreamingBody};
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+
+/// Largest request body read into memory, matching Vercel's payload limit.
+pub const MAX_BODY: usize = 4_500_000;
+
+/// Accepts connections on `listener` until accepting fails.
+pub async fn serve(listener: TcpListener, router: Arc<Router>) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let router = Arc::clone(&router);
+        tokio::spawn(async move {
+            let service = service_fn(move |req| {
+                let router = Arc::clone(&router);
+                async move { Ok::<_, Infallible>(handle(router, req).await) }
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                eprintln!("connection error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle(router: Arc<Router>, req: Request<Incoming>) -> Response<StreamingBody> {
+    let (parts, body) = req.into_parts();
+    // A declared length over the limit is refused before waiting for it.
+    if body.size_hint().lower() > MAX_BODY as u64 {
+        return ApiError::PayloadTooLarge(MAX_BODY)
+            .into_response(parts.uri.path())
+            .map(|body| Either::Left(Full::from(body)));
+    }
+    let body = match Limited::new(body, MAX_BODY).collect().await {
+        Ok(collected) => collected.to_bytes().to_vec(),
+        Err(e) => {
+            let error = if e.is::<http_body_util::LengthLimitError>() {
+                ApiError::PayloadTooLarge(MAX_BODY)
+            } else {
+                ApiError::InvalidBody(format!("could not read the request body: {}", e))
+            };
+            return error.into_response(parts.uri.path()).map(|body| Either::Left(Full::from(body)));
+        }
+    };
+
+    // The router blocks on the store, on webhook receivers and on locks,
+    // so it runs where that cannot hold up other connections.
+    let instance = parts.uri.path().to_string();
+    let req = Request::from_parts(parts, body);
+    match tokio::task::spawn_blocking(move || router.handle_streaming(req)).await {
+        Ok(response) => response,
+        Err(e) => ApiError::Internal(format!("the request handler failed: {}", e))
+            .into_response(&instance)
+            .map(|body| Either::Left(Full::from(body))),
+    }
+}
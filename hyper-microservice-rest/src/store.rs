@@ -0,0 +1,616 @@
+//! Where users live between requests.
+//!
+//! [`MemoryStore`] keeps them for as long as the process runs. [`FileStore`]
+//! also appends every change to a JSON log and replays it on startup, so
+//! users survive restarts. [`StoreConfig`] picks one from the environment.
+//!
+//! Both stores also keep every change ever made, with who made it and when,
+//! for the audit log in [`changes`](crate::changes).
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::changes::{ChangeKind, ChangeRecord};
+use crate::keys::{ApiKeys, Limits};
+use crate::user::{UserData, UserId};
+use crate::webhook::{Retry, Webhooks};
+
+pub const DEFAULT_LOG_PATH: &str = "users.ndjson";
+
+/// A user store shared by every request the process handles.
+///
+/// Every write names its `actor`, which the audit log records next to the
+/// change.
+pub trait UserStore: Send + Sync {
+    fn insert(&self, user: UserData, actor: &str) -> Result<UserId, StoreError>;
+
+    fn get(&self, id: UserId) -> Result<Option<UserData>, StoreError>;
+
+    /// Whether `id` belonged to a user that has since been removed. Ids
+    /// are never handed out twice.
+    fn deleted(&self, id: UserId) -> Result<bool, StoreError>;
+
+    /// Every user, in no particular order.
+    fn list(&self) -> Result<Vec<(UserId, UserData)>, StoreError>;
+
+    /// Up to `limit` users in id order, starting after `after` or, without
+    /// it, at the first. A page of [`list`](UserStore::list) for callers
+    /// that should not hold every user at once.
+    fn list_after(&self, after: Option<UserId>, limit: usize) -> Result<Vec<(UserId, UserData)>, StoreError>;
+
+    /// Replaces the user, provided it is still at version `expected`.
+    fn update(&self, id: UserId, expected: u64, user: UserData, actor: &str) -> Result<Outcome, StoreError>;
+
+    /// Removes the user, provided it is at version `expected` if one is given.
+    fn remove(&self, id: UserId, expected: Option<u64>, actor: &str) -> Result<Outcome, StoreError>;
+
+    /// Makes all of `changes` or, if any update finds its user gone or at
+    /// another version, none of them.
+    fn apply_all(&self, changes: Vec<Change>, actor: &str) -> Result<Batch, StoreError>;
+
+    /// Every change made to `id`, oldest first. Empty if the id was never
+    /// handed out.
+    fn history(&self, id: UserId) -> Result<Vec<ChangeRecord>, StoreError>;
+
+    /// Up to `limit` changes after the one numbered `since`, oldest first.
+    fn changes(&self, since: u64, limit: usize) -> Result<Vec<ChangeRecord>, StoreError>;
+}
+
+/// One write in a batch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Insert(UserData),
+    /// Replaces the user, provided it is at version `expected`.
+    Update { id: UserId, expected: u64, user: UserData },
+}
+
+/// What became of a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Batch {
+    /// Every change was made. Holds the id each one wrote, in order.
+    Done(Vec<UserId>),
+    /// Nothing was changed, because the change at `index` could not be made.
+    Refused { index: usize, outcome: Outcome },
+}
+
+/// What became of an update or removal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    /// There is no live user with that id.
+    Missing,
+    /// The user is at another version than expected; this is the one it is at.
+    Conflict(u64),
+}
+
+impl Outcome {
+    /// Checks `current` against what the caller expected.
+    fn check(current: Option<&UserData>, expected: Option<u64>) -> Outcome {
+        match (current, expected) {
+            (None, _) => Outcome::Missing,
+            (Some(user), Some(expected)) if user.version != expected => Outcome::Conflict(user.version),
+            (Some(_), _) => Outcome::Done,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    /// The log could not be replayed. `line` counts from 1.
+    Corrupt { line: usize, reason: String },
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "store I/O error: {}", e),
+            StoreError::Corrupt { line, reason } => {
+                write!(f, "log is corrupt at line {}: {}", line, reason)
+            }
+        }
+    }
+}
+
+impl Error for StoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StoreError::Io(e) => Some(e),
+            StoreError::Corrupt { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+/// Replays the compacting log at `path`, if there is one, handing each
+/// entry to `apply`, then rewrites it as `compacted` of the result and
+/// opens it for appending. The API keys and webhooks keep their state in
+/// logs like this beside the user log.
+///
+/// As with the user log, a final line without a newline is dropped and any
+/// other bad line is an error. The rewrite goes through a temporary file,
+/// so a crash leaves the old log or the new one, never half of either.
+pub(crate) fn open_compacting<E, S>(
+    path: &Path,
+    state: &mut S,
+    apply: impl Fn(&mut S, E) -> Result<(), String>,
+    compacted: impl Fn(&S) -> Vec<E>,
+) -> Result<File, StoreError>
+where
+    E: Serialize + for<'de> Deserialize<'de>,
+{
+    match File::open(path) {
+        Ok(log) => {
+            let mut reader = BufReader::new(log);
+            let mut line = String::new();
+            for number in 1.. {
+                line.clear();
+                let read = reader.read_line(&mut line)?;
+                if read == 0 || !line.ends_with('\n') {
+                    break;
+                }
+                let corrupt = |reason: String| StoreError::Corrupt { line: number, reason };
+                let entry = serde_json::from_str(&line).map_err(|e| corrupt(e.to_string()))?;
+                apply(state, entry).map_err(corrupt)?;
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut temporary = OsString::from(path);
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let mut rewritten = File::create(&temporary)?;
+    append_entries(&mut rewritten, &compacted(state), false)?;
+    rewritten.sync_all()?;
+    fs::rename(&temporary, path)?;
+
+    Ok(OpenOptions::new().append(true).open(path)?)
+}
+
+/// Appends `entries`, each as a line of JSON, to a log and syncs it if
+/// `sync` is set.
+///
+/// If the write or the sync fails the log is cut back to where it was, so
+/// the caller can leave the entries unapplied: a line torn by, say, a full
+/// disk is not followed by good ones that make it unreadable on restart,
+/// and an entry that never got synced is not replayed either.
+pub(crate) fn append_entries<E: Serialize>(log: &mut File, entries: &[E], sync: bool) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut bytes, entry).expect("log entries always serialize");
+        bytes.push(b'\n');
+    }
+    let length = log.metadata()?.len();
+    let written = log
+        .write_all(&bytes)
+        .and_then(|()| if sync { log.sync_data() } else { Ok(()) });
+    if let Err(e) = written {
+        // The error worth reporting is the first one.
+        let _ = log.set_len(length);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Which store to use.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreConfig {
+    Memory,
+    File(PathBuf),
+}
+
+impl StoreConfig {
+    /// Reads `USER_STORE` (`memory`, the default, or `file`) and, for the
+    /// file store, `USER_STORE_PATH` (default [`DEFAULT_LOG_PATH`]).
+    pub fn from_env() -> Result<StoreConfig, String> {
+        match env::var("USER_STORE").as_deref() {
+            Err(_) | Ok("memory") => Ok(StoreConfig::Memory),
+            Ok("file") => {
+                let path = env::var_os("USER_STORE_PATH")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from(DEFAULT_LOG_PATH));
+                Ok(StoreConfig::File(path))
+            }
+            Ok(other) => Err(format!(
+                "unknown USER_STORE {:?}, expected \"memory\" or \"file\"",
+                other
+            )),
+        }
+    }
+
+    pub fn open(&self) -> Result<Arc<dyn UserStore>, StoreError> {
+        Ok(match self {
+            StoreConfig::Memory => Arc::new(MemoryStore::new()),
+            StoreConfig::File(path) => Arc::new(FileStore::open(path)?),
+        })
+    }
+
+    /// The API keys kept alongside the users: in memory, or for the file
+    /// store in a second log beside the first, `users.keys.ndjson` for
+    /// `users.ndjson`.
+    pub fn open_keys(&self, defaults: Limits) -> Result<ApiKeys, StoreError> {
+        Ok(match self {
+            StoreConfig::Memory => ApiKeys::in_memory(defaults),
+            StoreConfig::File(path) => ApiKeys::open(path.with_extension("keys.ndjson"), defaults)?,
+        })
+    }
+
+    /// The webhooks and their deliveries for `users`, which this opened:
+    /// in memory, or for the file store in `users.webhooks.ndjson`.
+    pub fn open_webhooks(&self, users: Arc<dyn UserStore>, retry: Retry) -> Result<Webhooks, StoreError> {
+        Ok(match self {
+            StoreConfig::Memory => Webhooks::new(users, retry),
+            StoreConfig::File(path) => Webhooks::open(users, path.with_extension("webhooks.ndjson"), retry)?,
+        })
+    }
+}
+
+/// The users of either store. Ids come from a counter that only goes up,
+/// so an id below it that is not live belonged to a deleted user.
+#[derive(Default)]
+struct Users {
+    live: BTreeMap<UserId, UserData>,
+    next_id: UserId,
+    /// Every change so far; the one numbered `seq` is at `seq - 1`.
+    changes: Vec<ChangeRecord>,
+}
+
+impl Users {
+    fn deleted(&self, id: UserId) -> bool {
+        id < self.next_id && !self.live.contains_key(&id)
+    }
+
+    /// The first change in `changes` that cannot be made, if any.
+    fn refusal(&self, changes: &[Change]) -> Option<Batch> {
+        changes.iter().enumerate().find_map(|(index, change)| match change {
+            Change::Insert(_) => None,
+            Change::Update { id, expected, .. } => match Outcome::check(self.live.get(id), Some(*expected)) {
+                Outcome::Done => None,
+                outcome => Some(Batch::Refused { index, outcome }),
+            },
+        })
+    }
+
+    /// The ids `changes` will write and the entry that writes them. The
+    /// changes must have passed [`Users::refusal`].
+    fn batch(&self, changes: Vec<Change>) -> (Vec<UserId>, Entry) {
+        let mut next_id = self.next_id;
+        let (ids, entries) = changes
+            .into_iter()
+            .map(|change| match change {
+                Change::Insert(user) => {
+                    let id = next_id;
+                    next_id += 1;
+                    (id, Entry::Insert { id, user })
+                }
+                Change::Update { id, user, .. } => (id, Entry::Update { id, user }),
+            })
+            .unzip();
+        (ids, Entry::Batch { entries })
+    }
+
+    fn snapshot(&self) -> Vec<(UserId, UserData)> {
+        self.live
+            .iter()
+            .map(|(id, user)| (*id, user.clone()))
+            .collect()
+    }
+
+    fn page(&self, after: Option<UserId>, limit: usize) -> Vec<(UserId, UserData)> {
+        let start = match after {
+            Some(id) => Bound::Excluded(id),
+            None => Bound::Unbounded,
+        };
+        self.live
+            .range((start, Bound::Unbounded))
+            .take(limit)
+            .map(|(id, user)| (*id, user.clone()))
+            .collect()
+    }
+
+    fn history(&self, id: UserId) -> Vec<ChangeRecord> {
+        self.changes.iter().filter(|change| change.id == id).cloned().collect()
+    }
+
+    fn changes(&self, since: u64, limit: usize) -> Vec<ChangeRecord> {
+        let start = (since as usize).min(self.changes.len());
+        self.changes[start..].iter().take(limit).cloned().collect()
+    }
+
+    /// Makes the change `entry` describes and records it under `stamp`.
+    fn apply(&mut self, entry: Entry, stamp: &Stamp) -> Result<(), String> {
+        let (id, kind, before, after) = match entry {
+            Entry::Insert { id, user } => {
+                // Deleting the newest user leaves its insert in the log, so the
+                // counter is rebuilt from inserts alone.
+                if id < self.next_id {
+                    return Err(format!("insert of id {} after id {} was handed out", id, self.next_id - 1));
+                }
+                self.next_id = id + 1;
+                self.live.insert(id, user.clone());
+                (id, ChangeKind::Created, None, Some(user))
+            }
+            Entry::Update { id, user } => match self.live.get_mut(&id) {
+                Some(existing) => {
+                    let before = std::mem::replace(existing, user.clone());
+                    (id, ChangeKind::Updated, Some(before), Some(user))
+                }
+                None => return Err(format!("update of missing id {}", id)),
+            },
+            Entry::Delete { id } => match self.live.remove(&id) {
+                Some(before) => (id, ChangeKind::Deleted, Some(before), None),
+                None => return Err(format!("delete of missing id {}", id)),
+            },
+            Entry::Batch { entries } => {
+                for entry in entries {
+                    self.apply(entry, stamp)?;
+                }
+                return Ok(());
+            }
+        };
+        self.changes.push(ChangeRecord {
+            seq: self.changes.len() as u64 + 1,
+            id,
+            kind,
+            at: stamp.at,
+            actor: stamp.actor.clone(),
+            before,
+            after,
+        });
+        Ok(())
+    }
+}
+
+/// A change to the users. The file store logs these; the memory store
+/// only applies them.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Entry {
+    Insert { id: UserId, user: UserData },
+    Update { id: UserId, user: UserData },
+    Delete { id: UserId },
+    /// Entries written together. Being one line, a crash while appending
+    /// it loses all of them or none.
+    Batch { entries: Vec<Entry> },
+}
+
+/// Who made a change, and when.
+#[derive(Serialize, Deserialize)]
+struct Stamp {
+    at: DateTime<Utc>,
+    actor: String,
+}
+
+impl Stamp {
+    fn now(actor: &str) -> Stamp {
+        Stamp {
+            at: Utc::now(),
+            actor: actor.to_string(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    users: Mutex<Users>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn commit(users: &mut Users, entry: Entry, actor: &str) {
+        users
+            .apply(entry, &Stamp::now(actor))
+            .expect("entry was checked against the users");
+    }
+}
+
+impl UserStore for MemoryStore {
+    fn insert(&self, user: UserData, actor: &str) -> Result<UserId, StoreError> {
+        let mut users = self.users.lock().unwrap();
+        let id = users.next_id;
+        MemoryStore::commit(&mut users, Entry::Insert { id, user }, actor);
+        Ok(id)
+    }
+
+    fn get(&self, id: UserId) -> Result<Option<UserData>, StoreError> {
+        Ok(self.users.lock().unwrap().live.get(&id).cloned())
+    }
+
+    fn deleted(&self, id: UserId) -> Result<bool, StoreError> {
+        Ok(self.users.lock().unwrap().deleted(id))
+    }
+
+    fn list(&self) -> Result<Vec<(UserId, UserData)>, StoreError> {
+        Ok(self.users.lock().unwrap().snapshot())
+    }
+
+    fn list_after(&self, after: Option<UserId>, limit: usize) -> Result<Vec<(UserId, UserData)>, StoreError> {
+        Ok(self.users.lock().unwrap().page(after, limit))
+    }
+
+    fn update(&self, id: UserId, expected: u64, user: UserData, actor: &str) -> Result<Outcome, StoreError> {
+        let mut users = self.users.lock().unwrap();
+        let outcome = Outcome::check(users.live.get(&id), Some(expected));
+        if outcome == Outcome::Done {
+            MemoryStore::commit(&mut users, Entry::Update { id, user }, actor);
+        }
+        Ok(outcome)
+    }
+
+    fn remove(&self, id: UserId, expected: Option<u64>, actor: &str) -> Result<Outcome, StoreError> {
+        let mut users = self.users.lock().unwrap();
+        let outcome = Outcome::check(users.live.get(&id), expected);
+        if outcome == Outcome::Done {
+            MemoryStore::commit(&mut users, Entry::Delete { id }, actor);
+        }
+        Ok(outcome)
+    }
+
+    fn apply_all(&self, changes: Vec<Change>, actor: &str) -> Result<Batch, StoreError> {
+        let mut users = self.users.lock().unwrap();
+        if let Some(refused) = users.refusal(&changes) {
+            return Ok(refused);
+        }
+        let (ids, entry) = users.batch(changes);
+        MemoryStore::commit(&mut users, entry, actor);
+        Ok(Batch::Done(ids))
+    }
+
+    fn history(&self, id: UserId) -> Result<Vec<ChangeRecord>, StoreError> {
+        Ok(self.users.lock().unwrap().history(id))
+    }
+
+    fn changes(&self, since: u64, limit: usize) -> Result<Vec<ChangeRecord>, StoreError> {
+        Ok(self.users.lock().unwrap().changes(since, limit))
+    }
+}
+
+/// One line of the log: an entry and its stamp, side by side.
+#[derive(Serialize, Deserialize)]
+struct Line {
+    #[serde(flatten)]
+    stamp: Stamp,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+/// Keeps users in memory and appends every change to a newline-delimited
+/// JSON log before applying it.
+pub struct FileStore {
+    inner: Mutex<FileInner>,
+}
+
+struct FileInner {
+    users: Users,
+    log: File,
+}
+
+impl FileStore {
+    /// Opens the log at `path`, creating it if needed, and replays it.
+    ///
+    /// A final line without a newline is what a crash in the middle of an
+    /// append leaves behind; it is dropped. Any other bad line is an error.
+    pub fn open(path: impl AsRef<Path>) -> Result<FileStore, StoreError> {
+        let log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut users = Users::default();
+        let mut complete_len = 0;
+        let mut reader = BufReader::new(&log);
+        let mut line = String::new();
+        for number in 1.. {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            let corrupt = |reason: String| StoreError::Corrupt { line: number, reason };
+            let Line { stamp, entry } = serde_json::from_str(&line).map_err(|e| corrupt(e.to_string()))?;
+            users.apply(entry, &stamp).map_err(corrupt)?;
+            complete_len += read as u64;
+        }
+        log.set_len(complete_len)?;
+
+        Ok(FileStore {
+            inner: Mutex::new(FileInner { users, log }),
+        })
+    }
+}
+
+impl FileInner {
+    fn commit(&mut self, entry: Entry, actor: &str) -> Result<(), StoreError> {
+        let line = Line {
+            stamp: Stamp::now(actor),
+            entry,
+        };
+        append_entries(&mut self.log, std::slice::from_ref(&line), true)?;
+        self.users
+            .apply(line.entry, &line.stamp)
+            .expect("entry was checked against the users");
+        Ok(())
+    }
+}
+
+impl UserStore for FileStore {
+    fn insert(&self, user: UserData, actor: &str) -> Result<UserId, StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.users.next_id;
+        inner.commit(Entry::Insert { id, user }, actor)?;
+        Ok(id)
+    }
+
+    fn get(&self, id: UserId) -> Result<Option<UserData>, StoreError> {
+        Ok(self.inner.lock().unwrap().users.live.get(&id).cloned())
+    }
+
+    fn deleted(&self, id: UserId) -> Result<bool, StoreError> {
+        Ok(self.inner.lock().unwrap().users.deleted(id))
+    }
+
+    fn list(&self) -> Result<Vec<(UserId, UserData)>, StoreError> {
+        Ok(self.inner.lock().unwrap().users.snapshot())
+    }
+
+    fn list_after(&self, after: Option<UserId>, limit: usize) -> Result<Vec<(UserId, UserData)>, StoreError> {
+        Ok(self.inner.lock().unwrap().users.page(after, limit))
+    }
+
+    fn update(&self, id: UserId, expected: u64, user: UserData, actor: &str) -> Result<Outcome, StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        let outcome = Outcome::check(inner.users.live.get(&id), Some(expected));
+        if outcome == Outcome::Done {
+            inner.commit(Entry::Update { id, user }, actor)?;
+        }
+        Ok(outcome)
+    }
+
+    fn remove(&self, id: UserId, expected: Option<u64>, actor: &str) -> Result<Outcome, StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        let outcome = Outcome::check(inner.users.live.get(&id), expected);
+        if outcome == Outcome::Done {
+            inner.commit(Entry::Delete { id }, actor)?;
+        }
+        Ok(outcome)
+    }
+
+    fn apply_all(&self, changes: Vec<Change>, actor: &str) -> Result<Batch, StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(refused) = inner.users.refusal(&changes) {
+            return Ok(refused);
+        }
+        let (ids, entry) = inner.users.batch(changes);
+        inner.commit(entry, actor)?;
+        Ok(Batch::Done(ids))
+    }
+
+    fn history(&self, id: UserId) -> Result<Vec<ChangeRecord>, StoreError> {
+        Ok(self.inner.lock().unwrap().users.history(id))
+    }
+
+    fn changes(&self, since: u64, limit: usize) -> Result<Vec<ChangeRecord>, StoreError> {
+        Ok(self.inner.lock().unwrap().users.changes(since, limit))
+    }
+}
@@ -0,0 +1,131 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::error::Category;
+use serde_json::Value;
+
+pub type UserId = u64;
+
+/// Parses an id as it appears in a path: plain decimal digits with no sign
+/// and no leading zeros, so every id has exactly one spelling.
+pub fn parse_id(s: &str) -> Option<UserId> {
+    let canonical = s.bytes().all(|b| b.is_ascii_digit()) && (s == "0" || !s.starts_with('0'));
+    if !canonical {
+        return None;
+    }
+    s.parse().ok()
+}
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_EMAIL_LEN: usize = 254;
+
+/// The fields a client sets with POST and PUT, and changes with PATCH.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UserInput {
+    /// Leading and trailing whitespace is dropped.
+    #[schemars(length(min = 1, max = MAX_NAME_LEN))]
+    pub name: String,
+    #[schemars(email, length(max = MAX_EMAIL_LEN))]
+    pub email: String,
+}
+
+impl UserInput {
+    /// Parses and checks a JSON request body. The error says what was wrong
+    /// in words meant for the client.
+    pub fn from_json(body: &[u8]) -> Result<UserInput, String> {
+        let input: UserInput = serde_json::from_slice(body).map_err(|e| match e.classify() {
+            // Missing, unknown or mistyped fields; serde's message names them.
+            Category::Data => e.to_string(),
+            _ => format!("request body is not valid JSON: {}", e),
+        })?;
+        input.validate()?;
+        Ok(input)
+    }
+
+    /// Like [`UserInput::from_json`], for a body that has already been
+    /// parsed and patched.
+    pub fn from_value(value: Value) -> Result<UserInput, String> {
+        let input: UserInput = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        input.validate()?;
+        Ok(input)
+    }
+
+    /// Checks the fields the way [`UserInput::from_json`] does.
+    pub fn validate(&self) -> Result<(), String> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            return Err("`name` must not be empty".to_string());
+        }
+        if name.chars().count() > MAX_NAME_LEN {
+            return Err(format!("`name` must be at most {} characters", MAX_NAME_LEN));
+        }
+
+        if self.email.len() > MAX_EMAIL_LEN {
+            return Err(format!("`email` must be at most {} bytes", MAX_EMAIL_LEN));
+        }
+        let valid_email = match self.email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.is_empty()
+                    && !domain.contains('@')
+                    && !self.email.chars().any(char::is_whitespace)
+            }
+            None => false,
+        };
+        if !valid_email {
+            return Err(format!("`email` {:?} is not an email address", self.email));
+        }
+        Ok(())
+    }
+}
+
+/// A stored user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct UserData {
+    pub name: String,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Starts at 1 and goes up with every change.
+    #[schemars(range(min = 1))]
+    pub version: u64,
+}
+
+impl UserData {
+    pub fn new(input: UserInput, now: DateTime<Utc>) -> UserData {
+        UserData {
+            name: input.name.trim().to_string(),
+            email: input.email,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+        }
+    }
+
+    /// The fields the client controls, as it would send them.
+    pub fn input(&self) -> UserInput {
+        UserInput {
+            name: self.name.clone(),
+            email: self.email.clone(),
+        }
+    }
+
+    /// Replaces everything the client controls, keeping when the user was
+    /// created and moving to the next version.
+    pub fn replaced(&self, input: UserInput, now: DateTime<Utc>) -> UserData {
+        UserData {
+            created_at: self.created_at,
+            version: self.version + 1,
+            ..UserData::new(input, now)
+        }
+    }
+}
+
+/// How a user looks in responses.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct User<'a> {
+    pub id: UserId,
+    #[serde(flatten)]
+    pub data: &'a UserData,
+}
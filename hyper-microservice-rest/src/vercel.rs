@@ -0,0 +1,22 @@
+//! Serving the [`Router`] from the Vercel runtime, which hands over each
+//! request with its body already read.
+
+use vercel_runtime::{Body, Request, Response};
+
+use crate::router::Router;
+
+/// Handles one Vercel request. Bodies that are valid UTF-8 go back as text
+/// and anything else as binary; an empty body stays empty.
+pub fn handle(router: &Router, req: Request) -> Response<Body> {
+    let response = router.handle(req.map(|body| body.to_vec()));
+    response.map(|body| {
+        if body.is_empty() {
+            Body::Empty
+        } else {
+            match String::from_utf8(body) {
+                Ok(text) => Body::Text(text),
+                Err(e) => Body::Binary(e.into_bytes()),
+            }
+        }
+    })
+}
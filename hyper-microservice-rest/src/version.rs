@@ -0,0 +1,354 @@
+//! Versions of the user representation, and the conversions between them.
+//!
+//! Version 1 is what the router speaks. Version 2 calls `name`
+//! `display_name`, in bodies, patch documents and list queries alike.
+//! Clients pick a version with a path prefix, `/v2/user/1`, or with
+//! `Accept: application/vnd.users+json; version=2` on the plain path.
+//! Requests that pick neither get version 1.
+//!
+//! A version 2 request is rewritten into its version 1 form before it is
+//! routed, and the response is rewritten back, so the router needs no
+//! knowledge of versions. Version 1 responses carry `Deprecation` and
+//! `Sunset` headers.
+
+use http::header::{HeaderName, HeaderValue, ACCEPT, CONTENT_TYPE, LINK, LOCATION, VARY};
+use http::uri::Uri;
+use http::{Method, Request, Response};
+use serde_json::Value;
+
+use crate::error::ApiError;
+use crate::patch;
+
+/// The vendor media type whose `version` parameter picks a version.
+pub const MEDIA_TYPE: &str = "application/vnd.users+json";
+
+/// What a versioned `Accept` header may ask for.
+pub const OFFERED: &[&str] = &[
+    "application/vnd.users+json; version=1",
+    "application/vnd.users+json; version=2",
+    "application/json",
+];
+
+/// When version 1 was deprecated (2026-10-01), as an RFC 9745 date.
+pub const V1_DEPRECATION: &str = "@1790812800";
+/// When version 1 goes away, as an RFC 8594 HTTP date.
+pub const V1_SUNSET: &str = "Thu, 01 Apr 2027 00:00:00 GMT";
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// Paths the versions apply to, in their unversioned form.
+const USER_PATH: &str = "/user/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::V2];
+
+    pub fn number(self) -> u32 {
+        match self {
+            ApiVersion::V1 => 1,
+            ApiVersion::V2 => 2,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<ApiVersion> {
+        ApiVersion::ALL.iter().copied().find(|v| v.number().to_string() == s)
+    }
+}
+
+/// A version and how the client picked it, which decides how links and
+/// the content type are written back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// From a `/vN` path prefix.
+    Path(ApiVersion),
+    /// From the vendor media type in `Accept`.
+    Accept(ApiVersion),
+    /// Neither: version 1.
+    Default,
+    /// Not a user path, so versions do not apply.
+    Unversioned,
+}
+
+impl Selection {
+    pub fn version(self) -> ApiVersion {
+        match self {
+            Selection::Path(version) | Selection::Accept(version) => version,
+            Selection::Default | Selection::Unversioned => ApiVersion::V1,
+        }
+    }
+}
+
+/// Works out which version `req` asks for and rewrites it into the
+/// version 1 request the router serves.
+pub fn negotiate(req: Request<Vec<u8>>) -> Result<(Selection, Request<Vec<u8>>), ApiError> {
+    let path = req.uri().path();
+    let selection = match split_prefix(path) {
+        Some((version, rest)) if rest.starts_with(USER_PATH) => match ApiVersion::parse(version) {
+            Some(version) => Selection::Path(version),
+            None => return Err(ApiError::NotAcceptable(OFFERED)),
+        },
+        Some(_) => Selection::Unversioned,
+        None if path.starts_with(USER_PATH) => {
+            let accept = req.headers().get(ACCEPT).and_then(|v| v.to_str().ok());
+            from_accept(accept)?
+        }
+        None => Selection::Unversioned,
+    };
+
+    let (mut parts, body) = req.into_parts();
+    if let Selection::Path(_) = selection {
+        let path = split_prefix(parts.uri.path()).map_or("", |(_, rest)| rest).to_string();
+        parts.uri = with_path_and_query(&parts.uri, &path, parts.uri.query());
+    }
+    let mut req = Request::from_parts(parts, body);
+    if selection.version() == ApiVersion::V2 {
+        upgrade_request(&mut req)?;
+    }
+    Ok((selection, req))
+}
+
+/// Rewrites the version 1 response to `req` for the version the client
+/// asked for. `req` is the request as routed.
+pub fn present(selection: Selection, req: &Request<Vec<u8>>, mut response: Response<Vec<u8>>) -> Response<Vec<u8>> {
+    if selection == Selection::Unversioned {
+        return response;
+    }
+    let version = selection.version();
+    let headers = response.headers_mut();
+    if selection != Selection::Path(version) {
+        headers.append(VARY, HeaderValue::from_static("Accept"));
+    }
+    if version == ApiVersion::V1 {
+        headers.insert(DEPRECATION, HeaderValue::from_static(V1_DEPRECATION));
+        headers.insert(SUNSET, HeaderValue::from_static(V1_SUNSET));
+    }
+    for name in [LOCATION, LINK].iter() {
+        if let Some(value) = headers.get(name).and_then(|v| v.to_str().ok()) {
+            let value = rewrite_links(value, selection);
+            headers.insert(name, value.parse().expect("rewritten links are valid header values"));
+        }
+    }
+
+    let is_json = headers.get(CONTENT_TYPE).is_some_and(|v| v == "application/json");
+    if !is_json {
+        return response;
+    }
+    if let Selection::Accept(version) = selection {
+        let media_type = format!("{}; version={}", MEDIA_TYPE, version.number());
+        headers.insert(CONTENT_TYPE, media_type.parse().unwrap());
+    }
+    if version == ApiVersion::V1 {
+        return response;
+    }
+
+    let mut body: Value = match serde_json::from_slice(response.body()) {
+        Ok(body) => body,
+        Err(_) => return response,
+    };
+    let path = req.uri().path();
+    if path == USER_PATH && req.method() == Method::GET {
+        for user in body["users"].as_array_mut().into_iter().flatten() {
+            rename(user, "name", "display_name");
+        }
+        for link in ["next", "prev"].iter() {
+            if let Some(url) = body[*link].as_str() {
+                body[*link] = Value::String(rewrite_links(url, selection));
+            }
+        }
+    } else if path.ends_with("/history") {
+        for change in body.as_array_mut().into_iter().flatten().filter_map(Value::as_object_mut) {
+            for state in ["before", "after"].iter() {
+                if let Some(user) = change.get_mut(*state) {
+                    rename(user, "name", "display_name");
+                }
+            }
+        }
+    } else {
+        rename(&mut body, "name", "display_name");
+    }
+    *response.body_mut() = serde_json::to_vec(&body).expect("JSON values always serialize");
+    response
+}
+
+/// Splits `/vN/rest` into `N` and `/rest`.
+fn split_prefix(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix("/v")?;
+    let end = rest.find('/')?;
+    let (version, rest) = rest.split_at(end);
+    if version.is_empty() || !version.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((version, rest))
+}
+
+/// The version the `Accept` header asks for. Without the vendor type in it,
+/// that is version 1; with only versions of it that do not exist, and no
+/// plain JSON to fall back on, it is 406.
+fn from_accept(accept: Option<&str>) -> Result<Selection, ApiError> {
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return Ok(Selection::Default),
+    };
+    let mut asked = false;
+    let mut fallback = false;
+    let mut best: Option<(ApiVersion, f32)> = None;
+    for element in accept.split(',') {
+        let mut parts = element.split(';').map(str::trim);
+        let range = parts.next().unwrap_or("");
+        let params: Vec<(&str, &str)> = parts.filter_map(|p| p.split_once('=')).map(|(n, v)| (n.trim(), v.trim())).collect();
+        let param = |name: &str| params.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| *v);
+        let q: f32 = param("q").and_then(|q| q.parse().ok()).unwrap_or(1.0);
+        if q <= 0.0 {
+            continue;
+        }
+        if range.eq_ignore_ascii_case(MEDIA_TYPE) {
+            asked = true;
+            let version = match param("version") {
+                Some(version) => ApiVersion::parse(version.trim_matches('"')),
+                None => Some(ApiVersion::V1),
+            };
+            if let Some(version) = version {
+                if best.is_none_or(|(_, best_q)| q > best_q) {
+                    best = Some((version, q));
+                }
+            }
+        } else if ["application/json", "application/*", "*/*"].iter().any(|r| range.eq_ignore_ascii_case(r)) {
+            fallback = true;
+        }
+    }
+    match best {
+        Some((version, _)) => Ok(Selection::Accept(version)),
+        None if asked && !fallback => Err(ApiError::NotAcceptable(OFFERED)),
+        None => Ok(Selection::Default),
+    }
+}
+
+fn with_path_and_query(uri: &Uri, path: &str, query: Option<&str>) -> Uri {
+    let path_and_query = match query {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().expect("a valid path stays valid"));
+    Uri::from_parts(parts).expect("only the path changed")
+}
+
+/// Turns a version 2 request into version 1: the query, then the body.
+fn upgrade_request(req: &mut Request<Vec<u8>>) -> Result<(), ApiError> {
+    let stale = |what: &str| format!("version 2 has no {} `name`, it is `display_name`", what);
+
+    if let Some(query) = req.uri().query() {
+        if query_uses(query, "name") {
+            return Err(ApiError::InvalidQuery(stale("query parameter or sort field")));
+        }
+        let query = rename_in_query(query, "display_name", "name");
+        let path = req.uri().path().to_string();
+        *req.uri_mut() = with_path_and_query(req.uri(), &path, Some(&query));
+    }
+
+    let mut body: Value = match serde_json::from_slice(req.body()) {
+        Ok(body) => body,
+        // Left for the router to reject.
+        Err(_) => return Ok(()),
+    };
+    let content_type = req.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("");
+    if content_type.starts_with(patch::JSON_PATCH_JSON) {
+        for operation in body.as_array_mut().into_iter().flatten() {
+            for member in ["path", "from"].iter() {
+                if let Some(pointer) = operation[*member].as_str() {
+                    if pointer == "/name" || pointer.starts_with("/name/") {
+                        return Err(ApiError::InvalidBody(stale("member")));
+                    }
+                    if let Some(rest) = pointer.strip_prefix("/display_name") {
+                        operation[*member] = Value::String(format!("/name{}", rest));
+                    }
+                }
+            }
+        }
+    } else if body.is_object() {
+        if body.get("name").is_some() {
+            return Err(ApiError::InvalidBody(stale("field")));
+        }
+        rename(&mut body, "display_name", "name");
+    } else {
+        return Ok(());
+    }
+    *req.body_mut() = serde_json::to_vec(&body).expect("JSON values always serialize");
+    Ok(())
+}
+
+/// Moves the member `from` of an object to `to`. Anything else is left be.
+fn rename(value: &mut Value, from: &str, to: &str) {
+    if let Some(object) = value.as_object_mut() {
+        if let Some(member) = object.remove(from) {
+            object.insert(to.to_string(), member);
+        }
+    }
+}
+
+/// Whether `query` filters or sorts by `field`.
+fn query_uses(query: &str, field: &str) -> bool {
+    query.split('&').any(|pair| {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        name == field || (name == "sort" && value.trim_start_matches('-') == field)
+    })
+}
+
+/// Renames the filter `from` and sorting by it in a raw query string.
+fn rename_in_query(query: &str, from: &str, to: &str) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) if name == from => format!("{}={}", to, value),
+            Some(("sort", value)) if value.trim_start_matches('-') == from => {
+                let descending = if value.starts_with('-') { "-" } else { "" };
+                format!("sort={}{}", descending, to)
+            }
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Rewrites the URLs in a `Location` or `Link` value, or a bare URL, for
+/// the selection: version 2 names in queries, and the path prefix if the
+/// client used one.
+fn rewrite_links(value: &str, selection: Selection) -> String {
+    let version = selection.version();
+    let prefix = match selection {
+        Selection::Path(version) => format!("/v{}", version.number()),
+        _ => String::new(),
+    };
+    let rewrite = |url: &str| {
+        let url = match url.split_once('?') {
+            Some((path, query)) if version == ApiVersion::V2 => {
+                format!("{}?{}", path, rename_in_query(query, "name", "display_name"))
+            }
+            _ => url.to_string(),
+        };
+        if url.starts_with(USER_PATH) {
+            format!("{}{}", prefix, url)
+        } else {
+            url
+        }
+    };
+    if !value.contains('<') {
+        return rewrite(value);
+    }
+    value
+        .split(", ")
+        .map(|link| match (link.find('<'), link.find('>')) {
+            (Some(start), Some(end)) if start < end => {
+                format!("{}<{}>{}", &link[..start], rewrite(&link[start + 1..end]), &link[end + 1..])
+            }
+            _ => link.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
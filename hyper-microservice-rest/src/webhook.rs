@@ -0,0 +1,646 @@
+//! Outgoing webhooks: registered URLs are sent every change to a user.
+//!
+//! Deliveries come from the audit log in [`changes`](crate::changes), so
+//! every write reaches them however it was made, imports included. Each
+//! delivery is a JSON POST signed with the webhook's secret. Failed ones
+//! are retried with exponential backoff until [`Retry::max_attempts`] is
+//! reached, after which they are `dead`: the dead-letter queue is the dead
+//! deliveries, and any of them can be sent again by hand.
+//!
+//! Like API keys, webhooks and deliveries live in memory or, with the file
+//! backend, in a log next to the user log that is compacted on open.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use http::Uri;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::changes::{ChangeKind, ChangeRecord};
+use crate::store::{self, StoreError, UserStore};
+
+pub const WEBHOOKS_PATH: &str = "/webhooks";
+pub const DELIVERIES_PATH: &str = "/webhooks/deliveries";
+/// Makes the attempts that are due.
+pub const RUN_PATH: &str = "/webhooks/run";
+/// Does what [`RUN_PATH`] does for Vercel Cron, which can only send `GET`.
+pub const CRON_PATH: &str = "/webhooks/cron";
+
+/// `sha256=` and the hex HMAC-SHA256 of the body, keyed with the secret.
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+/// The event, like `user.created`.
+pub const EVENT_HEADER: &str = "x-webhook-event";
+/// The delivery id, the same for every attempt.
+pub const DELIVERY_HEADER: &str = "x-webhook-delivery";
+
+pub const MIN_SECRET_LEN: usize = 16;
+
+/// How many delivered deliveries are kept for inspection. Pending and dead
+/// ones are always kept.
+const KEEP_DELIVERED: usize = 1000;
+/// Changes read from the store at a time.
+const PULL_SIZE: usize = 100;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub type WebhookId = u64;
+pub type DeliveryId = u64;
+
+/// The body of `POST /webhooks`.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookInput {
+    /// Where to POST changes; `http` or `https`.
+    pub url: String,
+    /// Key for the signature. Never shown again.
+    #[schemars(length(min = MIN_SECRET_LEN))]
+    pub secret: String,
+    /// The kinds of change to send. All of them if empty or left out.
+    #[serde(default)]
+    pub events: Vec<ChangeKind>,
+}
+
+impl WebhookInput {
+    pub fn validate(&self) -> Result<(), String> {
+        let not_http = || format!("`{}` is not an http or https URL", self.url);
+        let uri: Uri = self.url.parse().map_err(|_| not_http())?;
+        if !matches!(uri.scheme_str(), Some("http" | "https")) || uri.host().is_none_or(str::is_empty) {
+            return Err(not_http());
+        }
+        if self.secret.chars().count() < MIN_SECRET_LEN {
+            return Err(format!("the secret must be at least {} characters", MIN_SECRET_LEN));
+        }
+        Ok(())
+    }
+}
+
+/// A registered webhook, as shown to clients.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Webhook {
+    pub id: WebhookId,
+    pub url: String,
+    pub events: Vec<ChangeKind>,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip)]
+    secret: String,
+}
+
+impl Webhook {
+    fn wants(&self, kind: ChangeKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    /// Not delivered yet; will be tried at `next_attempt_at`.
+    Pending,
+    Delivered,
+    /// Gave up after the last retry. Stays until sent again by hand.
+    Dead,
+}
+
+impl DeliveryStatus {
+    pub fn parse(s: &str) -> Option<DeliveryStatus> {
+        match s {
+            "pending" => Some(DeliveryStatus::Pending),
+            "delivered" => Some(DeliveryStatus::Delivered),
+            "dead" => Some(DeliveryStatus::Dead),
+            _ => None,
+        }
+    }
+}
+
+/// One change on its way to one webhook.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Delivery {
+    pub id: DeliveryId,
+    pub webhook: WebhookId,
+    /// The `seq` of the change being delivered.
+    pub seq: u64,
+    pub event: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    /// Why the last attempt failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// Set while an attempt is under way, so that no other run sends the
+    /// delivery too. It only lasts past the attempt if the process doing it
+    /// dies, and then the delivery is due again once this has passed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leased_until: Option<DateTime<Utc>>,
+    /// Sent as is on every attempt, so the signature never changes.
+    #[serde(skip)]
+    body: Arc<str>,
+}
+
+/// The response to a run.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Run {
+    /// How many deliveries were attempted.
+    pub attempts: usize,
+}
+
+/// When failed deliveries are tried again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// The wait after the first failure. Each later one doubles it.
+    pub first_delay: Duration,
+    /// No wait grows beyond this.
+    pub max_delay: Duration,
+    /// Attempts, the first included, before a delivery is dead.
+    pub max_attempts: u32,
+}
+
+impl Retry {
+    /// The wait before the attempt after `attempts` failed ones.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.first_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+impl Default for Retry {
+    /// Eight attempts over about two minutes.
+    fn default() -> Self {
+        Retry {
+            first_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(600),
+            max_attempts: 8,
+        }
+    }
+}
+
+/// The value of [`SIGNATURE_HEADER`] for `body`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+/// What [`Webhooks::redeliver`] did.
+#[derive(Debug, Clone)]
+pub enum Redelivery {
+    /// Queued to be sent on the next round.
+    Queued(Delivery),
+    Missing,
+    /// Delivered already, so it is not sent again.
+    Delivered,
+}
+
+/// The registered webhooks and what has been sent to them.
+pub struct Webhooks {
+    users: Arc<dyn UserStore>,
+    retry: Retry,
+    /// Shared by every attempt, so connections to a receiver are reused.
+    agent: ureq::Agent,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The last change turned into deliveries.
+    cursor: u64,
+    next_webhook: WebhookId,
+    next_delivery: DeliveryId,
+    hooks: BTreeMap<WebhookId, Webhook>,
+    deliveries: BTreeMap<DeliveryId, Delivery>,
+}
+
+/// One line of the webhook log.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Entry {
+    Register { webhook: Webhook, secret: String },
+    /// Also retires the id, even one that is not live, so it is never
+    /// given out again.
+    Remove { id: WebhookId },
+    /// A delivery as it now stands. A new one comes with its body; later
+    /// ones keep it.
+    Delivery {
+        delivery: Delivery,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        body: Option<String>,
+    },
+    /// Changes up to `seq` are deliveries now, numbered below `next_delivery`.
+    Cursor { seq: u64, next_delivery: DeliveryId },
+}
+
+impl State {
+    fn apply(&mut self, entry: Entry) -> Result<(), String> {
+        match entry {
+            Entry::Register { mut webhook, secret } => {
+                if webhook.id < self.next_webhook {
+                    return Err(format!("webhook {} was registered already", webhook.id));
+                }
+                self.next_webhook = webhook.id + 1;
+                webhook.secret = secret;
+                self.hooks.insert(webhook.id, webhook);
+            }
+            Entry::Remove { id } => {
+                self.deliveries.retain(|_, delivery| delivery.webhook != id);
+                self.hooks.remove(&id);
+                self.next_webhook = self.next_webhook.max(id + 1);
+            }
+            Entry::Delivery { mut delivery, body } => {
+                let existing = self.deliveries.get(&delivery.id);
+                delivery.body = match (body, existing) {
+                    (Some(_), Some(_)) => return Err(format!("delivery {} was queued already", delivery.id)),
+                    (Some(body), None) => body.into(),
+                    (None, Some(existing)) => Arc::clone(&existing.body),
+                    (None, None) => return Err(format!("there is no delivery {}", delivery.id)),
+                };
+                self.next_delivery = self.next_delivery.max(delivery.id + 1);
+                self.deliveries.insert(delivery.id, delivery);
+                self.forget_delivered();
+            }
+            Entry::Cursor { seq, next_delivery } => {
+                self.cursor = seq;
+                self.next_delivery = self.next_delivery.max(next_delivery);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the oldest delivered deliveries beyond [`KEEP_DELIVERED`].
+    fn forget_delivered(&mut self) {
+        let delivered: Vec<DeliveryId> = self
+            .deliveries
+            .values()
+            .filter(|d| d.status == DeliveryStatus::Delivered)
+            .map(|d| d.id)
+            .collect();
+        for id in delivered.iter().take(delivered.len().saturating_sub(KEEP_DELIVERED)) {
+            self.deliveries.remove(id);
+        }
+    }
+
+    /// The entries that rebuild this state: every webhook and delivery,
+    /// one to keep the next webhook id, and the cursor.
+    fn compacted(&self) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        for hook in self.hooks.values() {
+            entries.push(Entry::Register {
+                webhook: hook.clone(),
+                secret: hook.secret.clone(),
+            });
+        }
+        if self.next_webhook > 0 && !self.hooks.contains_key(&(self.next_webhook - 1)) {
+            entries.push(Entry::Remove { id: self.next_webhook - 1 });
+        }
+        for delivery in self.deliveries.values() {
+            entries.push(Entry::Delivery {
+                delivery: delivery.clone(),
+                body: Some(delivery.body.to_string()),
+            });
+        }
+        entries.push(Entry::Cursor {
+            seq: self.cursor,
+            next_delivery: self.next_delivery,
+        });
+        entries
+    }
+}
+
+struct Inner {
+    state: State,
+    /// Where entries are appended, for the file backend.
+    log: Option<File>,
+}
+
+impl Inner {
+    fn commit(&mut self, entries: Vec<Entry>) -> Result<(), StoreError> {
+        if let Some(log) = &mut self.log {
+            store::append_entries(log, &entries, true)?;
+        }
+        for entry in entries {
+            self.state.apply(entry).expect("entry was checked against the webhooks");
+        }
+        Ok(())
+    }
+}
+
+impl Webhooks {
+    /// Webhooks kept for as long as the process runs.
+    pub fn new(users: Arc<dyn UserStore>, retry: Retry) -> Webhooks {
+        Webhooks {
+            users,
+            retry,
+            agent: agent(),
+            inner: Mutex::new(Inner {
+                state: State::default(),
+                log: None,
+            }),
+        }
+    }
+
+    /// Webhooks kept in the log at `path`, which is created if needed,
+    /// replayed, and rewritten with only what is still needed. `users`
+    /// should be kept as long, or changes are sent twice or not at all.
+    pub fn open(users: Arc<dyn UserStore>, path: impl AsRef<Path>, retry: Retry) -> Result<Webhooks, StoreError> {
+        let mut state = State::default();
+        let log = store::open_compacting(path.as_ref(), &mut state, State::apply, State::compacted)?;
+        Ok(Webhooks {
+            users,
+            retry,
+            agent: agent(),
+            inner: Mutex::new(Inner { state, log: Some(log) }),
+        })
+    }
+
+    /// Adds a webhook, which is sent every change from now on.
+    pub fn register(&self, input: WebhookInput, now: DateTime<Utc>) -> Result<Webhook, StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        // Earlier changes go to the webhooks that existed when they were made.
+        self.pull(&mut inner, now)?;
+        let hook = Webhook {
+            id: inner.state.next_webhook,
+            url: input.url,
+            events: input.events,
+            created_at: now,
+            secret: String::new(),
+        };
+        inner.commit(vec![Entry::Register {
+            webhook: hook.clone(),
+            secret: input.secret,
+        }])?;
+        Ok(hook)
+    }
+
+    pub fn list(&self) -> Vec<Webhook> {
+        self.inner.lock().unwrap().state.hooks.values().cloned().collect()
+    }
+
+    /// Removes a webhook and drops its deliveries. False if there was none.
+    pub fn remove(&self, id: WebhookId) -> Result<bool, StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.state.hooks.contains_key(&id) {
+            return Ok(false);
+        }
+        inner.commit(vec![Entry::Remove { id }])?;
+        Ok(true)
+    }
+
+    /// Deliveries, oldest first, optionally only one webhook's or only those
+    /// in one status.
+    pub fn deliveries(&self, webhook: Option<WebhookId>, status: Option<DeliveryStatus>) -> Vec<Delivery> {
+        self.inner
+            .lock()
+            .unwrap()
+            .state
+            .deliveries
+            .values()
+            .filter(|d| webhook.is_none_or(|id| d.webhook == id) && status.is_none_or(|s| d.status == s))
+            .cloned()
+            .collect()
+    }
+
+    pub fn delivery(&self, id: DeliveryId) -> Option<Delivery> {
+        self.inner.lock().unwrap().state.deliveries.get(&id).cloned()
+    }
+
+    /// Queues a delivery that has not gone through to be tried again, with a
+    /// fresh set of retries.
+    pub fn redeliver(&self, id: DeliveryId, now: DateTime<Utc>) -> Result<Redelivery, StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut delivery = match inner.state.deliveries.get(&id) {
+            None => return Ok(Redelivery::Missing),
+            Some(delivery) if delivery.status == DeliveryStatus::Delivered => return Ok(Redelivery::Delivered),
+            Some(delivery) => delivery.clone(),
+        };
+        delivery.status = DeliveryStatus::Pending;
+        delivery.attempts = 0;
+        delivery.next_attempt_at = Some(now);
+        inner.commit(vec![Entry::Delivery {
+            delivery: delivery.clone(),
+            body: None,
+        }])?;
+        Ok(Redelivery::Queued(delivery))
+    }
+
+    /// Turns new changes into deliveries and makes every attempt that is due
+    /// at `now`. Returns how many attempts were made.
+    ///
+    /// Each webhook is sent its deliveries, in order, on a thread of its
+    /// own. Once one fails the rest of that webhook's wait for the next
+    /// round, so an unreachable receiver costs a round one send timeout at
+    /// most and the other webhooks nothing.
+    pub fn run(&self, now: DateTime<Utc>) -> Result<usize, StoreError> {
+        let mut batches: BTreeMap<WebhookId, Vec<(Delivery, String, String)>> = BTreeMap::new();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            self.pull(&mut inner, now)?;
+            let state = &inner.state;
+            let due = state.deliveries.values().filter(|d| {
+                d.status == DeliveryStatus::Pending
+                    && d.next_attempt_at.is_some_and(|at| at <= now)
+                    && d.leased_until.is_none_or(|until| until <= now)
+            });
+            for delivery in due {
+                if let Some(hook) = state.hooks.get(&delivery.webhook) {
+                    let batch = batches.entry(delivery.webhook).or_default();
+                    batch.push((delivery.clone(), hook.url.clone(), hook.secret.clone()));
+                }
+            }
+
+            // Claimed before the lock is let go, so that overlapping runs do
+            // not send them twice. A webhook's deliveries go one after the
+            // other, each within the send timeout, so the lease of the n-th
+            // is n timeouts and one to spare.
+            let mut claims = Vec::new();
+            for batch in batches.values_mut() {
+                for (place, (delivery, _, _)) in batch.iter_mut().enumerate() {
+                    let lease = TIMEOUT.saturating_mul(place as u32 + 2);
+                    delivery.leased_until = Some(now + chrono::Duration::from_std(lease).unwrap_or(chrono::Duration::MAX));
+                    claims.push(Entry::Delivery {
+                        delivery: delivery.clone(),
+                        body: None,
+                    });
+                }
+            }
+            if !claims.is_empty() {
+                inner.commit(claims)?;
+            }
+        }
+
+        // Attempts are timed from `now`, which tests set, by the clock.
+        let started = Instant::now();
+        let finished = &|| now + chrono::Duration::from_std(started.elapsed()).unwrap_or(chrono::Duration::MAX);
+        thread::scope(|scope| {
+            let senders: Vec<_> = batches
+                .values()
+                .map(|batch| {
+                    scope.spawn(move || {
+                        let mut attempts = 0;
+                        for (delivery, url, secret) in batch {
+                            let result = send(&self.agent, url, secret, delivery);
+                            let failed = result.is_err();
+                            attempts += 1;
+                            self.record(delivery.id, result, finished())?;
+                            if failed {
+                                break;
+                            }
+                        }
+                        self.release(batch[attempts..].iter().map(|(delivery, _, _)| delivery.id))?;
+                        Ok(attempts)
+                    })
+                })
+                .collect();
+            senders.into_iter().map(|sender| sender.join().unwrap()).sum()
+        })
+    }
+
+    /// Calls [`Webhooks::run`] every `interval` on a thread of its own, for
+    /// a long-running server. Under Vercel a function instance is frozen
+    /// between requests, so a cron job calls `/webhooks/cron` instead.
+    pub fn spawn(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        thread::spawn(move || loop {
+            if let Err(e) = self.run(Utc::now()) {
+                eprintln!("webhooks: {}", e);
+            }
+            thread::sleep(interval);
+        })
+    }
+
+    /// Reads changes past the cursor and queues one delivery per change and
+    /// interested webhook.
+    fn pull(&self, inner: &mut Inner, now: DateTime<Utc>) -> Result<(), StoreError> {
+        loop {
+            let changes = self.users.changes(inner.state.cursor, PULL_SIZE)?;
+            let last = match changes.last() {
+                Some(change) => change.seq,
+                None => return Ok(()),
+            };
+            let mut entries = Vec::new();
+            let mut next = inner.state.next_delivery;
+            for change in &changes {
+                for hook in inner.state.hooks.values().filter(|hook| hook.wants(change.kind)) {
+                    let delivery = queued(next, hook.id, change, now);
+                    let body = Some(delivery.body.to_string());
+                    entries.push(Entry::Delivery { delivery, body });
+                    next += 1;
+                }
+            }
+            entries.push(Entry::Cursor {
+                seq: last,
+                next_delivery: next,
+            });
+            inner.commit(entries)?;
+        }
+    }
+
+    /// Notes an attempt that ended at `now`; any retry waits from then.
+    fn record(&self, id: DeliveryId, result: Result<(), String>, now: DateTime<Utc>) -> Result<(), StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut delivery = match inner.state.deliveries.get(&id) {
+            Some(delivery) => delivery.clone(),
+            // The webhook was removed while the attempt was under way.
+            None => return Ok(()),
+        };
+        delivery.attempts += 1;
+        delivery.last_attempt_at = Some(now);
+        delivery.leased_until = None;
+        match result {
+            Ok(()) => {
+                delivery.status = DeliveryStatus::Delivered;
+                delivery.next_attempt_at = None;
+                delivery.last_error = None;
+                delivery.delivered_at = Some(now);
+            }
+            Err(e) => {
+                delivery.last_error = Some(e);
+                if delivery.attempts >= self.retry.max_attempts {
+                    delivery.status = DeliveryStatus::Dead;
+                    delivery.next_attempt_at = None;
+                } else {
+                    let delay = self.retry.delay(delivery.attempts);
+                    delivery.next_attempt_at = Some(now + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX));
+                }
+            }
+        }
+        inner.commit(vec![Entry::Delivery { delivery, body: None }])
+    }
+
+    /// Gives up the lease on deliveries that were claimed but not attempted,
+    /// so the next run can have them.
+    fn release(&self, ids: impl Iterator<Item = DeliveryId>) -> Result<(), StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut entries = Vec::new();
+        for id in ids {
+            if let Some(delivery) = inner.state.deliveries.get(&id) {
+                let mut delivery = delivery.clone();
+                delivery.leased_until = None;
+                entries.push(Entry::Delivery { delivery, body: None });
+            }
+        }
+        if entries.is_empty() {
+            return Ok(());
+        }
+        inner.commit(entries)
+    }
+}
+
+fn event(kind: ChangeKind) -> &'static str {
+    match kind {
+        ChangeKind::Created => "user.created",
+        ChangeKind::Updated => "user.updated",
+        ChangeKind::Deleted => "user.deleted",
+    }
+}
+
+fn queued(id: DeliveryId, webhook: WebhookId, change: &ChangeRecord, now: DateTime<Utc>) -> Delivery {
+    let event = event(change.kind);
+    let body = json!({ "event": event, "delivery": id, "webhook": webhook, "change": change });
+    Delivery {
+        id,
+        webhook,
+        seq: change.seq,
+        event: event.to_string(),
+        status: DeliveryStatus::Pending,
+        attempts: 0,
+        next_attempt_at: Some(now),
+        last_attempt_at: None,
+        last_error: None,
+        delivered_at: None,
+        leased_until: None,
+        body: body.to_string().into(),
+    }
+}
+
+/// The client deliveries are sent with. Redirects are not followed.
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(TIMEOUT).redirects(0).build()
+}
+
+/// POSTs the delivery. Anything but a 2xx response is a failure.
+fn send(agent: &ureq::Agent, url: &str, secret: &str, delivery: &Delivery) -> Result<(), String> {
+    let response = agent
+        .post(url)
+        .set("content-type", "application/json")
+        .set(SIGNATURE_HEADER, &sign(secret, delivery.body.as_bytes()))
+        .set(EVENT_HEADER, &delivery.event)
+        .set(DELIVERY_HEADER, &delivery.id.to_string())
+        .send_string(&delivery.body);
+    match response {
+        Ok(response) if (200..300).contains(&response.status()) => Ok(()),
+        Ok(response) => Err(format!("the receiver answered {}", response.status())),
+        Err(ureq::Error::Status(code, _)) => Err(format!("the receiver answered {}", code)),
+        Err(ureq::Error::Transport(e)) => Err(e.to_string()),
+    }
+}
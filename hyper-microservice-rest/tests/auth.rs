@@ -0,0 +1,148 @@
+use http::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use http::{Method, Request, Response, StatusCode};
+use hyper_microservice_rest::auth::{AuthError, Authenticator, Role};
+use hyper_microservice_rest::router::Router;
+use hyper_microservice_rest::store::MemoryStore;
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+const SECRET: &[u8] = b"test secret";
+const RSA_PRIVATE: &[u8] = include_bytes!("keys/rs256-private.pem");
+const RSA_PUBLIC: &[u8] = include_bytes!("keys/rs256-public.pem");
+
+fn in_an_hour() -> i64 {
+    chrono::Utc::now().timestamp() + 3600
+}
+
+fn hs256(claims: Value) -> String {
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+}
+
+fn rs256(claims: Value) -> String {
+    let key = EncodingKey::from_rsa_pem(RSA_PRIVATE).unwrap();
+    encode(&Header::new(jsonwebtoken::Algorithm::RS256), &claims, &key).unwrap()
+}
+
+fn token(role: &str) -> String {
+    hs256(json!({"sub": "tester", "exp": in_an_hour(), "roles": [role]}))
+}
+
+fn bearer(token: &str) -> Option<String> {
+    Some(format!("Bearer {}", token))
+}
+
+fn request(router: &Router, method: Method, uri: &str, authorization: Option<String>) -> Response<Vec<u8>> {
+    let mut builder = Request::builder().method(method.clone()).uri(uri);
+    if let Some(authorization) = authorization {
+        builder = builder.header(AUTHORIZATION, authorization);
+    }
+    let body = if method == Method::POST || method == Method::PUT {
+        builder = builder.header(CONTENT_TYPE, "application/json");
+        br#"{"name": "Ada", "email": "ada@example.com"}"#.to_vec()
+    } else {
+        Vec::new()
+    };
+    router.handle(builder.body(body).unwrap())
+}
+
+fn router() -> Router {
+    let auth = Authenticator::new().hs256(SECRET).rs256(RSA_PUBLIC).unwrap();
+    Router::new(Arc::new(MemoryStore::new())).with_auth(auth)
+}
+
+#[test]
+fn requests_without_a_valid_token_are_unauthorized() {
+    let router = router();
+    let expired = hs256(json!({"exp": chrono::Utc::now().timestamp() - 3600, "roles": ["admin"]}));
+    let forged = encode(
+        &Header::default(),
+        &json!({"exp": in_an_hour(), "roles": ["admin"]}),
+        &EncodingKey::from_secret(b"other secret"),
+    )
+    .unwrap();
+    let no_expiry = hs256(json!({"roles": ["admin"]}));
+    let cases = [
+        None,
+        Some("Basic dXNlcjpwYXNz".to_string()),
+        bearer("not.a.token"),
+        bearer(&expired),
+        bearer(&forged),
+        bearer(&no_expiry),
+    ];
+    for authorization in cases.iter() {
+        let response = request(&router, Method::GET, "/user/", authorization.clone());
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", authorization);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+    }
+}
+
+#[test]
+fn the_index_stays_public() {
+    assert_eq!(request(&router(), Method::GET, "/", None).status(), StatusCode::OK);
+}
+
+#[test]
+fn roles_decide_what_is_allowed() {
+    let router = router();
+    let cases = [
+        ("read-only", Method::GET, "/user/", StatusCode::OK),
+        ("read-only", Method::POST, "/user/", StatusCode::FORBIDDEN),
+        ("editor", Method::POST, "/user/", StatusCode::CREATED),
+        ("editor", Method::PUT, "/user/0", StatusCode::OK),
+        ("editor", Method::DELETE, "/user/0", StatusCode::FORBIDDEN),
+        ("admin", Method::DELETE, "/user/0", StatusCode::NO_CONTENT),
+        ("auditor", Method::GET, "/user/", StatusCode::FORBIDDEN),
+    ];
+    for (role, method, uri, status) in cases.iter() {
+        let response = request(&router, method.clone(), uri, bearer(&token(role)));
+        assert_eq!(response.status(), *status, "{} {} {}", role, method, uri);
+    }
+}
+
+#[test]
+fn verifies_rs256_tokens() {
+    let router = router();
+    let token = rs256(json!({"exp": in_an_hour(), "roles": ["read-only"]}));
+    assert_eq!(request(&router, Method::GET, "/user/", bearer(&token)).status(), StatusCode::OK);
+
+    // A token "signed" with the public key as an HMAC secret must not pass
+    // when only RS256 is configured.
+    let rs256_only = Router::new(Arc::new(MemoryStore::new()))
+        .with_auth(Authenticator::new().rs256(RSA_PUBLIC).unwrap());
+    let confused = encode(
+        &Header::default(),
+        &json!({"exp": in_an_hour(), "roles": ["admin"]}),
+        &EncodingKey::from_secret(RSA_PUBLIC),
+    )
+    .unwrap();
+    let response = request(&rs256_only, Method::GET, "/user/", bearer(&confused));
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn checks_issuer_and_audience_when_configured() {
+    let auth = Authenticator::new().hs256(SECRET).issuer("https://issuer.test").audience("users");
+    let claims = |iss: &str, aud: &str| json!({"exp": in_an_hour(), "iss": iss, "aud": aud, "roles": ["editor"]});
+    let header = |claims| bearer(&hs256(claims));
+
+    let principal = auth.authorize(header(claims("https://issuer.test", "users")).as_deref(), Role::Editor).unwrap();
+    assert_eq!(principal.role, Role::Editor);
+    assert!(matches!(
+        auth.authenticate(header(claims("https://evil.test", "users")).as_deref()),
+        Err(AuthError::Unauthenticated(_))
+    ));
+    assert!(matches!(
+        auth.authenticate(header(claims("https://issuer.test", "billing")).as_deref()),
+        Err(AuthError::Unauthenticated(_))
+    ));
+}
+
+#[test]
+fn the_highest_role_wins() {
+    let auth = Authenticator::new().hs256(SECRET);
+    let token = hs256(json!({"sub": "ada", "exp": in_an_hour(), "roles": ["unknown", "admin", "read-only"]}));
+    let principal = auth.authenticate(bearer(&token).as_deref()).unwrap();
+    assert_eq!(principal.subject.as_deref(), Some("ada"));
+    assert_eq!(principal.role, Role::Admin);
+}
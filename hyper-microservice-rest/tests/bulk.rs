@@ -0,0 +1,176 @@
+mod harness;
+
+use harness::{get, post, Harness};
+use http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use hyper_microservice_rest::accept;
+use hyper_microservice_rest::bulk;
+use hyper_microservice_rest::router::Router;
+use hyper_microservice_rest::store::{MemoryStore, UserStore};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+fn import(app: &Harness, uri: &str, format: &str, body: &str) -> (StatusCode, Value) {
+    let reply = app.send(post(uri).body(format, body));
+    (reply.status(), reply.json())
+}
+
+/// A harness holding Ada (id 0) and Grace (id 1).
+fn setup() -> (Harness, Arc<MemoryStore>) {
+    let store = Arc::new(MemoryStore::new());
+    let app = Harness::with_router(Router::new(store.clone()));
+    let rows = "{\"name\": \"Ada\", \"email\": \"ada@example.com\"}\n{\"name\": \"Grace\", \"email\": \"grace@example.com\"}\n";
+    let (status, report) = import(&app, bulk::IMPORT_PATH, bulk::NDJSON, rows);
+    assert_eq!(status, StatusCode::OK, "{}", report);
+    assert_eq!(report["created"], 2);
+    (app, store)
+}
+
+fn names(store: &MemoryStore) -> Vec<String> {
+    let mut users = store.list().unwrap();
+    users.sort_by_key(|(id, _)| *id);
+    users.into_iter().map(|(_, user)| user.name).collect()
+}
+
+#[test]
+fn negotiates_with_accept() {
+    let offered = &["application/x-ndjson", "text/csv"];
+    assert_eq!(accept::preferred(None, offered), Some("application/x-ndjson"));
+    assert_eq!(accept::preferred(Some("*/*"), offered), Some("application/x-ndjson"));
+    assert_eq!(accept::preferred(Some("text/*"), offered), Some("text/csv"));
+    assert_eq!(accept::preferred(Some("application/x-ndjson;q=0.5, text/csv"), offered), Some("text/csv"));
+    assert_eq!(accept::preferred(Some("*/*, application/x-ndjson;q=0"), offered), Some("text/csv"));
+    assert_eq!(accept::preferred(Some("application/json"), offered), None);
+}
+
+#[test]
+fn exports_in_the_accepted_format() {
+    let (app, _) = setup();
+
+    let ndjson = app.send(get(bulk::EXPORT_PATH));
+    ndjson.expect(StatusCode::OK).expect_header("content-type", bulk::NDJSON);
+    let lines: Vec<Value> = ndjson.text().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["name"], "Grace");
+    assert_eq!(lines[1]["id"], 1);
+
+    let csv = app.send(get(bulk::EXPORT_PATH).header("accept", "text/csv"));
+    csv.expect(StatusCode::OK).expect_header("content-type", bulk::CSV);
+    let mut lines = csv.text().lines();
+    assert_eq!(lines.next(), Some("id,name,email,created_at,updated_at,version"));
+    assert!(lines.next().unwrap().starts_with("0,Ada,ada@example.com,"));
+
+    let refused = app.send(get(bulk::EXPORT_PATH).header("accept", "application/json"));
+    refused.expect(StatusCode::NOT_ACCEPTABLE);
+}
+
+#[tokio::test]
+async fn exports_stream_a_row_per_frame() {
+    let (app, _) = setup();
+    let rows: String = (2..250).map(|n| format!("{{\"name\": \"user {}\", \"email\": \"user{}@example.com\"}}\n", n, n)).collect();
+    assert_eq!(import(&app, bulk::IMPORT_PATH, bulk::NDJSON, &rows).0, StatusCode::OK);
+
+    // The Vercel handler collects the body, so this goes to the router.
+    let router = app.router();
+    for (format, frames) in &[(bulk::NDJSON, 250), (bulk::CSV, 251)] {
+        let request = || Request::get(bulk::EXPORT_PATH).header("accept", *format).body(Vec::new()).unwrap();
+        let mut body = router.handle_streaming(request()).into_body();
+        let mut received = Vec::new();
+        while let Some(frame) = body.frame().await {
+            received.push(frame.unwrap().into_data().unwrap());
+        }
+        assert_eq!(received.len(), *frames, "{}", format);
+        assert_eq!(received.concat(), *router.handle(request()).body());
+    }
+}
+
+#[test]
+fn an_export_imports_back_unchanged() {
+    let (app, store) = setup();
+    let before = store.list().unwrap();
+    for format in bulk::FORMATS {
+        let export = app.send(get(bulk::EXPORT_PATH).header("accept", format));
+        let (status, report) = import(&app, bulk::IMPORT_PATH, format, export.text());
+        assert_eq!(status, StatusCode::OK, "{}", report);
+        assert_eq!(report["unchanged"], 2, "{}", report);
+    }
+    assert_eq!(store.list().unwrap(), before);
+}
+
+#[test]
+fn reports_each_row() {
+    let (app, store) = setup();
+    let csv = "id,name,email,version\n\
+               0,\"Lovelace, Ada\",ada@example.com,1\n\
+               ,Alan,alan@example.com,\n\
+               1,Grace,grace@example.com,1\n";
+    let (status, report) = import(&app, bulk::IMPORT_PATH, bulk::CSV, csv);
+    assert_eq!(status, StatusCode::OK, "{}", report);
+    assert_eq!(
+        report,
+        json!({
+            "atomic": true,
+            "created": 1,
+            "updated": 1,
+            "unchanged": 1,
+            "rejected": 0,
+            "rows": [
+                {"line": 2, "result": "updated", "id": 0},
+                {"line": 3, "result": "created", "id": 2},
+                {"line": 4, "result": "unchanged", "id": 1},
+            ],
+        })
+    );
+    assert_eq!(names(&store), ["Lovelace, Ada", "Grace", "Alan"]);
+}
+
+const MIXED: &str = r#"{"name": "Alan", "email": "alan@example.com"}
+{"id": 0, "version": 7, "name": "Ada L.", "email": "ada@example.com"}
+{"id": 9, "name": "Nobody", "email": "nobody@example.com"}
+{"name": "", "email": "empty@example.com"}
+{"id": 1, "name": "Grace H.", "email": "grace@example.com"}
+{"id": 1, "name": "Grace M.", "email": "grace@example.com"}
+{"name": "Bad", "email": "bad@example.com", "admin": true}
+not json
+"#;
+
+#[test]
+fn atomic_imports_apply_nothing_if_a_row_is_rejected() {
+    let (app, store) = setup();
+    let (status, report) = import(&app, bulk::IMPORT_PATH, bulk::NDJSON, MIXED);
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(report["created"], 0);
+    assert_eq!(report["rejected"], 6);
+    let results: Vec<&str> = report["rows"].as_array().unwrap().iter().map(|row| row["result"].as_str().unwrap()).collect();
+    assert_eq!(results, ["skipped", "rejected", "rejected", "rejected", "skipped", "rejected", "rejected", "rejected"]);
+    assert_eq!(report["rows"][1]["error"], "user 0 is at version 1, not 7");
+    assert_eq!(report["rows"][5]["error"], "user 1 is already on line 5");
+    assert_eq!(names(&store), ["Ada", "Grace"]);
+}
+
+#[test]
+fn non_atomic_imports_apply_the_good_rows() {
+    let (app, store) = setup();
+    let uri = format!("{}?atomic=false", bulk::IMPORT_PATH);
+    let (status, report) = import(&app, &uri, bulk::NDJSON, MIXED);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["atomic"], false);
+    assert_eq!((report["created"].as_u64(), report["updated"].as_u64()), (Some(1), Some(1)));
+    assert_eq!(report["rows"][2]["error"], "there is no user with id 9");
+    assert_eq!(report["rows"][7]["line"], 8);
+    assert_eq!(names(&store), ["Ada", "Grace H.", "Alan"]);
+}
+
+#[test]
+fn rejects_what_cannot_be_read_at_all() {
+    let (app, _) = setup();
+    let cases = [
+        (bulk::IMPORT_PATH, bulk::CSV, "id,nickname\n1,x\n", StatusCode::BAD_REQUEST),
+        (bulk::IMPORT_PATH, bulk::CSV, "id,name\n1,x\n", StatusCode::BAD_REQUEST),
+        (bulk::IMPORT_PATH, "application/json", "{}", StatusCode::UNSUPPORTED_MEDIA_TYPE),
+        ("/users/import?atomic=maybe", bulk::NDJSON, "", StatusCode::BAD_REQUEST),
+    ];
+    for (uri, format, body, status) in cases.iter() {
+        app.send(post(uri).body(format, body)).expect(*status);
+    }
+}
@@ -0,0 +1,92 @@
+mod harness;
+
+use harness::{delete, get, post, put, Harness};
+use http::StatusCode;
+use hyper_microservice_rest::auth::Authenticator;
+use hyper_microservice_rest::router::Router;
+use hyper_microservice_rest::store::MemoryStore;
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+const SECRET: &[u8] = b"changes-test-secret";
+
+/// Creates Ada, renames her and deletes her, then creates Grace.
+fn setup() -> Harness {
+    let app = Harness::new();
+    app.send(post("/user/").json(json!({ "name": "Ada", "email": "ada@example.com" }))).expect(StatusCode::CREATED);
+    app.send(put("/user/0").json(json!({ "name": "Ada L", "email": "ada@example.com" }))).expect(StatusCode::OK);
+    app.send(delete("/user/0")).expect(StatusCode::NO_CONTENT);
+    app.send(post("/user/").json(json!({ "name": "Grace", "email": "grace@example.com" }))).expect(StatusCode::CREATED);
+    app
+}
+
+#[test]
+fn history_outlives_the_user() {
+    let app = setup();
+    let history = app.send(get("/user/0/history")).expect(StatusCode::OK).json();
+    let kinds: Vec<&str> = history.as_array().unwrap().iter().map(|c| c["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["created", "updated", "deleted"]);
+
+    let (created, updated, deleted) = (&history[0], &history[1], &history[2]);
+    assert_eq!(created["before"], Value::Null);
+    assert_eq!(created["after"]["name"], "Ada");
+    assert_eq!(updated["before"]["name"], "Ada");
+    assert_eq!(updated["after"]["name"], "Ada L");
+    assert_eq!(updated["after"]["version"], 2);
+    assert_eq!(deleted["before"]["name"], "Ada L");
+    assert_eq!(deleted["after"], Value::Null);
+    assert!(history.as_array().unwrap().iter().all(|c| c["actor"] == "anonymous" && c["id"] == 0));
+
+    app.send(get("/user/7/history")).expect(StatusCode::NOT_FOUND);
+    app.send(get("/user/x/history")).expect(StatusCode::NOT_FOUND);
+    app.send(post("/user/1/history"))
+        .expect(StatusCode::METHOD_NOT_ALLOWED)
+        .expect_header("allow", "GET");
+}
+
+#[test]
+fn the_feed_pages_by_sequence_number() {
+    let app = setup();
+    let page = app.send(get("/changes?limit=3")).expect(StatusCode::OK).json();
+    let seqs: Vec<u64> = page["changes"].as_array().unwrap().iter().map(|c| c["seq"].as_u64().unwrap()).collect();
+    assert_eq!(seqs, [1, 2, 3]);
+    assert_eq!(page["next"], 3);
+
+    let page = app.send(get("/changes?since=3")).json();
+    assert_eq!(page["changes"].as_array().unwrap().len(), 1);
+    assert_eq!(page["changes"][0]["id"], 1);
+    assert_eq!(page["changes"][0]["kind"], "created");
+    assert_eq!(page["next"], 4);
+
+    // Nothing new: the cursor stays put, ready to poll again.
+    assert_eq!(app.send(get("/changes?since=4")).json(), json!({ "changes": [], "next": 4 }));
+    assert_eq!(app.send(get("/changes?since=100")).json(), json!({ "changes": [], "next": 100 }));
+
+    for bad in &["since=-1", "since=x", "limit=0", "limit=1001", "after=1"] {
+        app.send(get(&format!("/changes?{}", bad))).expect(StatusCode::BAD_REQUEST);
+    }
+    app.send(delete("/changes")).expect(StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[test]
+fn changes_are_attributed_to_the_token_subject() {
+    let auth = Authenticator::new().hs256(SECRET);
+    let app = Harness::with_router(Router::new(Arc::new(MemoryStore::new())).with_auth(auth));
+    let token = |claims: Value| {
+        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(SECRET)).unwrap();
+        format!("Bearer {}", token)
+    };
+    let exp = chrono::Utc::now().timestamp() + 3600;
+    let alice = token(json!({ "sub": "alice", "roles": ["editor"], "exp": exp }));
+    let nobody = token(json!({ "roles": ["admin"], "exp": exp }));
+
+    let ada = json!({ "name": "Ada", "email": "ada@example.com" });
+    app.send(post("/user/").header("authorization", &alice).json(ada)).expect(StatusCode::CREATED);
+    app.send(delete("/user/0").header("authorization", &nobody)).expect(StatusCode::NO_CONTENT);
+
+    app.send(get("/changes")).expect(StatusCode::UNAUTHORIZED);
+    let page = app.send(get("/changes").header("authorization", &nobody)).expect(StatusCode::OK).json();
+    let actors: Vec<&str> = page["changes"].as_array().unwrap().iter().map(|c| c["actor"].as_str().unwrap()).collect();
+    assert_eq!(actors, ["alice", "anonymous"]);
+}
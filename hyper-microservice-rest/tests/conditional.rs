@@ -0,0 +1,69 @@
+mod harness;
+
+use harness::{delete, get, post, put, Harness};
+use http::StatusCode;
+use hyper_microservice_rest::conditional;
+use serde_json::{json, Value};
+
+fn ada() -> Value {
+    json!({ "name": "Ada", "email": "ada@example.com" })
+}
+
+/// A harness holding user 0, already replaced once so it is at version 2.
+fn with_ada() -> Harness {
+    let app = Harness::new();
+    app.send(post("/user/").json(ada())).expect_header("etag", "\"1\"");
+    app.send(put("/user/0").json(ada())).expect_header("etag", "\"2\"");
+    app
+}
+
+#[test]
+fn parses_tag_lists() {
+    let tag = conditional::etag(3);
+    assert_eq!(tag, "\"3\"");
+    assert!(conditional::if_match("\"3\"", &tag));
+    assert!(conditional::if_match("\"1\", \"3\"", &tag));
+    assert!(conditional::if_match("*", &tag));
+    assert!(!conditional::if_match("\"33\"", &tag));
+    assert!(!conditional::if_match("W/\"3\"", &tag));
+
+    assert!(conditional::if_none_match("W/\"3\"", &tag));
+    assert!(conditional::if_none_match("\"2\",\"3\"", &tag));
+    assert!(!conditional::if_none_match("\"2\"", &tag));
+}
+
+#[test]
+fn get_answers_not_modified_for_a_current_tag() {
+    let app = with_ada();
+
+    let fresh = app.send(get("/user/0").header("if-none-match", "\"2\""));
+    fresh.expect(StatusCode::NOT_MODIFIED).expect_header("etag", "\"2\"");
+    assert!(fresh.text().is_empty());
+
+    let stale = app.send(get("/user/0").header("if-none-match", "\"1\""));
+    stale.expect(StatusCode::OK).expect_header("etag", "\"2\"");
+    assert_eq!(stale.json()["version"], 2);
+}
+
+#[test]
+fn put_with_a_stale_tag_is_refused() {
+    let app = with_ada();
+    let grace = json!({ "name": "Grace", "email": "grace@example.com" });
+
+    let stale = app.send(put("/user/0").header("if-match", "\"1\"").json(grace.clone()));
+    stale.expect_problem(StatusCode::PRECONDITION_FAILED, "precondition-failed");
+
+    let current = app.send(put("/user/0").header("if-match", "\"2\"").json(grace));
+    current.expect(StatusCode::OK).expect_header("etag", "\"3\"");
+}
+
+#[test]
+fn delete_with_a_stale_tag_is_refused() {
+    let app = with_ada();
+
+    app.send(delete("/user/0").header("if-match", "\"1\"")).expect(StatusCode::PRECONDITION_FAILED);
+    app.send(get("/user/0")).expect(StatusCode::OK);
+
+    app.send(delete("/user/0").header("if-match", "*")).expect(StatusCode::NO_CONTENT);
+    app.send(delete("/user/0").header("if-match", "\"2\"")).expect(StatusCode::GONE);
+}
@@ -0,0 +1,60 @@
+use http::header::{ALLOW, CONTENT_TYPE};
+use http::{Method, StatusCode};
+use hyper_microservice_rest::error::ApiError;
+use serde_json::{json, Value};
+
+fn body(response: &http::Response<String>) -> Value {
+    serde_json::from_str(response.body()).unwrap()
+}
+
+#[test]
+fn errors_keep_their_status() {
+    let cases = [
+        (ApiError::Unauthorized("x".into()), StatusCode::UNAUTHORIZED),
+        (ApiError::Forbidden("x".into()), StatusCode::FORBIDDEN),
+        (ApiError::NotFound("x".into()), StatusCode::NOT_FOUND),
+        (ApiError::MethodNotAllowed(vec![Method::GET]), StatusCode::METHOD_NOT_ALLOWED),
+        (ApiError::InvalidBody("x".into()), StatusCode::BAD_REQUEST),
+        (ApiError::UnsupportedMediaType("x".into()), StatusCode::UNSUPPORTED_MEDIA_TYPE),
+        (ApiError::NotAcceptable(&["text/csv"]), StatusCode::NOT_ACCEPTABLE),
+        (ApiError::Unprocessable("x".into()), StatusCode::UNPROCESSABLE_ENTITY),
+        (ApiError::Conflict("x".into()), StatusCode::CONFLICT),
+        (ApiError::PreconditionFailed("x".into()), StatusCode::PRECONDITION_FAILED),
+        (ApiError::TooManyRequests { detail: "x".into(), retry_after: 1 }, StatusCode::TOO_MANY_REQUESTS),
+        (ApiError::Internal("x".into()), StatusCode::INTERNAL_SERVER_ERROR),
+    ];
+    for (error, status) in cases {
+        let response = error.into_response("/user/1");
+        assert_eq!(response.status(), status);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/problem+json");
+        assert_eq!(body(&response)["status"], status.as_u16());
+    }
+}
+
+#[test]
+fn problem_body_has_every_member() {
+    let response = ApiError::InvalidBody("missing field `email`".into()).into_response("/user/");
+    assert_eq!(
+        body(&response),
+        json!({
+            "type": "/problems/invalid-body",
+            "title": "Invalid request body",
+            "status": 400,
+            "detail": "missing field `email`",
+            "instance": "/user/",
+        })
+    );
+}
+
+#[test]
+fn method_not_allowed_lists_allowed_methods() {
+    let response = ApiError::MethodNotAllowed(vec![Method::GET, Method::PUT, Method::DELETE]).into_response("/user/3");
+    assert_eq!(response.headers()[ALLOW], "GET, PUT, DELETE");
+    assert_eq!(body(&response)["detail"], "allowed methods are GET, PUT, DELETE");
+}
+
+#[test]
+fn internal_details_stay_on_the_server() {
+    let response = ApiError::Internal("disk on fire".into()).into_response("/user/");
+    assert!(!response.body().contains("disk on fire"));
+}
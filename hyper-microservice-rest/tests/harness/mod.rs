@@ -0,0 +1,158 @@
+//! Runs requests through the Vercel handler in-process, the way the
+//! runtime would, and checks what comes back.
+
+#![allow(dead_code)]
+
+use http::{Method, StatusCode};
+use hyper_microservice_rest::router::Router;
+use hyper_microservice_rest::store::MemoryStore;
+use hyper_microservice_rest::vercel;
+use serde_json::Value;
+use std::sync::Arc;
+use vercel_runtime::{Body, Request, Response};
+
+/// A router over a fresh memory store, called through the Vercel handler.
+pub struct Harness {
+    router: Router,
+}
+
+impl Harness {
+    pub fn new() -> Harness {
+        Harness::with_router(Router::new(Arc::new(MemoryStore::new())))
+    }
+
+    pub fn with_router(router: Router) -> Harness {
+        Harness { router }
+    }
+
+    /// The router, for tests that need more than the Vercel handler gives.
+    pub fn router(&self) -> &Router {
+        &self.router
+    }
+
+    pub fn send(&self, request: TestRequest) -> Reply {
+        let description = format!("{} {}", request.method, request.uri);
+        Reply {
+            description,
+            response: vercel::handle(&self.router, request.build()),
+        }
+    }
+}
+
+/// A request under construction.
+pub struct TestRequest {
+    method: Method,
+    uri: String,
+    headers: Vec<(String, String)>,
+    body: Body,
+}
+
+pub fn request(method: Method, uri: &str) -> TestRequest {
+    TestRequest {
+        method,
+        uri: uri.to_string(),
+        headers: Vec::new(),
+        body: Body::Empty,
+    }
+}
+
+pub fn get(uri: &str) -> TestRequest {
+    request(Method::GET, uri)
+}
+
+pub fn post(uri: &str) -> TestRequest {
+    request(Method::POST, uri)
+}
+
+pub fn put(uri: &str) -> TestRequest {
+    request(Method::PUT, uri)
+}
+
+pub fn patch(uri: &str) -> TestRequest {
+    request(Method::PATCH, uri)
+}
+
+pub fn delete(uri: &str) -> TestRequest {
+    request(Method::DELETE, uri)
+}
+
+impl TestRequest {
+    pub fn header(mut self, name: &str, value: &str) -> TestRequest {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// A JSON body, with its `Content-Type`.
+    pub fn json(self, value: Value) -> TestRequest {
+        self.body("application/json", &value.to_string())
+    }
+
+    /// A text body of the given type.
+    pub fn body(mut self, content_type: &str, body: &str) -> TestRequest {
+        self.body = Body::Text(body.to_string());
+        self.header("content-type", content_type)
+    }
+
+    /// A body the runtime passes on as bytes rather than text.
+    pub fn binary(mut self, content_type: &str, body: &[u8]) -> TestRequest {
+        self.body = Body::Binary(body.to_vec());
+        self.header("content-type", content_type)
+    }
+
+    pub fn build(self) -> Request {
+        let mut builder = http::Request::builder().method(self.method).uri(self.uri);
+        for (name, value) in &self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        builder.body(self.body).unwrap()
+    }
+}
+
+/// What the handler answered. The `expect_*` methods panic with the whole
+/// response when it is not what the test wanted.
+pub struct Reply {
+    description: String,
+    pub response: Response<Body>,
+}
+
+impl Reply {
+    pub fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.response.headers().get(name).and_then(|v| v.to_str().ok())
+    }
+
+    pub fn text(&self) -> &str {
+        match self.response.body() {
+            Body::Empty => "",
+            Body::Text(text) => text,
+            Body::Binary(_) => panic!("{} answered with a binary body", self.description),
+        }
+    }
+
+    pub fn json(&self) -> Value {
+        serde_json::from_str(self.text())
+            .unwrap_or_else(|e| panic!("{} did not answer with JSON ({}): {}", self.description, e, self.text()))
+    }
+
+    pub fn expect(&self, status: StatusCode) -> &Reply {
+        assert_eq!(self.status(), status, "{} answered {}", self.description, self.text());
+        self
+    }
+
+    pub fn expect_header(&self, name: &str, value: &str) -> &Reply {
+        assert_eq!(self.header(name), Some(value), "{}: header {}", self.description, name);
+        self
+    }
+
+    /// An RFC 7807 problem with this status and type, like `not-found`.
+    pub fn expect_problem(&self, status: StatusCode, kind: &str) -> &Reply {
+        self.expect(status).expect_header("content-type", "application/problem+json");
+        let problem = self.json();
+        assert_eq!(problem["type"], format!("/problems/{}", kind), "{}: {}", self.description, problem);
+        assert_eq!(problem["status"], status.as_u16());
+        self
+    }
+}
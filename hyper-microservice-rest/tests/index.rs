@@ -0,0 +1,43 @@
+//! Starts the `index` binary with different settings.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+
+/// The binary serving on a free local port, with no settings but `vars`.
+fn index(vars: &[(&str, &str)]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_index"));
+    command.args(["--listen", "127.0.0.1:0"]).env_clear().envs(vars.iter().copied());
+    command
+}
+
+#[test]
+fn api_keys_without_authentication_do_not_start() {
+    let output = index(&[("API_KEYS", "on")]).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("API_KEYS is on but no AUTH_JWT_* key is set"), "{}", stderr);
+}
+
+#[test]
+fn an_empty_or_short_hs256_secret_does_not_start() {
+    for secret in ["", "too short to sign with"] {
+        let output = index(&[("AUTH_JWT_HS256_SECRET", secret)]).output().unwrap();
+        assert!(!output.status.success(), "{:?} was accepted", secret);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("it must be at least 32"), "{}", stderr);
+    }
+}
+
+#[test]
+fn api_keys_with_authentication_start() {
+    let mut child = index(&[("API_KEYS", "on"), ("AUTH_JWT_HS256_SECRET", "a secret for the test, 32 bytes or more")])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(line.starts_with("Listening on http://127.0.0.1:"), "{:?}", line);
+}
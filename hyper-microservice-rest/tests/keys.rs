@@ -0,0 +1,186 @@
+mod harness;
+
+use chrono::{DateTime, Duration, Utc};
+use harness::{delete, get, post, Harness};
+use http::{HeaderMap, StatusCode};
+use hyper_microservice_rest::keys::{ApiKeys, Check, KeyInput, Limits, KEY_HEADER};
+use hyper_microservice_rest::router::Router;
+use hyper_microservice_rest::store::{MemoryStore, StoreConfig};
+use serde_json::json;
+use std::fs;
+use std::process;
+use std::sync::Arc;
+
+fn with_keys() -> Harness {
+    let router = Router::new(Arc::new(MemoryStore::new())).with_api_keys(ApiKeys::in_memory(Limits::default()));
+    Harness::with_router(router)
+}
+
+/// Issues a key through the API and returns it.
+fn issue(app: &Harness, body: serde_json::Value) -> String {
+    let issued = app.send(post("/keys").json(body));
+    issued.expect(StatusCode::CREATED);
+    issued.json()["key"].as_str().unwrap().to_string()
+}
+
+fn input(per_minute: u64, per_day: u64) -> KeyInput {
+    KeyInput {
+        name: "tests".to_string(),
+        per_minute: Some(per_minute),
+        per_day: Some(per_day),
+    }
+}
+
+/// 12:00:30 UTC on some day.
+fn noon() -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339("2026-03-02T12:00:30Z").unwrap().with_timezone(&Utc)
+}
+
+#[test]
+fn requests_need_a_valid_key() {
+    let app = with_keys();
+    let issued = app.send(post("/keys").json(json!({ "name": "billing" })));
+    issued
+        .expect(StatusCode::CREATED)
+        .expect_header("location", "/keys/0");
+    let key = issued.json()["key"].as_str().unwrap().to_string();
+    assert!(key.starts_with(issued.json()["prefix"].as_str().unwrap()));
+    assert_eq!(issued.json()["per_minute"], 60);
+    assert_eq!(issued.json()["per_day"], 10_000);
+
+    let listed = app.send(get("/keys")).json();
+    assert_eq!(listed[0]["name"], "billing");
+    assert!(listed[0].get("key").is_none());
+
+    app.send(get("/user/")).expect_problem(StatusCode::UNAUTHORIZED, "unauthorized");
+    app.send(get("/user/").header(KEY_HEADER, "uk_not-a-key"))
+        .expect_problem(StatusCode::UNAUTHORIZED, "unauthorized");
+    app.send(get("/user/").header(KEY_HEADER, &key))
+        .expect(StatusCode::OK)
+        .expect_header("ratelimit-limit", "60")
+        .expect_header("ratelimit-remaining", "59")
+        .expect_header("ratelimit-policy", "60;w=60, 10000;w=86400");
+    app.send(get("/")).expect(StatusCode::OK);
+    app.send(get("/openapi.json")).expect(StatusCode::OK);
+}
+
+#[test]
+fn over_quota_requests_get_429_and_are_not_counted() {
+    let app = with_keys();
+    let key = issue(&app, json!({ "name": "tight", "per_minute": 2 }));
+    for remaining in &["1", "0"] {
+        let reply = app.send(get("/user/").header(KEY_HEADER, &key));
+        reply.expect(StatusCode::OK).expect_header("ratelimit-remaining", remaining);
+        assert_eq!(reply.header("retry-after"), None);
+    }
+    let refused = app.send(get("/user/").header(KEY_HEADER, &key));
+    refused
+        .expect_problem(StatusCode::TOO_MANY_REQUESTS, "too-many-requests")
+        .expect_header("ratelimit-remaining", "0");
+    let retry_after: u64 = refused.header("retry-after").unwrap().parse().unwrap();
+    assert!((1..=60).contains(&retry_after), "{}", retry_after);
+    assert_eq!(refused.json()["detail"], "key 0 has made its 2 requests for this minute");
+
+    let usage = app.send(get("/keys/0/usage")).json();
+    assert_eq!(usage["minute"]["used"], 2);
+    assert_eq!(usage["minute"]["remaining"], 0);
+    assert_eq!(usage["day"]["used"], 2);
+    assert_eq!(usage["day"]["limit"], 10_000);
+
+    app.send(delete("/keys/0/usage")).expect(StatusCode::NO_CONTENT);
+    assert_eq!(app.send(get("/keys/0/usage")).json()["day"]["used"], 0);
+    app.send(get("/user/").header(KEY_HEADER, &key)).expect(StatusCode::OK);
+}
+
+#[test]
+fn quotas_reset_with_their_window() {
+    let keys = ApiKeys::in_memory(Limits::default());
+    let key = keys.issue(input(2, 3), noon()).unwrap().key;
+    let check = |at: DateTime<Utc>| keys.check(Some(&key), at).unwrap();
+
+    assert!(matches!(check(noon()), Check::Allowed(_)));
+    assert!(matches!(check(noon()), Check::Allowed(_)));
+    let usage = match check(noon()) {
+        Check::Limited(usage) => usage,
+        other => panic!("{:?}", other),
+    };
+    let mut headers = HeaderMap::new();
+    usage.write_headers(noon(), &mut headers);
+    assert_eq!(headers["ratelimit-limit"], "2");
+    assert_eq!(headers["ratelimit-reset"], "30");
+
+    // The next minute has room, until the day runs out.
+    let later = noon() + Duration::seconds(30);
+    assert!(matches!(check(later), Check::Allowed(_)));
+    let usage = match check(later) {
+        Check::Limited(usage) => usage,
+        other => panic!("{:?}", other),
+    };
+    let mut headers = HeaderMap::new();
+    usage.write_headers(later, &mut headers);
+    assert_eq!(headers["ratelimit-limit"], "3");
+    assert_eq!(usage.reset_in(later), 12 * 3600 - 60);
+
+    assert!(matches!(check(noon() + Duration::days(1)), Check::Allowed(_)));
+    assert_eq!(keys.check(None, noon()).unwrap(), Check::Missing);
+    assert_eq!(keys.check(Some("uk_nope"), noon()).unwrap(), Check::Unknown);
+}
+
+#[test]
+fn revoked_keys_stop_working() {
+    let app = with_keys();
+    let key = issue(&app, json!({ "name": "leaked" }));
+    app.send(delete("/keys/0")).expect(StatusCode::NO_CONTENT);
+    app.send(get("/user/").header(KEY_HEADER, &key)).expect_problem(StatusCode::UNAUTHORIZED, "unauthorized");
+    app.send(delete("/keys/0")).expect_problem(StatusCode::NOT_FOUND, "not-found");
+    app.send(get("/keys/0/usage")).expect_problem(StatusCode::NOT_FOUND, "not-found");
+    app.send(get("/keys/0"))
+        .expect_problem(StatusCode::METHOD_NOT_ALLOWED, "method-not-allowed")
+        .expect_header("allow", "DELETE");
+    assert_eq!(app.send(get("/keys")).json(), json!([]));
+}
+
+#[test]
+fn issuing_is_validated() {
+    let app = with_keys();
+    let cases = [
+        (json!({ "name": " " }), StatusCode::UNPROCESSABLE_ENTITY, "unprocessable"),
+        (json!({ "name": "x".repeat(101) }), StatusCode::UNPROCESSABLE_ENTITY, "unprocessable"),
+        (json!({ "name": "ci", "per_day": 0 }), StatusCode::UNPROCESSABLE_ENTITY, "unprocessable"),
+        (json!({ "name": "ci", "per_hour": 5 }), StatusCode::BAD_REQUEST, "invalid-body"),
+        (json!({}), StatusCode::BAD_REQUEST, "invalid-body"),
+    ];
+    for (body, status, kind) in cases {
+        app.send(post("/keys").json(body)).expect_problem(status, kind);
+    }
+    let plain = Harness::new();
+    plain.send(get("/keys")).expect_problem(StatusCode::NOT_FOUND, "not-found");
+    plain.send(get("/user/")).expect(StatusCode::OK);
+}
+
+#[test]
+fn the_file_backend_keeps_keys_and_usage() {
+    let path = std::env::temp_dir().join(format!("users-keys-{}.ndjson", process::id()));
+    let key_log = path.with_extension("keys.ndjson");
+    let _ = fs::remove_file(&key_log);
+    let config = StoreConfig::File(path);
+
+    let keys = config.open_keys(Limits::default()).unwrap();
+    let kept = keys.issue(input(10, 100), noon()).unwrap().key;
+    keys.issue(input(10, 100), noon()).unwrap();
+    for _ in 0..3 {
+        keys.check(Some(&kept), noon()).unwrap();
+    }
+    assert!(keys.revoke(1).unwrap());
+    drop(keys);
+    assert_eq!(fs::read_to_string(&key_log).unwrap().lines().count(), 6);
+
+    let keys = config.open_keys(Limits::default()).unwrap();
+    assert_eq!(keys.usage(0, noon()).unwrap().day.used, 3);
+    assert_eq!(keys.usage(1, noon()), None);
+    // Issue, usage, and the revoke that keeps id 1 from coming back.
+    assert_eq!(fs::read_to_string(&key_log).unwrap().lines().count(), 3);
+    assert_eq!(keys.issue(input(10, 100), noon()).unwrap().info.id, 2);
+    assert!(matches!(keys.check(Some(&kept), noon()).unwrap(), Check::Allowed(usage) if usage.day.used == 4));
+    fs::remove_file(&key_log).unwrap();
+}
@@ -0,0 +1,110 @@
+use chrono::{Duration, TimeZone, Utc};
+use hyper_microservice_rest::list::{self, ListQuery, Page};
+use hyper_microservice_rest::user::{UserData, UserId, UserInput};
+
+/// Users 0..n, created a minute apart, with names running backwards.
+fn users(n: u64) -> Vec<(UserId, UserData)> {
+    let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    (0..n)
+        .map(|id| {
+            let input = UserInput {
+                name: format!("user {}", (b'z' - id as u8) as char),
+                email: format!("u{}@{}.example.com", id, if id % 2 == 0 { "even" } else { "odd" }),
+            };
+            (id, UserData::new(input, start + Duration::minutes(id as i64)))
+        })
+        .collect()
+}
+
+fn page(users: &[(UserId, UserData)], query: &str) -> Page {
+    list::paginate(users.to_vec(), &ListQuery::parse(Some(query)).unwrap())
+}
+
+fn ids(page: &Page) -> Vec<UserId> {
+    page.users.iter().map(|(id, _)| *id).collect()
+}
+
+#[test]
+fn default_is_first_page_by_id() {
+    let all = users(25);
+    let first = list::paginate(all, &ListQuery::parse(None).unwrap());
+    assert_eq!(ids(&first), (0..20).collect::<Vec<_>>());
+    assert!(first.prev.is_none());
+    assert!(first.next.is_some());
+}
+
+#[test]
+fn walks_forward_and_back() {
+    let all = users(7);
+    let mut pages = vec![page(&all, "limit=3")];
+    while let Some(next) = &pages.last().unwrap().next {
+        let next = page(&all, next);
+        pages.push(next);
+    }
+    let seen: Vec<Vec<UserId>> = pages.iter().map(ids).collect();
+    assert_eq!(seen, vec![vec![0, 1, 2], vec![3, 4, 5], vec![6]]);
+
+    let back = page(&all, pages[2].prev.as_ref().unwrap());
+    assert_eq!(ids(&back), vec![3, 4, 5]);
+    let back = page(&all, back.prev.as_ref().unwrap());
+    assert_eq!(ids(&back), vec![0, 1, 2]);
+    assert!(back.prev.is_none());
+}
+
+#[test]
+fn cursors_survive_changes_to_the_list() {
+    let mut all = users(6);
+    let first = page(&all, "limit=2");
+    // Removing a user already shown must not make the next page skip one.
+    all.retain(|(id, _)| *id != 0);
+    assert_eq!(ids(&page(&all, first.next.as_ref().unwrap())), vec![2, 3]);
+}
+
+#[test]
+fn sorts_by_any_field_either_way() {
+    let all = users(4);
+    assert_eq!(ids(&page(&all, "sort=name")), vec![3, 2, 1, 0]);
+    assert_eq!(ids(&page(&all, "sort=-created_at")), vec![3, 2, 1, 0]);
+    assert_eq!(ids(&page(&all, "sort=-id&limit=2")), vec![3, 2]);
+
+    let first = page(&all, "sort=-id&limit=2");
+    assert_eq!(ids(&page(&all, first.next.as_ref().unwrap())), vec![1, 0]);
+}
+
+#[test]
+fn filters_combine_and_carry_over_to_links() {
+    let all = users(10);
+    let even = page(&all, "email=EVEN&limit=2");
+    assert_eq!(ids(&even), vec![0, 2]);
+    let next = even.next.as_ref().unwrap();
+    assert!(next.starts_with("email=EVEN&limit=2&cursor="), "{}", next);
+    assert_eq!(ids(&page(&all, next)), vec![4, 6]);
+
+    let window = page(&all, "created_after=2024-01-01T00:02:00Z&created_before=2024-01-01T00:06:00%2B00:00");
+    assert_eq!(ids(&window), vec![3, 4, 5]);
+    assert_eq!(ids(&page(&all, "name=user+z")), vec![0]);
+}
+
+#[test]
+fn rejects_bad_parameters() {
+    let cases = [
+        ("limit=0", "`limit`"),
+        ("limit=101", "`limit`"),
+        ("sort=age", "cannot sort by `age`"),
+        ("created_after=yesterday", "RFC 3339"),
+        ("cursor=nope", "not a cursor"),
+        ("colour=red", "unknown query parameter `colour`"),
+        ("name=%zz", "percent-escape"),
+    ];
+    for (query, expected) in cases.iter() {
+        let error = ListQuery::parse(Some(query)).unwrap_err();
+        assert!(error.contains(expected), "{} gave {}", query, error);
+    }
+
+    let all = users(3);
+    let by_name = page(&all, "sort=name&limit=1");
+    let cursor = by_name.next.unwrap();
+    let cursor = cursor.split("cursor=").nth(1).unwrap();
+    let error = ListQuery::parse(Some(&format!("cursor={}", cursor))).unwrap_err();
+    assert!(error.contains("sorted by `name`"), "{}", error);
+}
@@ -0,0 +1,84 @@
+use chrono::Utc;
+use http::header::{ALLOW, CONTENT_TYPE};
+use http::{Method, Request, StatusCode};
+use hyper_microservice_rest::keys::{ApiKeys, KeyInput, Limits, KEY_HEADER};
+use hyper_microservice_rest::openapi::{self, ENDPOINTS, OPENAPI_PATH};
+use hyper_microservice_rest::router::Router;
+use hyper_microservice_rest::store::MemoryStore;
+use hyper_microservice_rest::webhook::{Retry, Webhooks};
+use serde_json::Value;
+use std::sync::Arc;
+
+fn send(router: &Router, method: Method, path: &str) -> http::Response<Vec<u8>> {
+    router.handle(Request::builder().method(method).uri(path).body(Vec::new()).unwrap())
+}
+
+#[test]
+fn documents_exactly_the_endpoints() {
+    let paths = openapi::document()["paths"].as_object().unwrap();
+    let documented: usize = paths.values().map(|operations| operations.as_object().unwrap().len()).sum();
+    assert_eq!(documented, ENDPOINTS.len());
+    for endpoint in ENDPOINTS {
+        let operation = &paths[endpoint.path][endpoint.method.as_str().to_ascii_lowercase()];
+        assert_eq!(operation["summary"], endpoint.summary);
+        assert!(operation["responses"].as_object().is_some_and(|r| !r.is_empty()));
+    }
+}
+
+#[test]
+fn schema_references_resolve() {
+    let document = openapi::document();
+    let text = document.to_string();
+    for reference in text.split("\"$ref\":\"").skip(1) {
+        let name = reference.split('"').next().unwrap();
+        let name = name.strip_prefix("#/components/schemas/").expect(name);
+        assert!(document["components"]["schemas"][name].is_object(), "{} is missing", name);
+    }
+}
+
+#[test]
+fn the_router_serves_every_documented_endpoint() {
+    let users = Arc::new(MemoryStore::new());
+    let webhooks = Arc::new(Webhooks::new(users.clone(), Retry::default()));
+    let api_keys = ApiKeys::in_memory(Limits::default());
+    let input = |name: &str| KeyInput {
+        name: name.to_string(),
+        per_minute: None,
+        per_day: None,
+    };
+    // Key 0 is there to be revoked; requests use key 1.
+    api_keys.issue(input("revoked"), Utc::now()).unwrap();
+    let key = api_keys.issue(input("tests"), Utc::now()).unwrap().key;
+    let router = Router::new(users).with_webhooks(webhooks).with_api_keys(api_keys);
+    let send = |method: Method, path: &str| {
+        router.handle(Request::builder().method(method).uri(path).header(KEY_HEADER, &key).body(Vec::new()).unwrap())
+    };
+    for endpoint in ENDPOINTS {
+        let path = endpoint.path.replace("{id}", "0");
+        let response = send(endpoint.method.clone(), &path);
+        assert_ne!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{} {}", endpoint.method, path);
+
+        let refused = send(Method::OPTIONS, &path);
+        assert_eq!(refused.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let allowed: Vec<_> = openapi::methods(endpoint.path).iter().map(Method::to_string).collect();
+        assert_eq!(refused.headers()[ALLOW], allowed.join(", "));
+    }
+}
+
+#[test]
+fn serves_the_document_and_an_index_built_from_it() {
+    let router = Router::new(Arc::new(MemoryStore::new()));
+    let response = send(&router, Method::GET, OPENAPI_PATH);
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+    let served: Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(&served, openapi::document());
+    assert_eq!(served["openapi"], "3.0.3");
+
+    let index = send(&router, Method::GET, "/");
+    let html = String::from_utf8(index.into_body()).unwrap();
+    for endpoint in ENDPOINTS {
+        let item = format!("<strong>{} {}</strong> - {}", endpoint.method, endpoint.path, endpoint.summary);
+        assert!(html.contains(&item), "{} missing from the index", item);
+    }
+}
@@ -0,0 +1,128 @@
+mod harness;
+
+use harness::{get, patch, post, Harness, Reply};
+use http::StatusCode;
+use hyper_microservice_rest::patch::{self as json_patch, Operation};
+use serde_json::{json, Value};
+
+#[test]
+fn merges_like_rfc_7396() {
+    // The examples from appendix A of the RFC.
+    let cases = [
+        (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+        (json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+        (json!({"a": "b"}), json!({"a": null}), json!({})),
+        (json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+        (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+        (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+        (json!({"a": {"b": "c"}}), json!({"a": {"b": "d", "c": null}}), json!({"a": {"b": "d"}})),
+        (json!({"a": [{"b": "c"}]}), json!({"a": [1]}), json!({"a": [1]})),
+        (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+        (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+        (json!({"a": "foo"}), json!(null), json!(null)),
+        (json!({"a": "foo"}), json!("bar"), json!("bar")),
+        (json!({"e": null}), json!({"a": 1}), json!({"e": null, "a": 1})),
+        (json!([1, 2]), json!({"a": "b", "c": null}), json!({"a": "b"})),
+        (json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}})),
+    ];
+    for (target, patch, expected) in cases.iter() {
+        let mut merged = target.clone();
+        json_patch::merge(&mut merged, patch);
+        assert_eq!(&merged, expected, "{} merged with {}", target, patch);
+    }
+}
+
+fn operations(value: Value) -> Vec<Operation> {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn applies_json_patch_operations() {
+    let mut doc = json!({"a": {"b": [1, 2]}, "c": "d", "x/y": 0});
+    let ops = operations(json!([
+        {"op": "test", "path": "/c", "value": "d"},
+        {"op": "add", "path": "/a/b/1", "value": 9},
+        {"op": "add", "path": "/a/b/-", "value": 3},
+        {"op": "remove", "path": "/a/b/0"},
+        {"op": "replace", "path": "/c", "value": "e"},
+        {"op": "copy", "from": "/c", "path": "/f"},
+        {"op": "move", "from": "/x~1y", "path": "/z"},
+    ]));
+    json_patch::apply(&mut doc, &ops).unwrap();
+    assert_eq!(doc, json!({"a": {"b": [9, 2, 3]}, "c": "e", "f": "e", "z": 0}));
+}
+
+#[test]
+fn json_patch_errors_name_the_operation() {
+    let cases = [
+        (json!([{"op": "test", "path": "/a", "value": 2}]), "operation 0: `/a` is not 2"),
+        (json!([{"op": "add", "path": "/b", "value": 1}, {"op": "remove", "path": "/c"}]), "operation 1: `/c` does not exist"),
+        (json!([{"op": "replace", "path": "a", "value": 1}]), "`a` is not a JSON pointer"),
+        (json!([{"op": "add", "path": "/l/01", "value": 1}]), "`01` is not an index"),
+        (json!([{"op": "add", "path": "/l/3", "value": 1}]), "`3` is not an index below 3"),
+        (json!([{"op": "move", "from": "/l", "path": "/l/0"}]), "cannot move `/l` into itself"),
+    ];
+    for (ops, expected) in cases.iter() {
+        let mut doc = json!({"a": 1, "l": [1, 2]});
+        let error = json_patch::apply(&mut doc, &operations(ops.clone())).unwrap_err();
+        assert!(error.contains(expected), "{} gave {:?}", ops, error);
+    }
+}
+
+/// A harness holding user 0.
+fn with_ada() -> Harness {
+    let app = Harness::new();
+    app.send(post("/user/").json(json!({ "name": "Ada", "email": "ada@example.com" }))).expect(StatusCode::CREATED);
+    app
+}
+
+fn patch_user(app: &Harness, content_type: &str, body: &str) -> Reply {
+    app.send(patch("/user/0").body(content_type, body))
+}
+
+#[test]
+fn merge_patch_changes_only_the_given_fields() {
+    let app = with_ada();
+    let user = patch_user(&app, json_patch::MERGE_PATCH_JSON, r#"{"name": "Ada Lovelace"}"#).expect(StatusCode::OK).json();
+    assert_eq!(user["name"], "Ada Lovelace");
+    assert_eq!(user["email"], "ada@example.com");
+    assert_eq!(user["version"], 2);
+}
+
+#[test]
+fn json_patch_changes_the_user() {
+    let app = with_ada();
+    let body = r#"[{"op": "test", "path": "/name", "value": "Ada"}, {"op": "replace", "path": "/email", "value": "ada@lovelace.org"}]"#;
+    let user = patch_user(&app, json_patch::JSON_PATCH_JSON, body).expect(StatusCode::OK).json();
+    assert_eq!(user["email"], "ada@lovelace.org");
+}
+
+#[test]
+fn patched_users_are_validated_before_saving() {
+    let app = with_ada();
+    let cases = [
+        (json_patch::MERGE_PATCH_JSON, r#"{"email": "nobody"}"#, StatusCode::UNPROCESSABLE_ENTITY),
+        (json_patch::MERGE_PATCH_JSON, r#"{"name": null}"#, StatusCode::UNPROCESSABLE_ENTITY),
+        (json_patch::MERGE_PATCH_JSON, r#"{"admin": true}"#, StatusCode::UNPROCESSABLE_ENTITY),
+        (json_patch::JSON_PATCH_JSON, r#"[{"op": "test", "path": "/name", "value": "Bob"}]"#, StatusCode::UNPROCESSABLE_ENTITY),
+        (json_patch::JSON_PATCH_JSON, r#"[{"op": "frobnicate", "path": "/name"}]"#, StatusCode::BAD_REQUEST),
+        (json_patch::MERGE_PATCH_JSON, r#"{"name": "#, StatusCode::BAD_REQUEST),
+        ("application/json", r#"{"name": "Bob"}"#, StatusCode::UNSUPPORTED_MEDIA_TYPE),
+    ];
+    for (content_type, body, status) in cases.iter() {
+        patch_user(&app, content_type, body).expect(*status);
+    }
+
+    let user = app.send(get("/user/0")).json();
+    assert_eq!(user["name"], "Ada");
+    assert_eq!(user["version"], 1);
+}
+
+#[test]
+fn patch_honours_if_match() {
+    let app = with_ada();
+    let stale = patch("/user/0").header("if-match", "\"2\"").body(json_patch::MERGE_PATCH_JSON, r#"{"name": "Bob"}"#);
+    app.send(stale).expect(StatusCode::PRECONDITION_FAILED);
+
+    app.send(patch("/user/9").body(json_patch::MERGE_PATCH_JSON, "{}")).expect(StatusCode::NOT_FOUND);
+}
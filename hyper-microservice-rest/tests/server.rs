@@ -0,0 +1,78 @@
+use hyper_microservice_rest::router::Router;
+use hyper_microservice_rest::server;
+use hyper_microservice_rest::store::MemoryStore;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn start() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let router = Arc::new(Router::new(Arc::new(MemoryStore::new())));
+    tokio::spawn(server::serve(listener, router));
+    addr
+}
+
+/// Sends one raw HTTP/1.1 request and returns the raw response. Stops
+/// reading once the response is complete, whatever the server does next.
+async fn exchange(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        let read = stream.read(&mut buf).await.unwrap();
+        assert!(read > 0, "connection closed mid-response");
+        response.extend_from_slice(&buf[..read]);
+
+        let text = String::from_utf8_lossy(&response);
+        if let Some(end) = text.find("\r\n\r\n") {
+            let length: usize = text[..end]
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .map_or(0, |len| len.parse().unwrap());
+            if response.len() >= end + 4 + length {
+                return text.into_owned();
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn serves_the_router_over_tcp() {
+    let addr = start().await;
+
+    let body = r#"{"name": "Ada", "email": "ada@example.com"}"#;
+    let created = exchange(
+        addr,
+        &format!(
+            "POST /user/ HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+    )
+    .await;
+    assert!(created.starts_with("HTTP/1.1 201 Created\r\n"), "{}", created);
+    assert!(created.contains("location: /user/0\r\n"));
+
+    let fetched = exchange(addr, "GET /user/0 HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").await;
+    assert!(fetched.starts_with("HTTP/1.1 200 OK\r\n"), "{}", fetched);
+    assert!(fetched.contains(r#""name":"Ada""#));
+}
+
+#[tokio::test]
+async fn oversized_bodies_are_rejected() {
+    let addr = start().await;
+    let response = exchange(
+        addr,
+        &format!(
+            "POST /user/ HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            server::MAX_BODY + 1
+        ),
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{}", response);
+    assert!(response.contains("application/problem+json"));
+}
@@ -0,0 +1,293 @@
+use hyper_microservice_rest::changes::ChangeKind;
+use hyper_microservice_rest::store::{Batch, Change, FileStore, MemoryStore, Outcome, StoreError, UserStore};
+use hyper_microservice_rest::user::{UserData, UserInput};
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn temp_log() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "users-{}-{}.ndjson",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn input(name: &str) -> UserInput {
+    UserInput {
+        name: name.to_string(),
+        email: format!("{}@example.com", name),
+    }
+}
+
+fn user(name: &str) -> UserData {
+    UserData::new(input(name), chrono::Utc::now())
+}
+
+fn crud(store: &dyn UserStore) {
+    let a = store.insert(user("a"), "tester").unwrap();
+    let b = store.insert(user("b"), "tester").unwrap();
+    assert_ne!(a, b);
+    assert_eq!(store.get(a).unwrap().unwrap().name, "a");
+
+    assert_eq!(store.update(b, 1, user("c"), "tester").unwrap(), Outcome::Done);
+    assert_eq!(store.get(b).unwrap().unwrap().name, "c");
+    assert_eq!(store.update(99, 1, user("d"), "tester").unwrap(), Outcome::Missing);
+
+    assert_eq!(store.remove(a, None, "tester").unwrap(), Outcome::Done);
+    assert_eq!(store.remove(a, None, "tester").unwrap(), Outcome::Missing);
+    assert_eq!(store.get(a).unwrap(), None);
+    assert_eq!(store.get(b).unwrap().unwrap().name, "c");
+    assert_eq!(store.get(u64::MAX).unwrap(), None);
+
+    // Ids of deleted users are remembered and never handed out again.
+    assert!(store.deleted(a).unwrap());
+    assert!(!store.deleted(b).unwrap());
+    assert!(!store.deleted(u64::MAX).unwrap());
+    assert_eq!(store.remove(b, None, "tester").unwrap(), Outcome::Done);
+    let c = store.insert(user("e"), "tester").unwrap();
+    assert!(c > b);
+}
+
+fn versions(store: &dyn UserStore) {
+    let id = store.insert(user("a"), "tester").unwrap();
+    let v1 = store.get(id).unwrap().unwrap();
+    let v2 = v1.replaced(input("b"), chrono::Utc::now());
+    assert_eq!(store.update(id, 1, v2.clone(), "tester").unwrap(), Outcome::Done);
+
+    // A writer that read version 1 loses to the one that already moved on.
+    let stale = v1.replaced(input("c"), chrono::Utc::now());
+    assert_eq!(store.update(id, 1, stale, "tester").unwrap(), Outcome::Conflict(2));
+    assert_eq!(store.remove(id, Some(1), "tester").unwrap(), Outcome::Conflict(2));
+    assert_eq!(store.get(id).unwrap(), Some(v2));
+
+    assert_eq!(store.remove(id, Some(2), "tester").unwrap(), Outcome::Done);
+    assert_eq!(store.remove(id, Some(2), "tester").unwrap(), Outcome::Missing);
+}
+
+fn batches(store: &dyn UserStore) {
+    let a = store.insert(user("a"), "tester").unwrap();
+    let v1 = store.get(a).unwrap().unwrap();
+    let update = |expected| Change::Update {
+        id: a,
+        expected,
+        user: v1.replaced(input("b"), chrono::Utc::now()),
+    };
+
+    let refused = store.apply_all(vec![Change::Insert(user("c")), update(2)], "tester").unwrap();
+    assert_eq!(refused, Batch::Refused { index: 1, outcome: Outcome::Conflict(1) });
+    assert_eq!(store.list().unwrap().len(), 1);
+
+    let done = store.apply_all(vec![Change::Insert(user("c")), update(1), Change::Insert(user("d"))], "tester").unwrap();
+    assert_eq!(done, Batch::Done(vec![a + 1, a, a + 2]));
+    assert_eq!(name(store, a).as_deref(), Some("b"));
+    assert_eq!(name(store, a + 2).as_deref(), Some("d"));
+}
+
+#[test]
+fn memory_store_applies_batches_whole() {
+    batches(&MemoryStore::new());
+}
+
+#[test]
+fn file_store_applies_batches_whole() {
+    let path = temp_log();
+    batches(&FileStore::open(&path).unwrap());
+    let reopened = FileStore::open(&path).unwrap();
+    assert_eq!(name(&reopened, 1).as_deref(), Some("c"));
+    assert_eq!(reopened.insert(user("e"), "tester").unwrap(), 3);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn memory_store_checks_versions() {
+    versions(&MemoryStore::new());
+}
+
+#[test]
+fn file_store_checks_versions() {
+    let path = temp_log();
+    versions(&FileStore::open(&path).unwrap());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn memory_store_crud() {
+    crud(&MemoryStore::new());
+}
+
+#[test]
+fn file_store_crud() {
+    let path = temp_log();
+    crud(&FileStore::open(&path).unwrap());
+    fs::remove_file(path).unwrap();
+}
+
+fn name(store: &dyn UserStore, id: u64) -> Option<String> {
+    store.get(id).unwrap().map(|user| user.name)
+}
+
+#[test]
+fn file_store_survives_reopening() {
+    let path = temp_log();
+    let alice = user("alice");
+    {
+        let store = FileStore::open(&path).unwrap();
+        store.insert(alice.clone(), "tester").unwrap();
+        store.insert(user("bob"), "tester").unwrap();
+        store.insert(user("carol"), "tester").unwrap();
+        store.remove(1, None, "tester").unwrap();
+        store.update(2, 1, user("dave"), "tester").unwrap();
+    }
+
+    let store = FileStore::open(&path).unwrap();
+    assert_eq!(store.get(0).unwrap(), Some(alice));
+    assert_eq!(name(&store, 1), None);
+    assert_eq!(name(&store, 2).as_deref(), Some("dave"));
+    // New users continue after the highest id ever used.
+    assert_eq!(store.insert(user("erin"), "tester").unwrap(), 3);
+    assert!(store.deleted(1).unwrap());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn deleting_the_newest_user_does_not_free_its_id() {
+    let path = temp_log();
+    {
+        let store = FileStore::open(&path).unwrap();
+        store.insert(user("a"), "tester").unwrap();
+        store.insert(user("b"), "tester").unwrap();
+        store.remove(1, None, "tester").unwrap();
+    }
+
+    let store = FileStore::open(&path).unwrap();
+    assert!(store.deleted(1).unwrap());
+    assert_eq!(store.insert(user("c"), "tester").unwrap(), 2);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn torn_last_line_is_dropped() {
+    let path = temp_log();
+    FileStore::open(&path).unwrap().insert(user("a"), "tester").unwrap();
+    let mut log = fs::read(&path).unwrap();
+    let complete = log.len();
+    log.extend_from_slice(br#"{"op":"insert","id":1,"us"#);
+    fs::write(&path, log).unwrap();
+
+    let store = FileStore::open(&path).unwrap();
+    assert_eq!(name(&store, 1), None);
+    assert_eq!(fs::metadata(&path).unwrap().len() as usize, complete);
+    assert_eq!(store.insert(user("b"), "tester").unwrap(), 1);
+    assert_eq!(name(&FileStore::open(&path).unwrap(), 1).as_deref(), Some("b"));
+    fs::remove_file(path).unwrap();
+}
+
+/// Set for the child process that [`failed_append_leaves_the_log_readable`]
+/// runs, to the log it should use.
+const FAILED_APPEND_LOG: &str = "FAILED_APPEND_LOG";
+
+/// Lowers this process's file size limit, returning the old one.
+#[cfg(unix)]
+fn set_file_size_limit(bytes: u64) -> u64 {
+    unsafe {
+        // Hitting the limit is then an error from write, not a signal.
+        libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+        let mut limit: libc::rlimit = std::mem::zeroed();
+        assert_eq!(libc::getrlimit(libc::RLIMIT_FSIZE, &mut limit), 0);
+        let old = limit.rlim_cur;
+        limit.rlim_cur = bytes as libc::rlim_t;
+        assert_eq!(libc::setrlimit(libc::RLIMIT_FSIZE, &limit), 0);
+        old as u64
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_append_leaves_the_log_readable() {
+    if let Some(path) = std::env::var_os(FAILED_APPEND_LOG) {
+        // The limit is per process, so this half runs on its own: an append
+        // that stops partway, as on a full disk, then one that works.
+        let store = FileStore::open(&path).unwrap();
+        store.insert(user("a"), "tester").unwrap();
+        let old = set_file_size_limit(fs::metadata(&path).unwrap().len() + 10);
+        assert!(store.insert(user("b"), "tester").is_err());
+        set_file_size_limit(old);
+        store.insert(user("c"), "tester").unwrap();
+        return;
+    }
+
+    let path = temp_log();
+    let child = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "failed_append_leaves_the_log_readable", "--nocapture"])
+        .env(FAILED_APPEND_LOG, &path)
+        .output()
+        .unwrap();
+    assert!(child.status.success(), "{}", String::from_utf8_lossy(&child.stdout));
+
+    let store = FileStore::open(&path).unwrap();
+    assert_eq!(name(&store, 0).as_deref(), Some("a"));
+    // The failed insert never happened, so its id went to the next one.
+    assert_eq!(name(&store, 1).as_deref(), Some("c"));
+    assert_eq!(name(&store, 2), None);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn corrupt_log_is_an_error() {
+    const INSERT: &str = r#"{"at":"2024-01-01T00:00:00Z","actor":"tester","op":"insert","id":0,"user":{"name":"a","email":"a@example.com","created_at":"2024-01-01T00:00:00Z","updated_at":"2024-01-01T00:00:00Z","version":1}}"#;
+    let path = temp_log();
+
+    fs::write(&path, format!("{}\nnot json\n", INSERT)).unwrap();
+    match FileStore::open(&path) {
+        Err(StoreError::Corrupt { line: 2, .. }) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("corrupt log opened"),
+    }
+
+    fs::write(&path, format!("{}\n{{\"at\":\"2024-01-01T00:00:00Z\",\"actor\":\"tester\",\"op\":\"delete\",\"id\":1}}\n", INSERT)).unwrap();
+    assert!(matches!(FileStore::open(&path), Err(StoreError::Corrupt { line: 2, .. })));
+
+    fs::write(&path, format!("{}\n", INSERT)).unwrap();
+    assert_eq!(name(&FileStore::open(&path).unwrap(), 0).as_deref(), Some("a"));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn file_store_replays_the_audit_log() {
+    let path = temp_log();
+    {
+        let store = FileStore::open(&path).unwrap();
+        store.insert(user("a"), "alice").unwrap();
+        store.apply_all(vec![Change::Insert(user("b")), Change::Insert(user("c"))], "bob").unwrap();
+        store.update(0, 1, user("d"), "carol").unwrap();
+        store.remove(1, None, "alice").unwrap();
+    }
+
+    let store = FileStore::open(&path).unwrap();
+    let feed = store.changes(0, 10).unwrap();
+    let summary: Vec<_> = feed.iter().map(|c| (c.seq, c.id, c.kind, c.actor.as_str())).collect();
+    assert_eq!(
+        summary,
+        [
+            (1, 0, ChangeKind::Created, "alice"),
+            (2, 1, ChangeKind::Created, "bob"),
+            (3, 2, ChangeKind::Created, "bob"),
+            (4, 0, ChangeKind::Updated, "carol"),
+            (5, 1, ChangeKind::Deleted, "alice"),
+        ]
+    );
+    assert_eq!(store.changes(3, 1).unwrap(), feed[3..4]);
+    assert!(store.changes(5, 10).unwrap().is_empty());
+
+    let history = store.history(0).unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[1].before.as_ref().map(|u| u.name.as_str()), Some("a"));
+    assert_eq!(history[1].after.as_ref().map(|u| u.name.as_str()), Some("d"));
+    assert!(store.history(9).unwrap().is_empty());
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,76 @@
+use chrono::{TimeZone, Utc};
+use hyper_microservice_rest::user::{self, User, UserData, UserInput};
+
+#[test]
+fn parses_a_valid_body() {
+    let input = UserInput::from_json(br#"{"name": " Ada ", "email": "ada@example.com"}"#).unwrap();
+    assert_eq!(input.name, " Ada ");
+    assert_eq!(UserData::new(input, Utc::now()).name, "Ada");
+}
+
+#[test]
+fn describes_what_is_wrong() {
+    let cases: [(&[u8], &str); 7] = [
+        (br#"{"name": "Ada"}"#, "missing field `email`"),
+        (br#"{"name": "Ada", "email": "a@b", "admin": true}"#, "unknown field `admin`"),
+        (br#"{"name": 1, "email": "a@b"}"#, "invalid type: integer `1`, expected a string"),
+        (br#"{"name": "Ada", "#, "not valid JSON"),
+        (b"", "not valid JSON"),
+        (br#"{"name": "  ", "email": "a@b"}"#, "`name` must not be empty"),
+        (br#"{"name": "Ada", "email": "ada"}"#, "is not an email address"),
+    ];
+    for (body, expected) in cases.iter() {
+        let error = UserInput::from_json(body).unwrap_err();
+        assert!(error.contains(expected), "{:?} gave {:?}", String::from_utf8_lossy(body), error);
+    }
+}
+
+#[test]
+fn replacing_keeps_the_creation_time_and_bumps_the_version() {
+    let created = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    let updated = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+    let input = |name: &str| UserInput {
+        name: name.to_string(),
+        email: "x@example.com".to_string(),
+    };
+
+    let user = UserData::new(input("old"), created).replaced(input("new"), updated);
+    assert_eq!(user.name, "new");
+    assert_eq!(user.created_at, created);
+    assert_eq!(user.updated_at, updated);
+    assert_eq!(user.version, 2);
+}
+
+#[test]
+fn responses_include_the_id() {
+    let created = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    let data = UserData::new(
+        UserInput {
+            name: "Ada".to_string(),
+            email: "ada@example.com".to_string(),
+        },
+        created,
+    );
+    let json = serde_json::to_value(User { id: 7, data: &data }).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "id": 7,
+            "name": "Ada",
+            "email": "ada@example.com",
+            "created_at": "2024-01-01T00:00:00Z",
+            "updated_at": "2024-01-01T00:00:00Z",
+            "version": 1,
+        })
+    );
+}
+
+#[test]
+fn ids_have_one_spelling() {
+    assert_eq!(user::parse_id("0"), Some(0));
+    assert_eq!(user::parse_id("42"), Some(42));
+    assert_eq!(user::parse_id("18446744073709551615"), Some(u64::MAX));
+    for bad in ["", "+1", "-1", "01", "1.0", " 1", "1a", "18446744073709551616"].iter() {
+        assert_eq!(user::parse_id(bad), None, "{:?}", bad);
+    }
+}
@@ -0,0 +1,178 @@
+mod harness;
+
+use harness::{delete, get, patch, post, put, request, Harness};
+use http::{Method, StatusCode};
+use hyper_microservice_rest::auth::Authenticator;
+use hyper_microservice_rest::router::Router;
+use hyper_microservice_rest::store::MemoryStore;
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde_json::json;
+use std::sync::Arc;
+use vercel_runtime::Body;
+
+fn ada() -> serde_json::Value {
+    json!({ "name": "Ada", "email": "ada@example.com" })
+}
+
+/// A harness holding Ada as user 0.
+fn with_ada() -> Harness {
+    let app = Harness::new();
+    app.send(post("/user/").json(ada())).expect(StatusCode::CREATED);
+    app
+}
+
+#[test]
+fn create_read_update_delete() {
+    let app = Harness::new();
+
+    let created = app.send(post("/user/").json(ada()));
+    created
+        .expect(StatusCode::CREATED)
+        .expect_header("location", "/user/0")
+        .expect_header("etag", "\"1\"")
+        .expect_header("content-type", "application/json");
+    let user = created.json();
+    assert_eq!(user["id"], 0);
+    assert_eq!(user["name"], "Ada");
+    assert_eq!(user["version"], 1);
+
+    let read = app.send(get("/user/0"));
+    read.expect(StatusCode::OK).expect_header("etag", "\"1\"");
+    assert_eq!(read.json(), user);
+    assert_eq!(app.send(get("/user/")).expect(StatusCode::OK).json()["users"], json!([user]));
+
+    let replaced = app.send(put("/user/0").json(json!({ "name": "Ada L", "email": "ada@example.com" })));
+    replaced.expect(StatusCode::OK).expect_header("etag", "\"2\"");
+    assert_eq!(replaced.json()["name"], "Ada L");
+    assert_eq!(replaced.json()["created_at"], user["created_at"]);
+
+    let patched = app.send(patch("/user/0").body("application/merge-patch+json", r#"{"email": "lovelace@example.com"}"#));
+    patched.expect(StatusCode::OK).expect_header("etag", "\"3\"");
+    assert_eq!(patched.json()["email"], "lovelace@example.com");
+    assert_eq!(patched.json()["name"], "Ada L");
+
+    let deleted = app.send(delete("/user/0"));
+    deleted.expect(StatusCode::NO_CONTENT);
+    assert!(matches!(deleted.response.body(), Body::Empty));
+    app.send(get("/user/0")).expect_problem(StatusCode::GONE, "gone");
+    app.send(delete("/user/0")).expect_problem(StatusCode::GONE, "gone");
+    assert_eq!(app.send(get("/user/")).json()["users"], json!([]));
+}
+
+#[test]
+fn binary_bodies_are_read_like_text() {
+    let app = Harness::new();
+    let body = ada().to_string();
+    app.send(post("/user/").binary("application/json", body.as_bytes()))
+        .expect(StatusCode::CREATED);
+}
+
+#[test]
+fn unknown_resources() {
+    let app = with_ada();
+    app.send(get("/user/7")).expect_problem(StatusCode::NOT_FOUND, "not-found");
+    app.send(get("/user/abc")).expect_problem(StatusCode::NOT_FOUND, "not-found");
+    app.send(get("/user/007")).expect_problem(StatusCode::NOT_FOUND, "not-found");
+    app.send(get("/nowhere")).expect_problem(StatusCode::NOT_FOUND, "not-found");
+    app.send(put("/user/7").json(ada())).expect_problem(StatusCode::NOT_FOUND, "not-found");
+    app.send(delete("/user/7")).expect_problem(StatusCode::NOT_FOUND, "not-found");
+
+    let problem = app.send(get("/user/7")).json();
+    assert_eq!(problem["instance"], "/user/7");
+    assert_eq!(problem["detail"], "there is no user with id 7");
+}
+
+#[test]
+fn unsupported_methods() {
+    let app = with_ada();
+    app.send(delete("/user/"))
+        .expect_problem(StatusCode::METHOD_NOT_ALLOWED, "method-not-allowed")
+        .expect_header("allow", "GET, POST");
+    app.send(post("/user/0").json(ada()))
+        .expect_problem(StatusCode::METHOD_NOT_ALLOWED, "method-not-allowed")
+        .expect_header("allow", "GET, PUT, PATCH, DELETE");
+    app.send(request(Method::OPTIONS, "/"))
+        .expect_problem(StatusCode::METHOD_NOT_ALLOWED, "method-not-allowed")
+        .expect_header("allow", "GET");
+}
+
+#[test]
+fn bad_bodies() {
+    let app = with_ada();
+    let cases = [
+        (post("/user/").body("application/json", "{"), StatusCode::BAD_REQUEST, "invalid-body"),
+        (post("/user/").json(json!({ "name": "Ada" })), StatusCode::BAD_REQUEST, "invalid-body"),
+        (post("/user/").json(json!({ "name": "Ada", "email": "ada@example.com", "admin": true })), StatusCode::BAD_REQUEST, "invalid-body"),
+        (post("/user/").json(json!({ "name": "", "email": "ada@example.com" })), StatusCode::BAD_REQUEST, "invalid-body"),
+        (post("/user/").json(json!({ "name": "Ada", "email": "not an email" })), StatusCode::BAD_REQUEST, "invalid-body"),
+        (post("/user/").body("text/plain", "Ada"), StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported-media-type"),
+        (put("/user/0").body("text/plain", "Ada"), StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported-media-type"),
+        (patch("/user/0").json(json!({ "name": "Ada" })), StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported-media-type"),
+        (patch("/user/0").body("application/merge-patch+json", "["), StatusCode::BAD_REQUEST, "invalid-body"),
+        (
+            patch("/user/0").body("application/merge-patch+json", r#"{"email": null}"#),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "unprocessable",
+        ),
+        (
+            patch("/user/0").body("application/json-patch+json", r#"[{"op": "test", "path": "/name", "value": "Grace"}]"#),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "unprocessable",
+        ),
+    ];
+    for (request, status, kind) in cases {
+        app.send(request).expect_problem(status, kind);
+    }
+    // None of that changed Ada.
+    assert_eq!(app.send(get("/user/0")).json()["version"], 1);
+}
+
+#[test]
+fn bad_queries_and_accept_headers() {
+    let app = with_ada();
+    for query in &["limit=0", "sort=age", "cursor=zzz", "created_after=yesterday", "colour=red"] {
+        app.send(get(&format!("/user/?{}", query)))
+            .expect_problem(StatusCode::BAD_REQUEST, "invalid-query");
+    }
+    app.send(get("/changes?since=-1")).expect_problem(StatusCode::BAD_REQUEST, "invalid-query");
+    app.send(get("/users/export").header("accept", "application/xml"))
+        .expect_problem(StatusCode::NOT_ACCEPTABLE, "not-acceptable");
+}
+
+#[test]
+fn conditional_requests() {
+    let app = with_ada();
+    app.send(get("/user/0").header("if-none-match", "\"1\""))
+        .expect(StatusCode::NOT_MODIFIED)
+        .expect_header("etag", "\"1\"");
+    app.send(put("/user/0").header("if-match", "\"9\"").json(ada()))
+        .expect_problem(StatusCode::PRECONDITION_FAILED, "precondition-failed");
+    app.send(delete("/user/0").header("if-match", "\"9\""))
+        .expect_problem(StatusCode::PRECONDITION_FAILED, "precondition-failed");
+    app.send(put("/user/0").header("if-match", "\"1\"").json(ada()))
+        .expect(StatusCode::OK)
+        .expect_header("etag", "\"2\"");
+}
+
+#[test]
+fn authentication_errors() {
+    const SECRET: &[u8] = b"vercel-test-secret";
+    let router = Router::new(Arc::new(MemoryStore::new())).with_auth(Authenticator::new().hs256(SECRET));
+    let app = Harness::with_router(router);
+    let exp = chrono::Utc::now().timestamp() + 3600;
+    let token = |roles: serde_json::Value| {
+        let token = encode(&Header::default(), &json!({ "roles": roles, "exp": exp }), &EncodingKey::from_secret(SECRET)).unwrap();
+        format!("Bearer {}", token)
+    };
+
+    app.send(get("/user/"))
+        .expect_problem(StatusCode::UNAUTHORIZED, "unauthorized")
+        .expect_header("www-authenticate", "Bearer");
+    app.send(get("/user/").header("authorization", "Bearer nonsense"))
+        .expect_problem(StatusCode::UNAUTHORIZED, "unauthorized");
+    app.send(post("/user/").header("authorization", &token(json!(["read-only"]))).json(ada()))
+        .expect_problem(StatusCode::FORBIDDEN, "forbidden");
+    app.send(post("/user/").header("authorization", &token(json!(["editor"]))).json(ada()))
+        .expect(StatusCode::CREATED);
+    app.send(get("/")).expect(StatusCode::OK).expect_header("content-type", "text/html; charset=utf-8");
+}
@@ -0,0 +1,133 @@
+mod harness;
+
+use harness::{get, patch, post, put, Harness};
+use http::StatusCode;
+use hyper_microservice_rest::version::{V1_DEPRECATION, V1_SUNSET};
+use serde_json::json;
+
+const V2: &str = "application/vnd.users+json; version=2";
+
+/// A harness holding Ada as user 0 and Grace as user 1.
+fn with_users() -> Harness {
+    let app = Harness::new();
+    for name in &["Ada", "Grace"] {
+        let user = json!({ "name": name, "email": format!("{}@example.com", name.to_lowercase()) });
+        app.send(post("/user/").json(user)).expect(StatusCode::CREATED);
+    }
+    app
+}
+
+#[test]
+fn version_1_is_the_default_and_deprecated() {
+    let app = with_users();
+    for uri in &["/user/0", "/v1/user/0"] {
+        let reply = app.send(get(uri));
+        reply
+            .expect(StatusCode::OK)
+            .expect_header("content-type", "application/json")
+            .expect_header("deprecation", V1_DEPRECATION)
+            .expect_header("sunset", V1_SUNSET);
+        assert_eq!(reply.json()["name"], "Ada");
+        assert!(reply.json().get("display_name").is_none());
+    }
+    assert_eq!(app.send(get("/user/0")).header("vary"), Some("Accept"));
+    assert_eq!(app.send(get("/v1/user/0")).header("vary"), None);
+    assert_eq!(app.send(get("/changes")).header("deprecation"), None);
+}
+
+#[test]
+fn version_2_by_path_and_by_accept() {
+    let app = with_users();
+    let by_path = app.send(get("/v2/user/0"));
+    by_path.expect(StatusCode::OK).expect_header("content-type", "application/json");
+    assert_eq!(by_path.header("deprecation"), None);
+    assert_eq!(by_path.json()["display_name"], "Ada");
+    assert!(by_path.json().get("name").is_none());
+
+    let by_accept = app.send(get("/user/0").header("accept", V2));
+    by_accept
+        .expect(StatusCode::OK)
+        .expect_header("content-type", V2)
+        .expect_header("vary", "Accept");
+    assert_eq!(by_accept.json(), by_path.json());
+
+    let preferred = app.send(get("/user/0").header("accept", "application/vnd.users+json; version=1; q=0.5, application/vnd.users+json; version=2"));
+    assert_eq!(preferred.json()["display_name"], "Ada");
+}
+
+#[test]
+fn version_2_writes() {
+    let app = with_users();
+    let created = app.send(post("/v2/user/").json(json!({ "display_name": "Edsger", "email": "edsger@example.com" })));
+    created.expect(StatusCode::CREATED).expect_header("location", "/v2/user/2");
+    assert_eq!(created.json()["display_name"], "Edsger");
+    assert_eq!(app.send(get("/user/2")).json()["name"], "Edsger");
+
+    let replaced = app.send(put("/v2/user/2").json(json!({ "display_name": "E. W. Dijkstra", "email": "edsger@example.com" })));
+    assert_eq!(replaced.expect(StatusCode::OK).json()["display_name"], "E. W. Dijkstra");
+
+    let merged = app.send(patch("/v2/user/2").body("application/merge-patch+json", r#"{"display_name": "Dijkstra"}"#));
+    assert_eq!(merged.expect(StatusCode::OK).json()["display_name"], "Dijkstra");
+
+    let patched = app.send(
+        patch("/user/2")
+            .header("accept", V2)
+            .body("application/json-patch+json", r#"[{"op": "replace", "path": "/display_name", "value": "Edsger"}]"#),
+    );
+    patched.expect(StatusCode::OK).expect_header("etag", "\"4\"");
+    assert_eq!(patched.json()["display_name"], "Edsger");
+
+    // Entity tags are the same in both versions.
+    app.send(put("/v2/user/2").header("if-match", "\"4\"").json(json!({ "display_name": "EWD", "email": "edsger@example.com" })))
+        .expect(StatusCode::OK);
+
+    let history = app.send(get("/v2/user/2/history")).json();
+    assert_eq!(history[0]["after"]["display_name"], "Edsger");
+    assert_eq!(history[1]["before"]["display_name"], "Edsger");
+}
+
+#[test]
+fn version_1_names_are_rejected_in_version_2() {
+    let app = with_users();
+    app.send(post("/v2/user/").json(json!({ "name": "Edsger", "email": "edsger@example.com" })))
+        .expect_problem(StatusCode::BAD_REQUEST, "invalid-body");
+    app.send(patch("/v2/user/0").body("application/json-patch+json", r#"[{"op": "remove", "path": "/name"}]"#))
+        .expect_problem(StatusCode::BAD_REQUEST, "invalid-body");
+    app.send(get("/v2/user/?sort=-name")).expect_problem(StatusCode::BAD_REQUEST, "invalid-query");
+    // And the other way round.
+    app.send(post("/user/").json(json!({ "display_name": "Edsger", "email": "edsger@example.com" })))
+        .expect_problem(StatusCode::BAD_REQUEST, "invalid-body");
+}
+
+#[test]
+fn version_2_lists() {
+    let app = with_users();
+    let page = app.send(get("/v2/user/?sort=-display_name&limit=1"));
+    page.expect(StatusCode::OK);
+    let body = page.json();
+    assert_eq!(body["users"][0]["display_name"], "Grace");
+    let next = body["next"].as_str().unwrap();
+    assert!(next.starts_with("/v2/user/?"), "{}", next);
+    assert!(next.contains("sort=-display_name"), "{}", next);
+    assert!(page.header("link").unwrap().contains(&format!("<{}>", next)));
+
+    let rest = app.send(get(next));
+    assert_eq!(rest.expect(StatusCode::OK).json()["users"][0]["display_name"], "Ada");
+
+    let filtered = app.send(get("/user/?display_name=Ada").header("accept", V2));
+    assert_eq!(filtered.expect(StatusCode::OK).json()["users"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn unsupported_versions_are_not_acceptable() {
+    let app = with_users();
+    let reply = app.send(get("/v3/user/0"));
+    reply.expect_problem(StatusCode::NOT_ACCEPTABLE, "not-acceptable");
+    assert_eq!(reply.json()["instance"], "/v3/user/0");
+    app.send(get("/user/0").header("accept", "application/vnd.users+json; version=3"))
+        .expect_problem(StatusCode::NOT_ACCEPTABLE, "not-acceptable");
+    // Plain JSON is still on offer.
+    let fallback = app.send(get("/user/0").header("accept", "application/vnd.users+json; version=3, application/json; q=0.1"));
+    assert_eq!(fallback.expect(StatusCode::OK).json()["name"], "Ada");
+    app.send(get("/v2/nowhere")).expect_problem(StatusCode::NOT_FOUND, "not-found");
+}
@@ -0,0 +1,405 @@
+use chrono::{DateTime, Duration as Span, Utc};
+mod harness;
+
+use harness::{delete, get, post, request, Harness};
+use http::{Method, StatusCode};
+use hyper_microservice_rest::auth::Authenticator;
+use hyper_microservice_rest::router::Router;
+use hyper_microservice_rest::store::{MemoryStore, StoreConfig};
+use hyper_microservice_rest::webhook::{self, Retry, Webhooks};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const SECRET: &str = "a-long-enough-secret";
+
+/// A request the listener received.
+struct Received {
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+/// A local HTTP server that records what it is sent and answers with
+/// whatever status is set.
+struct Listener {
+    url: String,
+    status: Arc<Mutex<u16>>,
+    /// How long to wait before answering.
+    delay: Arc<Mutex<Duration>>,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl Listener {
+    fn start() -> Listener {
+        let socket = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", socket.local_addr().unwrap());
+        let status = Arc::new(Mutex::new(200));
+        let delay = Arc::new(Mutex::new(Duration::ZERO));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let (answer, wait, record) = (status.clone(), delay.clone(), received.clone());
+        thread::spawn(move || {
+            for stream in socket.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut headers = HashMap::new();
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(": ") {
+                        Some((name, value)) => headers.insert(name.to_ascii_lowercase(), value.to_string()),
+                        None => break,
+                    };
+                }
+                let len = headers.get("content-length").map_or(0, |len| len.parse().unwrap());
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                record.lock().unwrap().push(Received {
+                    headers,
+                    body: String::from_utf8(body).unwrap(),
+                });
+                thread::sleep(*wait.lock().unwrap());
+                let status = *answer.lock().unwrap();
+                write!(stream, "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            }
+        });
+        Listener {
+            url,
+            status,
+            delay,
+            received,
+        }
+    }
+
+    fn answer(&self, status: u16) {
+        *self.status.lock().unwrap() = status;
+    }
+
+    fn slow(&self, delay: Duration) {
+        *self.delay.lock().unwrap() = delay;
+    }
+
+    fn count(&self) -> usize {
+        self.received.lock().unwrap().len()
+    }
+}
+
+fn setup(retry: Retry) -> (Harness, Arc<Webhooks>) {
+    let users = Arc::new(MemoryStore::new());
+    let webhooks = Arc::new(Webhooks::new(users.clone(), retry));
+    (Harness::with_router(Router::new(users).with_webhooks(webhooks.clone())), webhooks)
+}
+
+fn register(app: &Harness, url: &str, events: Value) -> Value {
+    let body = json!({ "url": url, "secret": SECRET, "events": events });
+    app.send(post("/webhooks").json(body)).expect(StatusCode::CREATED).json()
+}
+
+fn create_user(app: &Harness, name: &str) {
+    let body = json!({ "name": name, "email": format!("{}@example.com", name) });
+    app.send(post("/user/").json(body)).expect(StatusCode::CREATED);
+}
+
+fn at(seconds: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).unwrap() + Span::seconds(seconds)
+}
+
+#[test]
+fn delivers_signed_changes() {
+    let listener = Listener::start();
+    let (app, webhooks) = setup(Retry::default());
+    create_user(&app, "before");
+    let hook = register(&app, &listener.url, json!([]));
+    assert_eq!(hook["url"], listener.url.as_str());
+    assert!(hook.get("secret").is_none());
+
+    create_user(&app, "ada");
+    assert_eq!(webhooks.run(Utc::now()).unwrap(), 1);
+    let received = listener.received.lock().unwrap();
+    assert_eq!(received.len(), 1, "changes from before the webhook are not sent");
+    let request = &received[0];
+    assert_eq!(request.headers[webhook::SIGNATURE_HEADER], webhook::sign(SECRET, request.body.as_bytes()));
+    assert_eq!(request.headers[webhook::EVENT_HEADER], "user.created");
+    let payload: Value = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(payload["event"], "user.created");
+    assert_eq!(payload["change"]["after"]["name"], "ada");
+    assert_eq!(payload["change"]["seq"], 2);
+    assert_eq!(request.headers[webhook::DELIVERY_HEADER], payload["delivery"].to_string());
+
+    let uri = format!("/webhooks/deliveries/{}", payload["delivery"]);
+    let delivery = app.send(get(&uri)).expect(StatusCode::OK).json();
+    assert_eq!(delivery["status"], "delivered");
+    assert_eq!(delivery["attempts"], 1);
+    assert_eq!(webhooks.run(Utc::now()).unwrap(), 0);
+}
+
+#[test]
+fn retries_with_backoff_then_gives_up() {
+    let listener = Listener::start();
+    listener.answer(500);
+    let retry = Retry {
+        first_delay: Duration::from_secs(10),
+        max_delay: Duration::from_secs(30),
+        max_attempts: 4,
+    };
+    let (app, webhooks) = setup(retry);
+    register(&app, &listener.url, json!([]));
+    create_user(&app, "ada");
+
+    // Attempts at 0s, then 10s, 20s and 30s (capped) after each one ends.
+    // Each ends a moment after it starts, so the next is due just after
+    // the whole second.
+    for (now, attempts) in &[(0, 1), (10, 0), (11, 1), (31, 0), (32, 1), (62, 0), (63, 1), (1000, 0)] {
+        assert_eq!(webhooks.run(at(*now)).unwrap(), *attempts, "at {}s", now);
+    }
+    assert_eq!(listener.count(), 4);
+
+    let dead = app.send(get("/webhooks/deliveries?status=dead")).json();
+    assert_eq!(dead.as_array().unwrap().len(), 1);
+    assert_eq!(dead[0]["attempts"], 4);
+    assert_eq!(dead[0]["last_error"], "the receiver answered 500");
+    assert!(dead[0].get("next_attempt_at").is_none());
+
+    listener.answer(204);
+    let retry_uri = format!("/webhooks/deliveries/{}/retry", dead[0]["id"]);
+    let queued = app.send(post(&retry_uri)).expect(StatusCode::ACCEPTED).json();
+    assert_eq!(queued["status"], "pending");
+    assert_eq!(webhooks.run(Utc::now()).unwrap(), 1);
+    assert_eq!(webhooks.deliveries(None, None)[0].status, webhook::DeliveryStatus::Delivered);
+    app.send(post(&retry_uri)).expect(StatusCode::CONFLICT);
+
+    let dead = app.send(get("/webhooks/deliveries?status=dead")).json();
+    assert_eq!(dead, json!([]));
+}
+
+#[test]
+fn unreachable_receivers_are_retried() {
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", closed.local_addr().unwrap());
+    drop(closed);
+    let (app, webhooks) = setup(Retry::default());
+    register(&app, &url, json!([]));
+    create_user(&app, "ada");
+    assert_eq!(webhooks.run(at(0)).unwrap(), 1);
+
+    let delivery = &webhooks.deliveries(None, None)[0];
+    assert_eq!(delivery.status, webhook::DeliveryStatus::Pending);
+    let next = delivery.next_attempt_at.unwrap();
+    assert!(at(1) <= next && next < at(2), "{}", next);
+    assert!(delivery.last_error.is_some());
+}
+
+#[test]
+fn a_slow_receiver_holds_up_only_its_own_deliveries() {
+    let (slow, fast) = (Listener::start(), Listener::start());
+    slow.slow(Duration::from_millis(500));
+    slow.answer(500);
+    let (app, webhooks) = setup(Retry::default());
+    // Registered first, so its delivery comes first.
+    register(&app, &slow.url, json!([]));
+    register(&app, &fast.url, json!([]));
+    create_user(&app, "ada");
+    create_user(&app, "grace");
+
+    // Two deliveries each; the slow receiver fails its first and is not
+    // sent its second until the next round.
+    assert_eq!(webhooks.run(at(0)).unwrap(), 3);
+    assert_eq!((slow.count(), fast.count()), (1, 2));
+
+    let deliveries = webhooks.deliveries(None, None);
+    let (failed, waiting) = (&deliveries[0], &deliveries[2]);
+    assert_eq!(failed.attempts, 1);
+    assert_eq!(waiting.attempts, 0);
+    assert_eq!(waiting.next_attempt_at, Some(at(0)));
+    assert_eq!(waiting.leased_until, None, "what was not attempted is free for the next round");
+    for delivered in &deliveries[1..] {
+        if delivered.status == webhook::DeliveryStatus::Delivered {
+            assert!(delivered.delivered_at.unwrap() < at(0) + Span::milliseconds(400), "{:?}", delivered);
+        }
+    }
+    // The retry waits from when the slow attempt ended, not from `now`.
+    let next = failed.next_attempt_at.unwrap();
+    assert!(at(1) + Span::milliseconds(500) <= next, "{}", next);
+}
+
+#[test]
+fn overlapping_runs_send_each_delivery_once() {
+    let listener = Listener::start();
+    listener.slow(Duration::from_millis(300));
+    let (app, webhooks) = setup(Retry::default());
+    register(&app, &listener.url, json!([]));
+    create_user(&app, "ada");
+
+    // A timer round and a cron call, say, both finding the delivery due.
+    let runs: Vec<_> = (0..3)
+        .map(|_| {
+            let webhooks = webhooks.clone();
+            thread::spawn(move || webhooks.run(at(0)).unwrap())
+        })
+        .collect();
+    let attempts: usize = runs.into_iter().map(|run| run.join().unwrap()).sum();
+    assert_eq!(attempts, 1);
+    assert_eq!(listener.count(), 1);
+
+    let delivery = &webhooks.deliveries(None, None)[0];
+    assert_eq!(delivery.status, webhook::DeliveryStatus::Delivered);
+    assert_eq!(delivery.leased_until, None);
+}
+
+#[test]
+fn webhooks_only_get_the_events_they_ask_for() {
+    let listener = Listener::start();
+    let (app, webhooks) = setup(Retry::default());
+    let hook = register(&app, &listener.url, json!(["deleted"]));
+    create_user(&app, "ada");
+    app.send(delete("/user/0")).expect(StatusCode::NO_CONTENT);
+    assert_eq!(webhooks.run(Utc::now()).unwrap(), 1);
+    assert_eq!(listener.received.lock().unwrap()[0].headers[webhook::EVENT_HEADER], "user.deleted");
+
+    let hooks = app.send(get("/webhooks")).json();
+    assert_eq!(hooks.as_array().unwrap().len(), 1);
+    let uri = format!("/webhooks/{}", hook["id"]);
+    app.send(delete(&uri)).expect(StatusCode::NO_CONTENT);
+    app.send(delete(&uri)).expect(StatusCode::NOT_FOUND);
+    assert!(webhooks.deliveries(None, None).is_empty());
+}
+
+#[test]
+fn run_makes_the_attempts_that_are_due() {
+    let listener = Listener::start();
+    let (app, _) = setup(Retry::default());
+    register(&app, &listener.url, json!([]));
+    create_user(&app, "ada");
+    create_user(&app, "grace");
+    assert_eq!(app.send(post("/webhooks/run")).expect(StatusCode::OK).json(), json!({ "attempts": 2 }));
+    assert_eq!(app.send(post("/webhooks/run")).expect(StatusCode::OK).json(), json!({ "attempts": 0 }));
+    assert_eq!(listener.count(), 2);
+    app.send(get("/webhooks/run")).expect(StatusCode::METHOD_NOT_ALLOWED);
+    app.send(delete("/webhooks/run")).expect(StatusCode::METHOD_NOT_ALLOWED);
+    // There is no cron secret, so there is no cron endpoint.
+    app.send(get("/webhooks/cron")).expect(StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn the_cron_secret_runs_deliveries_without_a_token() {
+    let listener = Listener::start();
+    let users = Arc::new(MemoryStore::new());
+    let webhooks = Arc::new(Webhooks::new(users.clone(), Retry::default()));
+    let open = Harness::with_router(Router::new(users.clone()).with_webhooks(webhooks.clone()));
+    register(&open, &listener.url, json!([]));
+    create_user(&open, "ada");
+    create_user(&open, "grace");
+
+    let app = Harness::with_router(
+        Router::new(users)
+            .with_webhooks(webhooks)
+            .with_auth(Authenticator::new().hs256(b"a-jwt-signing-key"))
+            .with_cron_secret("the-cron-secret".to_string()),
+    );
+    let call = |method: Method, uri: &str, token: &str| {
+        app.send(request(method, uri).header("authorization", &format!("Bearer {}", token)))
+    };
+    app.send(get("/webhooks/cron")).expect(StatusCode::UNAUTHORIZED);
+    call(Method::GET, "/webhooks/cron", "the-cron-secreT").expect(StatusCode::UNAUTHORIZED);
+    call(Method::POST, "/webhooks/run", "the-cron-secre").expect(StatusCode::UNAUTHORIZED);
+    assert_eq!(listener.count(), 0);
+
+    let cron = call(Method::GET, "/webhooks/cron", "the-cron-secret");
+    assert_eq!(cron.expect(StatusCode::OK).json(), json!({ "attempts": 2 }));
+    let run = call(Method::POST, "/webhooks/run", "the-cron-secret");
+    assert_eq!(run.expect(StatusCode::OK).json(), json!({ "attempts": 0 }));
+    assert_eq!(listener.count(), 2);
+    // The secret is only good for running the deliveries.
+    call(Method::GET, "/webhooks", "the-cron-secret").expect(StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn the_file_backend_keeps_webhooks_and_deliveries() {
+    let path = std::env::temp_dir().join(format!("users-webhooks-{}.ndjson", process::id()));
+    let webhook_log = path.with_extension("webhooks.ndjson");
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(&webhook_log);
+    let config = StoreConfig::File(path.clone());
+    let open = || {
+        let users = config.open().unwrap();
+        let webhooks = Arc::new(config.open_webhooks(users.clone(), Retry::default()).unwrap());
+        (Harness::with_router(Router::new(users).with_webhooks(webhooks.clone())), webhooks)
+    };
+
+    let listener = Listener::start();
+    listener.answer(500);
+    let (app, webhooks) = open();
+    let hook = register(&app, &listener.url, json!([]));
+    let removed = register(&app, &listener.url, json!([]));
+    let uri = format!("/webhooks/{}", removed["id"]);
+    app.send(delete(&uri)).expect(StatusCode::NO_CONTENT);
+    create_user(&app, "ada");
+    create_user(&app, "grace");
+    assert_eq!(webhooks.run(at(0)).unwrap(), 1);
+    drop((app, webhooks));
+
+    let (app, webhooks) = open();
+    let hooks = app.send(get("/webhooks")).json();
+    assert_eq!(hooks, json!([hook]));
+    let deliveries = webhooks.deliveries(None, None);
+    assert_eq!(deliveries.len(), 2, "the changes were not queued twice");
+    assert_eq!(deliveries[0].attempts, 1);
+    assert_eq!(deliveries[0].last_error.as_deref(), Some("the receiver answered 500"));
+    assert_eq!(register(&app, "https://example.com", json!([]))["id"], 2, "ids are not reused");
+
+    listener.answer(200);
+    assert_eq!(webhooks.run(at(10)).unwrap(), 2);
+    let received = listener.received.lock().unwrap();
+    let (first, last) = (&received[0], &received[1]);
+    assert_eq!(first.body, last.body, "a retry sends the same body");
+    assert_eq!(last.headers[webhook::SIGNATURE_HEADER], webhook::sign(SECRET, last.body.as_bytes()));
+    drop(received);
+    drop((app, webhooks));
+
+    let (_, webhooks) = open();
+    assert!(webhooks.deliveries(None, None).iter().all(|d| d.status == webhook::DeliveryStatus::Delivered));
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&webhook_log).unwrap();
+}
+
+#[test]
+fn registration_is_validated() {
+    let (app, _) = setup(Retry::default());
+    let cases = [
+        (json!({ "url": "ftp://example.com", "secret": SECRET }), StatusCode::UNPROCESSABLE_ENTITY),
+        (json!({ "url": "http://", "secret": SECRET }), StatusCode::UNPROCESSABLE_ENTITY),
+        (json!({ "url": "https:///hook", "secret": SECRET }), StatusCode::UNPROCESSABLE_ENTITY),
+        (json!({ "url": "https://example.com/a hook", "secret": SECRET }), StatusCode::UNPROCESSABLE_ENTITY),
+        (json!({ "url": "/hook", "secret": SECRET }), StatusCode::UNPROCESSABLE_ENTITY),
+        (json!({ "url": "https://example.com", "secret": "short" }), StatusCode::UNPROCESSABLE_ENTITY),
+        (json!({ "url": "https://example.com" }), StatusCode::BAD_REQUEST),
+        (json!({ "url": "https://example.com", "secret": SECRET, "events": ["renamed"] }), StatusCode::BAD_REQUEST),
+    ];
+    for (body, status) in &cases {
+        app.send(post("/webhooks").json(body.clone())).expect(*status);
+    }
+    app.send(get("/webhooks/deliveries?status=lost")).expect(StatusCode::BAD_REQUEST);
+    app.send(get("/webhooks/deliveries/9")).expect(StatusCode::NOT_FOUND);
+    app.send(get("/webhooks/x")).expect(StatusCode::NOT_FOUND);
+
+    Harness::new().send(get("/webhooks")).expect(StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn backoff_doubles_up_to_the_cap() {
+    let retry = Retry {
+        first_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(60),
+        max_attempts: 100,
+    };
+    let delays: Vec<u64> = (1..=8).map(|attempts| retry.delay(attempts).as_secs()).collect();
+    assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+    assert_eq!(retry.delay(99).as_secs(), 60);
+}
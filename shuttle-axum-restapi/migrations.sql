@@ -1,6 +0,0 @@
--- migrations.sql
-CREATE TABLE IF NOT EXISTS users (
-    id serial primary key,
-    name varchar not null,
-    age int not null
-);
\ No newline at end of file
@@ -0,0 +1,7 @@
+-- `IF NOT EXISTS` lets databases created before migrations were tracked
+-- adopt this one without losing their rows.
+CREATE TABLE IF NOT EXISTS users (
+    id serial primary key,
+    name varchar not null,
+    age int not null
+);
@@ -0,0 +1,16 @@
+ALTER TABLE users
+    DROP CONSTRAINT users_name_length,
+    DROP CONSTRAINT users_name_trimmed,
+    DROP CONSTRAINT users_age_range;
+
+-- Put back the names and ages the up migration fixed, unless they have
+-- changed since.
+UPDATE users SET name = fixed.name
+    FROM users_before_checks AS fixed
+    WHERE users.id = fixed.id AND users.name = fixed.fixed_name;
+
+UPDATE users SET age = fixed.age
+    FROM users_before_checks AS fixed
+    WHERE users.id = fixed.id AND users.age = fixed.fixed_age;
+
+DROP TABLE users_before_checks;
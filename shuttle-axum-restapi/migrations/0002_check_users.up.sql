@@ -0,0 +1,22 @@
+-- The rules `UserSubmission` and `UpdateRecord` validate, kept by the
+-- database too. The API used to take any name and age, so rows that break
+-- the rules are brought into line first: names are trimmed and cut to 100
+-- characters, an empty one becomes `user <id>`, and ages are clamped to
+-- 0 to 150. The originals, and what they became, are kept in
+-- `users_before_checks` for the down migration.
+CREATE TABLE users_before_checks AS
+    SELECT id, name, age,
+        coalesce(nullif(btrim(left(btrim(name, E' \t\r\n'), 100), E' \t\r\n'), ''), 'user ' || id) AS fixed_name,
+        least(greatest(age, 0), 150) AS fixed_age
+    FROM users;
+
+DELETE FROM users_before_checks WHERE name = fixed_name AND age = fixed_age;
+
+UPDATE users SET name = fixed.fixed_name, age = fixed.fixed_age
+    FROM users_before_checks AS fixed
+    WHERE users.id = fixed.id;
+
+ALTER TABLE users
+    ADD CONSTRAINT users_name_length CHECK (char_length(name) BETWEEN 1 AND 100),
+    ADD CONSTRAINT users_name_trimmed CHECK (name = btrim(name, E' \t\r\n')),
+    ADD CONSTRAINT users_age_range CHECK (age BETWEEN 0 AND 150);
@@ -0,0 +1,54 @@
+//! Manage the schema of the database at `DATABASE_URL`.
+//!
+//!     cargo run --bin migrate -- up
+//!     cargo run --bin migrate -- down [VERSION]
+//!     cargo run --bin migrate -- status
+
+use std::{env, process};
+
+use shuttle_axum_restapi::migrate;
+use sqlx::postgres::PgPoolOptions;
+
+const USAGE: &str = "usage: migrate up | down [VERSION] | status";
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let url = env::var("DATABASE_URL").unwrap_or_else(|_| fail("DATABASE_URL is not set"));
+    let db = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&url)
+        .await
+        .unwrap_or_else(|e| fail(&format!("couldn't connect to the database: {e}")));
+
+    let result = match args.as_slice() {
+        ["up"] => migrate::up(&db).await,
+        ["down"] => migrate::down(&db, None).await,
+        ["down", version] => match version.parse() {
+            Ok(version) => migrate::down(&db, Some(version)).await,
+            Err(_) => fail(&format!("not a version: {version}\n{USAGE}")),
+        },
+        ["status"] => {
+            let mut conn = db
+                .acquire()
+                .await
+                .unwrap_or_else(|e| fail(&format!("couldn't connect to the database: {e}")));
+            migrate::status(&mut conn).await.map(|statuses| {
+                for status in statuses {
+                    println!("{status}");
+                }
+            })
+        }
+        _ => fail(USAGE),
+    };
+    if let Err(e) = result {
+        fail(&e.to_string());
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    process::exit(1);
+}
@@ -0,0 +1,160 @@
+//! Failed requests. Every handler error becomes an [`AppError`], which
+//! answers with a status and a JSON body:
+//!
+//! ```json
+//! { "error": "not_found", "message": "user 7 does not exist" }
+//! ```
+//!
+//! Requests that fail validation also say what is wrong with each field:
+//!
+//! ```json
+//! {
+//!   "error": "validation",
+//!   "message": "some fields are invalid",
+//!   "fields": { "age": ["must be from 0 to 150"] }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+
+use axum::extract::rejection::{JsonRejection, PathRejection};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use validator::ValidationErrors;
+
+use crate::repo::RepoError;
+
+#[derive(Debug)]
+pub enum AppError {
+    /// The request could not be read. Says why.
+    BadRequest(String),
+    /// The body was not sent as `application/json`.
+    UnsupportedMediaType(String),
+    /// No such resource. Says which.
+    NotFound(String),
+    /// The change would break a database constraint. Says what kind.
+    Conflict(String),
+    /// A well-formed request with values that are not allowed. Says why.
+    Validation(String),
+    /// Fields that broke their validation rules.
+    Invalid(ValidationErrors),
+    /// Something broke on our side. Logged, never sent.
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<BTreeMap<String, Vec<String>>>,
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Validation(_) | AppError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "bad_request",
+            AppError::UnsupportedMediaType(_) => "unsupported_media_type",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::Validation(_) | AppError::Invalid(_) => "validation",
+            AppError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (message, fields) = match self {
+            AppError::Internal(ref details) => {
+                tracing::error!("{details}");
+                ("something went wrong on our side".to_string(), None)
+            }
+            AppError::Invalid(ref errors) => {
+                let (message, fields) = describe(errors);
+                (message, Some(fields))
+            }
+            AppError::BadRequest(ref message)
+            | AppError::UnsupportedMediaType(ref message)
+            | AppError::NotFound(ref message)
+            | AppError::Conflict(ref message)
+            | AppError::Validation(ref message) => (message.clone(), None),
+        };
+        let body = ErrorBody {
+            error: self.code(),
+            message,
+            fields,
+        };
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+/// The messages for each field, and one for the whole request: what the
+/// struct-level rules said, if they failed, or a summary.
+fn describe(errors: &ValidationErrors) -> (String, BTreeMap<String, Vec<String>>) {
+    let mut whole = Vec::new();
+    let mut fields = BTreeMap::new();
+    for (field, errors) in errors.field_errors() {
+        let messages: Vec<String> = errors
+            .iter()
+            .map(|e| e.message.as_deref().unwrap_or(&e.code).to_string())
+            .collect();
+        // Where validator puts the errors of `#[validate(schema(..))]`.
+        if field == "__all__" {
+            whole = messages;
+        } else {
+            fields.insert(field.into_owned(), messages);
+        }
+    }
+    let message = if whole.is_empty() {
+        "some fields are invalid".to_string()
+    } else {
+        whole.join("; ")
+    };
+    (message, fields)
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        AppError::Invalid(errors)
+    }
+}
+
+impl From<RepoError> for AppError {
+    fn from(e: RepoError) -> Self {
+        match e {
+            RepoError::Conflict(message) => AppError::Conflict(message),
+            RepoError::Database(e) => AppError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            // Valid JSON of the wrong shape, like a string where the age goes.
+            JsonRejection::JsonDataError(e) => AppError::Validation(e.body_text()),
+            JsonRejection::MissingJsonContentType(e) => AppError::UnsupportedMediaType(e.body_text()),
+            other => AppError::BadRequest(other.body_text()),
+        }
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        AppError::BadRequest(rejection.body_text())
+    }
+}
@@ -0,0 +1,63 @@
+//! axum's `Json` and `Path` extractors, rejecting with an [`AppError`] so
+//! that malformed requests get the same JSON error body as everything else,
+//! and [`Valid`] for bodies that must also pass their validation rules.
+
+use axum::async_trait;
+use axum::extract::rejection::{JsonRejection, PathRejection};
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::request::Parts;
+use validator::Validate;
+
+use crate::error::AppError;
+
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for Json<T>
+where
+    axum::Json<T>: FromRequest<S, Rejection = JsonRejection>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::Json(value) = axum::Json::<T>::from_request(req, state).await?;
+        Ok(Json(value))
+    }
+}
+
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for Path<T>
+where
+    axum::extract::Path<T>: FromRequestParts<S, Rejection = PathRejection>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Path(value) = axum::extract::Path::<T>::from_request_parts(parts, state).await?;
+        Ok(Path(value))
+    }
+}
+
+/// A JSON body that has passed [`Validate`]; otherwise 422 with what is
+/// wrong with each field.
+pub struct Valid<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for Valid<T>
+where
+    Json<T>: FromRequest<S, Rejection = AppError>,
+    T: Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        value.validate()?;
+        Ok(Valid(value))
+    }
+}
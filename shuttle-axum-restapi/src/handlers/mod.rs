@@ -1,5 +1,7 @@
 pub mod mock_driver_arrives;
 pub mod mock_driver_leaves;
+pub mod users;
 
 pub use mock_driver_arrives::*;
 pub use mock_driver_leaves::*;
+pub use users::*;
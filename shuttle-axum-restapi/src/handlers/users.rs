@@ -0,0 +1,53 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+
+use crate::error::AppError;
+use crate::extract::{Path, Valid};
+use crate::models::{UpdateRecord, UserSubmission};
+use crate::AppState;
+
+fn not_found(id: i32) -> AppError {
+    AppError::NotFound(format!("user {id} does not exist"))
+}
+
+pub async fn retrieve_all_records(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(axum::Json(state.users.list().await?))
+}
+
+pub async fn retrieve_record_by_id(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.users.get(id).await?.ok_or_else(|| not_found(id))?;
+    Ok(axum::Json(user))
+}
+
+pub async fn create_record(
+    State(state): State<AppState>,
+    Valid(json): Valid<UserSubmission>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.users.create(json).await?;
+    Ok((StatusCode::CREATED, axum::Json(user)))
+}
+
+pub async fn update_record_by_id(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Valid(json): Valid<UpdateRecord>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.users.update(id, json).await?.ok_or_else(|| not_found(id))?;
+    Ok(axum::Json(user))
+}
+
+pub async fn delete_record_by_id(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.users.delete(id).await? {
+        return Err(not_found(id));
+    }
+    Ok(StatusCode::OK)
+}
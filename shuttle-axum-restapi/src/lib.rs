@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use axum::{routing::get, Router};
+
+use repo::UserRepo;
+
+pub mod error;
+pub mod extract;
+pub mod handlers;
+pub mod migrate;
+pub mod models;
+pub mod repo;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub users: Arc<dyn UserRepo>,
+}
+
+impl AppState {
+    pub fn new(users: Arc<dyn UserRepo>) -> Self {
+        Self { users }
+    }
+}
+
+async fn hello_world() -> &'static str {
+    "Hello, world!"
+}
+
+/// The whole service, over whichever store `state` holds.
+pub fn app(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(hello_world))
+        .route("/mock_driver_leaves", get(handlers::mock_driver_leaves))
+        .route("/mock_driver_arrives", get(handlers::mock_driver_arrives))
+        .route("/users", get(handlers::retrieve_all_records).post(handlers::create_record))
+        .route("/users/:id", get(handlers::retrieve_record_by_id)
+               .put(handlers::update_record_by_id)
+               .delete(handlers::delete_record_by_id))
+        .with_state(state)
+}
@@ -0,0 +1,109 @@
+//! Schema migrations.
+//!
+//! Each migration is a numbered pair of files in `migrations/`,
+//! `NNNN_description.up.sql` and `NNNN_description.down.sql`, embedded at
+//! build time. sqlx records what has been applied in `_sqlx_migrations`
+//! along with a checksum of each script, runs every script in its own
+//! transaction, and refuses to go on if an applied script has since changed.
+
+use std::fmt;
+
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::{PgConnection, PgPool};
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Where one migration stands against a database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Applied,
+    Pending,
+    /// Applied, but the script has changed since.
+    Modified,
+    /// Started and did not finish; needs fixing by hand.
+    Failed,
+    /// Applied by a build that had a migration this one does not.
+    Unknown,
+}
+
+#[derive(Debug)]
+pub struct Status {
+    pub version: i64,
+    pub description: String,
+    pub state: State,
+}
+
+/// Applies every pending migration, oldest first.
+pub async fn up(db: &PgPool) -> Result<(), MigrateError> {
+    MIGRATOR.run(db).await
+}
+
+/// Reverts applied migrations newer than `target`, newest first. Without a
+/// target only the latest one is reverted; `Some(0)` reverts them all.
+pub async fn down(db: &PgPool, target: Option<i64>) -> Result<(), MigrateError> {
+    let target = match target {
+        Some(target) => target,
+        None => {
+            let mut conn = db.acquire().await?;
+            conn.ensure_migrations_table().await?;
+            let applied = conn.list_applied_migrations().await?;
+            // The one before the latest, or nothing at all.
+            applied.iter().rev().nth(1).map_or(0, |m| m.version)
+        }
+    };
+    MIGRATOR.undo(db, target).await
+}
+
+/// Every migration this build knows of or the database has seen, by version.
+pub async fn status(conn: &mut PgConnection) -> Result<Vec<Status>, MigrateError> {
+    conn.ensure_migrations_table().await?;
+    let failed = conn.dirty_version().await?;
+    let applied = conn.list_applied_migrations().await?;
+
+    let mut statuses: Vec<Status> = MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| {
+            let state = match applied.iter().find(|a| a.version == m.version) {
+                _ if failed == Some(m.version) => State::Failed,
+                Some(a) if a.checksum != m.checksum => State::Modified,
+                Some(_) => State::Applied,
+                None => State::Pending,
+            };
+            Status {
+                version: m.version,
+                description: m.description.to_string(),
+                state,
+            }
+        })
+        .collect();
+    for a in &applied {
+        if !statuses.iter().any(|s| s.version == a.version) {
+            statuses.push(Status {
+                version: a.version,
+                description: String::new(),
+                state: State::Unknown,
+            });
+        }
+    }
+    statuses.sort_by_key(|s| s.version);
+    Ok(statuses)
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            State::Applied => "applied",
+            State::Pending => "pending",
+            State::Modified => "modified",
+            State::Failed => "failed",
+            State::Unknown => "unknown",
+        })
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04} {:<9} {}", self.version, self.state, self.description)
+    }
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use validator::{Validate, ValidationError};
+
+// The same limits are CHECK constraints on `users`; see
+// migrations/0002_check_users.up.sql.
+
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow, Serialize, Deserialize)]
+pub struct User {
+    pub id: i32,
+    pub name: String,
+    pub age: i32,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UserSubmission {
+    #[serde(deserialize_with = "trimmed")]
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters, not counting surrounding spaces"))]
+    pub name: String,
+    #[validate(range(min = 0, max = 150, message = "must be from 0 to 150"))]
+    pub age: i32,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "changes_something"))]
+pub struct UpdateRecord {
+    #[serde(default, deserialize_with = "trimmed_option")]
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters, not counting surrounding spaces"))]
+    pub name: Option<String>,
+    #[validate(range(min = 0, max = 150, message = "must be from 0 to 150"))]
+    pub age: Option<i32>,
+}
+
+fn changes_something(update: &UpdateRecord) -> Result<(), ValidationError> {
+    if update.name.is_none() && update.age.is_none() {
+        return Err(ValidationError::new("empty").with_message("give at least one of name and age".into()));
+    }
+    Ok(())
+}
+
+fn trimmed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    Ok(s.trim().to_string())
+}
+
+fn trimmed_option<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let s = Option::<String>::deserialize(deserializer)?;
+    Ok(s.map(|s| s.trim().to_string()))
+}
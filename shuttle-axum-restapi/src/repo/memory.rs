@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::{RepoError, UserRepo};
+use crate::models::{UpdateRecord, User, UserSubmission};
+
+/// Users held in a map. Ids count up from 1 and are never reused, like a
+/// `serial` column.
+#[derive(Default)]
+pub struct MemoryUsers {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    last_id: i32,
+    users: BTreeMap<i32, User>,
+}
+
+impl MemoryUsers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl UserRepo for MemoryUsers {
+    async fn list(&self) -> Result<Vec<User>, RepoError> {
+        Ok(self.inner.lock().unwrap().users.values().cloned().collect())
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<User>, RepoError> {
+        Ok(self.inner.lock().unwrap().users.get(&id).cloned())
+    }
+
+    async fn create(&self, user: UserSubmission) -> Result<User, RepoError> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_id += 1;
+        let user = User {
+            id: inner.last_id,
+            name: user.name,
+            age: user.age,
+        };
+        inner.users.insert(user.id, user.clone());
+        Ok(user)
+    }
+
+    async fn update(&self, id: i32, changes: UpdateRecord) -> Result<Option<User>, RepoError> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(user) = inner.users.get_mut(&id) else {
+            return Ok(None);
+        };
+        if let Some(name) = changes.name {
+            user.name = name;
+        }
+        if let Some(age) = changes.age {
+            user.age = age;
+        }
+        Ok(Some(user.clone()))
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepoError> {
+        Ok(self.inner.lock().unwrap().users.remove(&id).is_some())
+    }
+}
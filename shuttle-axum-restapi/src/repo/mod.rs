@@ -0,0 +1,71 @@
+//! Where users are kept. Handlers only see [`UserRepo`], so the service runs
+//! against Postgres in production and against memory in tests.
+
+use std::fmt;
+
+use async_trait::async_trait;
+use sqlx::error::ErrorKind;
+
+use crate::models::{UpdateRecord, User, UserSubmission};
+
+mod memory;
+mod postgres;
+
+pub use memory::MemoryUsers;
+pub use postgres::PgUsers;
+
+#[async_trait]
+pub trait UserRepo: Send + Sync {
+    /// Every user, by id.
+    async fn list(&self) -> Result<Vec<User>, RepoError>;
+    async fn get(&self, id: i32) -> Result<Option<User>, RepoError>;
+    async fn create(&self, user: UserSubmission) -> Result<User, RepoError>;
+    /// Changes the fields that are present. `None` if there is no such user.
+    async fn update(&self, id: i32, changes: UpdateRecord) -> Result<Option<User>, RepoError>;
+    /// Whether there was a user to delete.
+    async fn delete(&self, id: i32) -> Result<bool, RepoError>;
+}
+
+#[derive(Debug)]
+pub enum RepoError {
+    /// The write would break a unique, foreign key, not-null or check
+    /// constraint. Says what kind, for the client; the constraint itself
+    /// is only logged.
+    Conflict(String),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepoError::Conflict(message) => f.write_str(message),
+            RepoError::Database(e) => write!(f, "database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RepoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RepoError::Conflict(_) => None,
+            RepoError::Database(e) => Some(e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for RepoError {
+    fn from(e: sqlx::Error) -> Self {
+        let Some(db) = e.as_database_error() else {
+            return RepoError::Database(e);
+        };
+        let message = match db.kind() {
+            ErrorKind::UniqueViolation => "the change would duplicate a user that exists already",
+            ErrorKind::ForeignKeyViolation => "the change refers to something that does not exist",
+            ErrorKind::NotNullViolation => "the change leaves out a required value",
+            ErrorKind::CheckViolation => "the change has a value the database does not allow",
+            _ => return RepoError::Database(e),
+        };
+        tracing::warn!(constraint = db.constraint(), "{db}");
+        RepoError::Conflict(message.to_string())
+    }
+}
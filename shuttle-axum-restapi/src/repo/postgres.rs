@@ -0,0 +1,69 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+
+use super::{RepoError, UserRepo};
+use crate::models::{UpdateRecord, User, UserSubmission};
+
+#[derive(Clone)]
+pub struct PgUsers {
+    db: PgPool,
+}
+
+impl PgUsers {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl UserRepo for PgUsers {
+    async fn list(&self) -> Result<Vec<User>, RepoError> {
+        let users = sqlx::query_as::<_, User>("SELECT id, name, age FROM users ORDER BY id")
+            .fetch_all(&self.db)
+            .await?;
+        Ok(users)
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<User>, RepoError> {
+        let user = sqlx::query_as::<_, User>("SELECT id, name, age FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(user)
+    }
+
+    async fn create(&self, user: UserSubmission) -> Result<User, RepoError> {
+        let user = sqlx::query_as::<_, User>(
+            "INSERT INTO users (name, age) VALUES ($1, $2) RETURNING id, name, age",
+        )
+        .bind(user.name)
+        .bind(user.age)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(user)
+    }
+
+    async fn update(&self, id: i32, changes: UpdateRecord) -> Result<Option<User>, RepoError> {
+        let user = sqlx::query_as::<_, User>(
+            "UPDATE users
+             SET name = COALESCE($1, name),
+                 age = COALESCE($2, age)
+             WHERE id = $3
+             RETURNING id, name, age",
+        )
+        .bind(changes.name)
+        .bind(changes.age)
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(user)
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, RepoError> {
+        let deleted = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use serde_json::Value;
+use shuttle_axum_restapi::repo::MemoryUsers;
+use shuttle_axum_restapi::{app, AppState};
+use tower::ServiceExt;
+
+/// The service over an empty in-memory store.
+pub fn service() -> Router {
+    app(AppState::new(Arc::new(MemoryUsers::new())))
+}
+
+pub fn json(method: &str, uri: &str, body: Value) -> Request<Body> {
+    raw(method, uri, "application/json", &body.to_string())
+}
+
+pub fn raw(method: &str, uri: &str, content_type: &str, body: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+pub fn empty(method: &str, uri: &str) -> Request<Body> {
+    Request::builder().method(method).uri(uri).body(Body::empty()).unwrap()
+}
+
+/// The status and the body as JSON, or `Null` if there is none.
+pub async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, body)
+}
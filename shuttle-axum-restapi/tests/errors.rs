@@ -0,0 +1,67 @@
+mod common;
+
+use axum::body::to_bytes;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use common::{empty, json, raw, send, service};
+use serde_json::{json, Value};
+use shuttle_axum_restapi::error::AppError;
+use shuttle_axum_restapi::repo::RepoError;
+
+#[tokio::test]
+async fn missing_users_are_404_for_every_method() {
+    let app = service();
+    let expected = json!({ "error": "not_found", "message": "user 7 does not exist" });
+    for request in [
+        empty("GET", "/users/7"),
+        json("PUT", "/users/7", json!({ "age": 40 })),
+        empty("DELETE", "/users/7"),
+    ] {
+        assert_eq!(send(&app, request).await, (StatusCode::NOT_FOUND, expected.clone()));
+    }
+
+    send(&app, json("POST", "/users", json!({ "name": "Ada", "age": 36 }))).await;
+    send(&app, empty("DELETE", "/users/1")).await;
+    let (status, _) = send(&app, empty("DELETE", "/users/1")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn malformed_requests_get_json_errors() {
+    let app = service();
+    let cases = [
+        (raw("POST", "/users", "application/json", "{\"name\":"), StatusCode::BAD_REQUEST, "bad_request"),
+        (raw("POST", "/users", "text/plain", "{}"), StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type"),
+        (json("POST", "/users", json!({ "name": "Ada", "age": "old" })), StatusCode::UNPROCESSABLE_ENTITY, "validation"),
+        (json("POST", "/users", json!({ "name": "Ada" })), StatusCode::UNPROCESSABLE_ENTITY, "validation"),
+        (empty("GET", "/users/ada"), StatusCode::BAD_REQUEST, "bad_request"),
+    ];
+    for (request, status, code) in cases {
+        let (got, body) = send(&app, request).await;
+        assert_eq!((got, body["error"].as_str()), (status, Some(code)), "{body}");
+        assert!(body["message"].is_string());
+    }
+}
+
+async fn respond(error: AppError) -> (StatusCode, Value) {
+    let response = error.into_response();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn constraint_violations_are_409() {
+    let error = RepoError::Conflict("the change would duplicate a user that exists already".to_string());
+    let (status, body) = respond(error.into()).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body, json!({ "error": "conflict", "message": "the change would duplicate a user that exists already" }));
+}
+
+#[tokio::test]
+async fn internal_details_are_not_sent() {
+    let error = RepoError::Database(sqlx::Error::PoolTimedOut);
+    let (status, body) = respond(error.into()).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body, json!({ "error": "internal", "message": "something went wrong on our side" }));
+}
@@ -0,0 +1,107 @@
+//! The migrations against a real database. Each test gets a fresh one from
+//! `#[sqlx::test]`, which needs `DATABASE_URL`; run with `--ignored`.
+
+use shuttle_axum_restapi::migrate::{self, State};
+use sqlx::PgPool;
+
+async fn states(db: &PgPool) -> Vec<(i64, State)> {
+    let mut conn = db.acquire().await.unwrap();
+    migrate::status(&mut conn).await.unwrap().into_iter().map(|s| (s.version, s.state)).collect()
+}
+
+async fn checks(db: &PgPool) -> Vec<String> {
+    sqlx::query_scalar(
+        "SELECT conname::text FROM pg_constraint WHERE conrelid = 'users'::regclass AND contype = 'c' ORDER BY conname",
+    )
+    .fetch_all(db)
+    .await
+    .unwrap()
+}
+
+async fn name(db: &PgPool, id: i32) -> String {
+    sqlx::query_scalar("SELECT name FROM users WHERE id = $1").bind(id).fetch_one(db).await.unwrap()
+}
+
+async fn user(db: &PgPool, id: i32) -> (String, i32) {
+    sqlx::query_as("SELECT name, age FROM users WHERE id = $1").bind(id).fetch_one(db).await.unwrap()
+}
+
+async fn insert(db: &PgPool, name: &str, age: i32) -> i32 {
+    sqlx::query_scalar("INSERT INTO users (name, age) VALUES ($1, $2) RETURNING id")
+        .bind(name)
+        .bind(age)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = false)]
+#[ignore = "needs DATABASE_URL to point at Postgres"]
+async fn migrations_go_up_and_back_down(db: PgPool) {
+    migrate::up(&db).await.unwrap();
+    assert_eq!(states(&db).await, [(1, State::Applied), (2, State::Applied)]);
+    assert_eq!(checks(&db).await, ["users_age_range", "users_name_length", "users_name_trimmed"]);
+
+    // Back to before 0002 to store names it would trim.
+    migrate::down(&db, Some(1)).await.unwrap();
+    assert_eq!(states(&db).await, [(1, State::Applied), (2, State::Pending)]);
+    let padded: i32 = sqlx::query_scalar("INSERT INTO users (name, age) VALUES ('  Ada ', 36) RETURNING id")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    let renamed: i32 = sqlx::query_scalar("INSERT INTO users (name, age) VALUES (' Grace', 45) RETURNING id")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+
+    migrate::up(&db).await.unwrap();
+    assert_eq!(name(&db, padded).await, "Ada");
+    assert_eq!(name(&db, renamed).await, "Grace");
+    sqlx::query("UPDATE users SET name = 'Grace Hopper' WHERE id = $1").bind(renamed).execute(&db).await.unwrap();
+
+    // Reverting 0002 drops its checks and puts back the names it trimmed,
+    // but not one changed since.
+    migrate::down(&db, None).await.unwrap();
+    assert_eq!(states(&db).await, [(1, State::Applied), (2, State::Pending)]);
+    assert!(checks(&db).await.is_empty());
+    assert_eq!(name(&db, padded).await, "  Ada ");
+    assert_eq!(name(&db, renamed).await, "Grace Hopper");
+    sqlx::query("INSERT INTO users (name, age) VALUES ('', 200)").execute(&db).await.unwrap();
+
+    migrate::down(&db, Some(0)).await.unwrap();
+    assert_eq!(states(&db).await, [(1, State::Pending), (2, State::Pending)]);
+    let users: Option<String> = sqlx::query_scalar("SELECT to_regclass('users')::text").fetch_one(&db).await.unwrap();
+    assert_eq!(users, None);
+
+    migrate::up(&db).await.unwrap();
+    assert_eq!(states(&db).await, [(1, State::Applied), (2, State::Applied)]);
+}
+
+#[sqlx::test(migrations = false)]
+#[ignore = "needs DATABASE_URL to point at Postgres"]
+async fn rows_the_old_api_took_are_fixed_rather_than_refused(db: PgPool) {
+    migrate::up(&db).await.unwrap();
+    migrate::down(&db, Some(1)).await.unwrap();
+    let long = format!("{} {}", "x".repeat(99), "y".repeat(20));
+    let fine = insert(&db, "Ada", 36).await;
+    let empty = insert(&db, "", -5).await;
+    let blank = insert(&db, " \t ", 200).await;
+    let too_long = insert(&db, &long, 30).await;
+    let old = insert(&db, "Grace", 151).await;
+
+    migrate::up(&db).await.unwrap();
+    assert_eq!(states(&db).await, [(1, State::Applied), (2, State::Applied)]);
+    assert_eq!(user(&db, fine).await, ("Ada".to_string(), 36));
+    assert_eq!(user(&db, empty).await, (format!("user {}", empty), 0));
+    assert_eq!(user(&db, blank).await, (format!("user {}", blank), 150));
+    // Cut at 100 characters, which leaves a trailing space to trim.
+    assert_eq!(user(&db, too_long).await, ("x".repeat(99), 30));
+    assert_eq!(user(&db, old).await, ("Grace".to_string(), 150));
+    sqlx::query("UPDATE users SET age = 40 WHERE id = $1").bind(empty).execute(&db).await.unwrap();
+
+    migrate::down(&db, None).await.unwrap();
+    assert_eq!(user(&db, empty).await, (String::new(), 40));
+    assert_eq!(user(&db, blank).await, (" \t ".to_string(), 200));
+    assert_eq!(user(&db, too_long).await, (long, 30));
+    assert_eq!(user(&db, old).await, ("Grace".to_string(), 151));
+}
@@ -0,0 +1,38 @@
+//! `PgUsers` errors from a real database, migrated by `#[sqlx::test]`,
+//! which needs `DATABASE_URL`; run with `--ignored`.
+
+use axum::body::to_bytes;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use serde_json::{json, Value};
+use shuttle_axum_restapi::error::AppError;
+use shuttle_axum_restapi::repo::RepoError;
+use sqlx::PgPool;
+
+/// Runs `sql` and answers with what its error becomes.
+async fn conflict(db: &PgPool, sql: &str) -> (StatusCode, Value) {
+    let e = sqlx::query(sql).execute(db).await.unwrap_err();
+    let error = RepoError::from(e);
+    assert!(matches!(error, RepoError::Conflict(_)), "{error:?}");
+    let response = AppError::from(error).into_response();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[sqlx::test]
+#[ignore = "needs DATABASE_URL to point at Postgres"]
+async fn violations_are_409_without_the_constraint(db: PgPool) {
+    sqlx::query("INSERT INTO users (id, name, age) VALUES (1, 'Ada', 36)").execute(&db).await.unwrap();
+
+    let duplicate = conflict(&db, "INSERT INTO users (id, name, age) VALUES (1, 'Grace', 45)").await;
+    let expected = json!({ "error": "conflict", "message": "the change would duplicate a user that exists already" });
+    assert_eq!(duplicate, (StatusCode::CONFLICT, expected));
+
+    let out_of_range = conflict(&db, "UPDATE users SET age = 200 WHERE id = 1").await;
+    let expected = json!({ "error": "conflict", "message": "the change has a value the database does not allow" });
+    assert_eq!(out_of_range, (StatusCode::CONFLICT, expected));
+
+    let untrimmed = conflict(&db, "INSERT INTO users (name, age) VALUES (' Grace', 45)").await;
+    assert!(!untrimmed.1["message"].as_str().unwrap().contains("users_name"), "{}", untrimmed.1);
+}
@@ -0,0 +1,63 @@
+//! The service end to end, over the in-memory store.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::{empty, json, send, service};
+use serde_json::json;
+
+#[tokio::test]
+async fn users_can_be_created_and_read() {
+    let app = service();
+    let (status, created) = send(&app, json("POST", "/users", json!({ "name": "Ada", "age": 36 }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created, json!({ "id": 1, "name": "Ada", "age": 36 }));
+    send(&app, json("POST", "/users", json!({ "name": "Grace", "age": 85 }))).await;
+
+    let (status, user) = send(&app, empty("GET", "/users/1")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(user["name"], "Ada");
+
+    let (status, users) = send(&app, empty("GET", "/users")).await;
+    assert_eq!(status, StatusCode::OK);
+    let names: Vec<_> = users.as_array().unwrap().iter().map(|u| u["name"].clone()).collect();
+    assert_eq!(names, [json!("Ada"), json!("Grace")]);
+}
+
+#[tokio::test]
+async fn updates_change_only_the_fields_given() {
+    let app = service();
+    send(&app, json("POST", "/users", json!({ "name": "Ada", "age": 36 }))).await;
+
+    let (status, user) = send(&app, json("PUT", "/users/1", json!({ "age": 37 }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(user, json!({ "id": 1, "name": "Ada", "age": 37 }));
+
+    send(&app, json("PUT", "/users/1", json!({ "name": "Ada Lovelace" }))).await;
+    let (_, user) = send(&app, empty("GET", "/users/1")).await;
+    assert_eq!(user, json!({ "id": 1, "name": "Ada Lovelace", "age": 37 }));
+}
+
+#[tokio::test]
+async fn deleted_users_are_gone_and_their_ids_not_reused() {
+    let app = service();
+    send(&app, json("POST", "/users", json!({ "name": "Ada", "age": 36 }))).await;
+
+    let (status, _) = send(&app, empty("DELETE", "/users/1")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, users) = send(&app, empty("GET", "/users")).await;
+    assert_eq!(users, json!([]));
+
+    let (_, created) = send(&app, json("POST", "/users", json!({ "name": "Grace", "age": 85 }))).await;
+    assert_eq!(created["id"], 2);
+}
+
+#[tokio::test]
+async fn the_mock_driver_still_comes_and_goes() {
+    let app = service();
+    let (status, body) = send(&app, empty("GET", "/mock_driver_arrives")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Driver has arrived.");
+    let (_, body) = send(&app, empty("GET", "/mock_driver_leaves")).await;
+    assert_eq!(body["message"], "Driver has left.");
+}
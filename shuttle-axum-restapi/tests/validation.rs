@@ -0,0 +1,66 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{empty, json, send, service};
+use serde_json::json;
+
+#[tokio::test]
+async fn submissions_are_checked_field_by_field() {
+    let app = service();
+    let (status, body) = send(&app, json("POST", "/users", json!({ "name": "  ", "age": -1 }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        body,
+        json!({
+            "error": "validation",
+            "message": "some fields are invalid",
+            "fields": {
+                "name": ["must be 1 to 100 characters, not counting surrounding spaces"],
+                "age": ["must be from 0 to 150"],
+            },
+        })
+    );
+
+    let (status, body) = send(&app, json("POST", "/users", json!({ "name": "x".repeat(101), "age": 151 }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["fields"].as_object().unwrap().len(), 2);
+
+    let (_, users) = send(&app, empty("GET", "/users")).await;
+    assert_eq!(users, json!([]));
+}
+
+#[tokio::test]
+async fn names_are_trimmed_and_limits_are_inclusive() {
+    let app = service();
+    let (status, user) = send(&app, json("POST", "/users", json!({ "name": " Ada\t", "age": 0 }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(user["name"], "Ada");
+
+    // Characters, not bytes.
+    let (status, _) = send(&app, json("POST", "/users", json!({ "name": "é".repeat(100), "age": 150 }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, user) = send(&app, json("PUT", "/users/1", json!({ "name": "  Ada Lovelace " }))).await;
+    assert_eq!(user["name"], "Ada Lovelace");
+}
+
+#[tokio::test]
+async fn updates_are_checked_and_must_change_something() {
+    let app = service();
+    send(&app, json("POST", "/users", json!({ "name": "Ada", "age": 36 }))).await;
+
+    for body in [json!({}), json!({ "name": null, "age": null })] {
+        let (status, body) = send(&app, json("PUT", "/users/1", body)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["message"], "give at least one of name and age");
+        assert_eq!(body["fields"], json!({}));
+    }
+
+    let (status, body) = send(&app, json("PUT", "/users/1", json!({ "name": "", "age": 200 }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["fields"]["age"], json!(["must be from 0 to 150"]));
+    assert!(body["fields"]["name"].is_array());
+
+    let (_, user) = send(&app, empty("GET", "/users/1")).await;
+    assert_eq!(user, json!({ "id": 1, "name": "Ada", "age": 36 }));
+}
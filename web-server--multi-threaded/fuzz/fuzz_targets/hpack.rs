@@ -0,0 +1,35 @@
+//! Decodes arbitrary header blocks, then checks that whatever decoded
+//! survives a round trip through our encoder.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use web_server__multi_threaded::h2::hpack::{self, Decoder, Encoder};
+
+const MAX_LIST_SIZE: usize = 64 * 1024;
+
+fuzz_target!(|data: &[u8]| {
+    // Two blocks on one decoder so the second can refer to entries the
+    // first added to the dynamic table.
+    let (first, second) = data.split_at(data.len() / 2);
+    let mut decoder = Decoder::new(hpack::DEFAULT_TABLE_SIZE);
+    for block in [first, second] {
+        let Ok(headers) = decoder.decode(block, MAX_LIST_SIZE) else {
+            return;
+        };
+
+        let Ok(text): Result<Vec<(&str, &str)>, _> = headers
+            .iter()
+            .map(|(n, v)| Ok((std::str::from_utf8(n)?, std::str::from_utf8(v)?)))
+            .collect::<Result<_, std::str::Utf8Error>>()
+        else {
+            continue;
+        };
+        let mut encoded = Vec::new();
+        Encoder::new().encode(text.iter().copied(), &mut encoded);
+        let decoded = Decoder::new(hpack::DEFAULT_TABLE_SIZE)
+            .decode(&encoded, usize::MAX)
+            .expect("our own encoding decodes");
+        assert_eq!(decoded, headers);
+    }
+});
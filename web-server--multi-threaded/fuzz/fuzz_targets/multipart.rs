@@ -0,0 +1,28 @@
+//! The first line of the input is a `Content-Type` header, the rest is the
+//! body it describes.
+
+#![no_main]
+
+use std::io::Read;
+
+use libfuzzer_sys::fuzz_target;
+use web_server__multi_threaded::multipart::{self, Multipart};
+
+fuzz_target!(|data: &[u8]| {
+    let split = data.iter().position(|&b| b == b'\n').unwrap_or(data.len());
+    let (content_type, body) = data.split_at(split);
+    let boundary = std::str::from_utf8(content_type)
+        .ok()
+        .and_then(multipart::boundary)
+        .unwrap_or("boundary");
+
+    let mut form = Multipart::new(body, boundary);
+    while let Ok(Some(mut part)) = form.next_part() {
+        let _ = part.name();
+        let _ = part.file_name();
+        let mut contents = Vec::new();
+        if part.read_to_end(&mut contents).is_err() {
+            break;
+        }
+    }
+});
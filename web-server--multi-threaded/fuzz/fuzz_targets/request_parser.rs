@@ -0,0 +1,38 @@
+//! Feeds arbitrary bytes to the HTTP/1.1 parser the way a keep-alive
+//! connection would: request after request until something fails.
+
+#![no_main]
+
+use std::io::{BufReader, Read};
+
+use libfuzzer_sys::fuzz_target;
+use web_server__multi_threaded::http::Request;
+
+fuzz_target!(|data: &[u8]| {
+    // A small buffer makes lines and bodies straddle refills.
+    let mut reader = BufReader::with_capacity(16, data);
+    loop {
+        let mut request = match Request::read_from(&mut reader) {
+            Ok(request) => request,
+            Err(_) => break,
+        };
+        let _ = request.path();
+        let _ = request.keep_alive();
+        let _ = request.content_length();
+
+        let mut body = Vec::new();
+        let read = request.body().read_to_end(&mut body);
+        if let Some(length) = request.content_length() {
+            // Never more than announced, and less only when the input ends
+            // early, which has to be reported rather than passed off as the
+            // whole body.
+            assert!(body.len() as u64 <= length);
+            if read.is_ok() {
+                assert_eq!(body.len() as u64, length);
+            }
+        }
+        if read.is_err() {
+            break;
+        }
+    }
+});
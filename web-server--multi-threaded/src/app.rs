@@ -0,0 +1,114 @@
+//! The routes the server binary serves: a static page and file uploads.
+
+use std::{fs, io::ErrorKind};
+
+use crate::{
+    http::{Request, Response, Status},
+    middleware::{Chain, Logger, RequestId},
+    multipart::{self, Multipart},
+    upload::{self, Limited, UploadConfig, UploadError},
+};
+
+const UPLOAD_PATH: &str = "/uploads/";
+
+/// Builds the full handler chain. Pages are read from the working directory.
+pub fn app(uploads: UploadConfig) -> Chain {
+    Chain::new(move |request: &mut Request| route(request, &uploads))
+        .with(Logger)
+        .with(RequestId::new())
+}
+
+fn route(request: &mut Request, uploads: &UploadConfig) -> Response {
+    match (request.method.as_str(), request.path()) {
+        ("GET", "/") => page(Status::OK, "hello.html"),
+        ("POST", "/uploads") | ("POST", UPLOAD_PATH) => upload_form(request, uploads),
+        ("PUT", path) if path.starts_with(UPLOAD_PATH) => {
+            let name = path.trim_start_matches(UPLOAD_PATH).to_string();
+            upload_raw(request, &name, uploads)
+        }
+        _ => page(Status::NOT_FOUND, "404.html"),
+    }
+}
+
+fn page(status: Status, filename: &str) -> Response {
+    let contents = fs::read_to_string(filename).unwrap();
+
+    Response::html(status, contents)
+}
+
+/// `PUT /uploads/{name}`: the request body is the file.
+fn upload_raw(request: &mut Request, name: &str, uploads: &UploadConfig) -> Response {
+    if request.content_length().is_some_and(|len| len > uploads.max_size) {
+        return upload_error(UploadError::TooLarge);
+    }
+
+    match upload::save(uploads, name, request.body()) {
+        Ok(saved) => {
+            let status = if saved.created { Status::CREATED } else { Status::OK };
+            Response::text(status, format!("{}\n", saved.name))
+        }
+        Err(e) => upload_error(e),
+    }
+}
+
+/// `POST /uploads` with a `multipart/form-data` body: every file field is
+/// stored, other fields are ignored.
+fn upload_form(request: &mut Request, uploads: &UploadConfig) -> Response {
+    let boundary = match request.header("Content-Type").and_then(multipart::boundary) {
+        Some(boundary) => boundary.to_string(),
+        None => {
+            return Response::text(Status::UNSUPPORTED_MEDIA_TYPE, "expected multipart/form-data\n");
+        }
+    };
+    if request.content_length().is_some_and(|len| len > uploads.max_size) {
+        return upload_error(UploadError::TooLarge);
+    }
+
+    // The limit covers the whole body, so no single file can exceed it either.
+    let mut form = Multipart::new(Limited::new(request.body(), uploads.max_size), &boundary);
+    let mut saved = Vec::new();
+    loop {
+        let part = match form.next_part() {
+            Ok(Some(part)) => part,
+            Ok(None) => break,
+            Err(e) => return upload_error(e.into()),
+        };
+        let file_name = match part.file_name() {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        match upload::save(uploads, &file_name, part) {
+            Ok(file) => saved.push(file.name),
+            Err(e) => return upload_error(e),
+        }
+    }
+
+    if saved.is_empty() {
+        return Response::text(Status::BAD_REQUEST, "no file fields in form\n");
+    }
+    let mut body = saved.join("\n");
+    body.push('\n');
+    Response::text(Status::CREATED, body)
+}
+
+fn upload_error(error: UploadError) -> Response {
+    let status = match error {
+        UploadError::TooLarge => Status::PAYLOAD_TOO_LARGE,
+        UploadError::BadFileName => Status::BAD_REQUEST,
+        // A malformed or truncated body is the client's fault.
+        UploadError::Io(ref e)
+            if matches!(e.kind(), ErrorKind::InvalidData | ErrorKind::UnexpectedEof) =>
+        {
+            Status::BAD_REQUEST
+        }
+        UploadError::Io(ref e) => {
+            println!("Upload failed: {e}");
+            Status::INTERNAL_SERVER_ERROR
+        }
+    };
+    let mut response = Response::text(status, format!("{error}\n"));
+    // Whatever is left of the body is still on the wire; do not try to reuse
+    // the connection.
+    response.headers.set("Connection", "close");
+    response
+}
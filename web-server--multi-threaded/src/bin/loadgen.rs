@@ -0,0 +1,478 @@
+//! Generates HTTP load against a local URL and reports throughput and latency.
+//!
+//! ```text
+//! cargo run --release --bin loadgen -- -c 8 -r 2000 -d 10 http://127.0.0.1:7878/
+//! ```
+//!
+//! Each connection runs on its own thread. With a rate set, requests are sent
+//! on a fixed schedule and latency is measured from when a request *should*
+//! have been sent, so a stalled server shows up in the percentiles instead of
+//! silently lowering the request rate. A connect, read or write that makes no
+//! progress within the timeout fails its request, so a server that stops
+//! responding cannot keep the run from ending. A kept-alive connection the
+//! server closed while it sat idle is not a failure: the request is sent
+//! again once on a new connection. After a failed connect a connection
+//! backs off before trying again, so a server that is down does not turn
+//! into a flood of errors.
+
+use std::{
+    env,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    process,
+    thread,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str = "\
+usage: loadgen [options] URL
+
+options:
+  -c, --connections N   concurrent connections (default 4)
+  -r, --rate N          total requests per second, 0 for as fast as possible (default 0)
+  -d, --duration SECS   how long to run (default 10)
+  -t, --timeout SECS    give up on a connect, read or write after this long (default 5)
+      --no-keep-alive   open a new connection for every request";
+
+struct Config {
+    host: String,
+    port: u16,
+    path: String,
+    connections: usize,
+    rate: u64,
+    duration: Duration,
+    timeout: Duration,
+    keep_alive: bool,
+}
+
+impl Config {
+    fn build(mut args: impl Iterator<Item = String>) -> Result<Config, String> {
+        args.next();
+
+        let mut url = None;
+        let mut connections = 4;
+        let mut rate = 0;
+        let mut duration = Duration::from_secs(10);
+        let mut timeout = Duration::from_secs(5);
+        let mut keep_alive = true;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-c" | "--connections" => connections = number(&arg, args.next())?,
+                "-r" | "--rate" => rate = number(&arg, args.next())?,
+                "-d" | "--duration" => duration = Duration::from_secs(number(&arg, args.next())?),
+                "-t" | "--timeout" => timeout = Duration::from_secs(number(&arg, args.next())?),
+                "--no-keep-alive" => keep_alive = false,
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                _ if arg.starts_with('-') => return Err(format!("unknown option {arg}\n\n{USAGE}")),
+                _ if url.is_none() => url = Some(arg),
+                _ => return Err(format!("unexpected argument {arg}\n\n{USAGE}")),
+            }
+        }
+
+        let url = url.ok_or_else(|| format!("missing URL\n\n{USAGE}"))?;
+        let (host, port, path) = parse_url(&url)?;
+        if connections == 0 {
+            return Err("need at least one connection".to_string());
+        }
+        if timeout.is_zero() {
+            return Err("the timeout must be at least one second".to_string());
+        }
+
+        Ok(Config {
+            host,
+            port,
+            path,
+            connections,
+            rate,
+            duration,
+            timeout,
+            keep_alive,
+        })
+    }
+}
+
+fn number<T: std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    value
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| format!("{option} needs a number"))
+}
+
+/// Splits `http://host[:port][/path]` into its parts. Only plain HTTP is
+/// supported.
+fn parse_url(url: &str) -> Result<(String, u16, String), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("{url}: only http:// URLs are supported"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| format!("{url}: bad port"))?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(format!("{url}: missing host"));
+    }
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// How long a connection waits after its first failed connect in a row.
+const FIRST_BACKOFF: Duration = Duration::from_millis(10);
+
+/// The most a connection waits between connect attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// What one connection thread saw.
+#[derive(Default)]
+struct Stats {
+    latencies: Vec<Duration>,
+    /// Requests that got a response outside 2xx.
+    bad_status: u64,
+    /// Requests that failed at the socket or protocol level.
+    errors: u64,
+    /// Of the `errors`, those where the server did not answer in time.
+    timeouts: u64,
+}
+
+fn main() {
+    let config = Config::build(env::args()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(2);
+    });
+
+    println!(
+        "Running {}s against http://{}:{}{} with {} connection(s), {}, keep-alive {}",
+        config.duration.as_secs(),
+        config.host,
+        config.port,
+        config.path,
+        config.connections,
+        if config.rate == 0 {
+            "unthrottled".to_string()
+        } else {
+            format!("{} req/s", config.rate)
+        },
+        if config.keep_alive { "on" } else { "off" },
+    );
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: {}\r\n\r\n",
+        config.path,
+        config.host,
+        config.port,
+        if config.keep_alive { "keep-alive" } else { "close" }
+    );
+
+    let interval = interval(config.connections, config.rate);
+
+    let start = Instant::now();
+    let deadline = start + config.duration;
+    let config = &config;
+    let request = request.as_str();
+
+    let stats: Vec<Stats> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..config.connections)
+            .map(|_| scope.spawn(move || run_connection(config, request, interval, deadline)))
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap()).collect()
+    });
+    let elapsed = start.elapsed();
+
+    report(stats, elapsed);
+}
+
+/// How often each of `connections` sends so that together they make `rate`
+/// requests a second, or `None` to send as fast as possible.
+fn interval(connections: usize, rate: u64) -> Option<Duration> {
+    match rate {
+        0 => None,
+        rate => Some(Duration::from_secs_f64(connections as f64 / rate as f64)),
+    }
+}
+
+/// How long to wait after `failures` connects in a row have failed.
+fn backoff(failures: u32) -> Duration {
+    FIRST_BACKOFF
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+fn run_connection(
+    config: &Config,
+    request: &str,
+    interval: Option<Duration>,
+    deadline: Instant,
+) -> Stats {
+    let mut stats = Stats::default();
+    let mut connection: Option<BufReader<TcpStream>> = None;
+    let mut next_send = Instant::now();
+    let mut connect_failures = 0;
+
+    while next_send < deadline {
+        if let Some(wait) = next_send.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+        let scheduled = match interval {
+            Some(_) => next_send,
+            None => Instant::now(),
+        };
+
+        match send(config, request, &mut connection) {
+            Ok(status) => {
+                connect_failures = 0;
+                stats.latencies.push(scheduled.elapsed());
+                if !(200..300).contains(&status) {
+                    stats.bad_status += 1;
+                }
+            }
+            Err(failure) => {
+                stats.errors += 1;
+                if matches!(failure.error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) {
+                    stats.timeouts += 1;
+                }
+                connection = None;
+                if failure.connecting {
+                    connect_failures += 1;
+                    let wait = backoff(connect_failures).min(deadline.saturating_duration_since(Instant::now()));
+                    thread::sleep(wait);
+                }
+            }
+        }
+        if !config.keep_alive {
+            connection = None;
+        }
+
+        next_send = match interval {
+            Some(interval) => next_send + interval,
+            None => Instant::now(),
+        };
+    }
+
+    stats
+}
+
+/// Why a request failed.
+struct Failure {
+    error: io::Error,
+    /// Whether it never got a connection to be sent on.
+    connecting: bool,
+}
+
+impl From<io::Error> for Failure {
+    fn from(error: io::Error) -> Failure {
+        Failure { error, connecting: false }
+    }
+}
+
+/// Sends one request, reusing `connection` if it is open, and reads the
+/// response. Returns the status code.
+fn send(config: &Config, request: &str, connection: &mut Option<BufReader<TcpStream>>) -> Result<u16, Failure> {
+    let reused = connection.is_some();
+    match attempt(config, request, connection)? {
+        Some(status) => Ok(status),
+        // The server closed the connection while it was idle, before it
+        // could have seen the request.
+        None if reused => attempt(config, request, connection)?.ok_or_else(|| closed().into()),
+        None => Err(closed().into()),
+    }
+}
+
+/// Sends the request once. `None` means the connection was closed before
+/// any of a response arrived.
+fn attempt(config: &Config, request: &str, connection: &mut Option<BufReader<TcpStream>>) -> Result<Option<u16>, Failure> {
+    if connection.is_none() {
+        let stream = open(config).map_err(|error| Failure { error, connecting: true })?;
+        *connection = Some(BufReader::new(stream));
+    }
+    let reader = connection.as_mut().unwrap();
+
+    let response = match reader.get_mut().write_all(request.as_bytes()) {
+        Err(e) if is_closed(&e) => None,
+        Err(e) => return Err(e.into()),
+        Ok(()) => read_response(reader)?,
+    };
+    match response {
+        Some((status, closes)) => {
+            if closes {
+                *connection = None;
+            }
+            Ok(Some(status))
+        }
+        None => {
+            *connection = None;
+            Ok(None)
+        }
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "the server closed the connection without responding")
+}
+
+/// Whether `e` means the peer had already closed the connection.
+fn is_closed(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset)
+}
+
+/// A new connection, set up for sending requests.
+fn open(config: &Config) -> io::Result<TcpStream> {
+    let stream = connect(config)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(config.timeout))?;
+    stream.set_write_timeout(Some(config.timeout))?;
+    Ok(stream)
+}
+
+/// Connects to the first address of the host that answers within the timeout.
+fn connect(config: &Config) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in (config.host.as_str(), config.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, config.timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no addresses")))
+}
+
+/// Reads a response with a `Content-Length` body and returns its status and
+/// whether the server is closing the connection, or `None` if the connection
+/// closed before the response began.
+fn read_response<R: BufRead>(reader: &mut R) -> io::Result<Option<(u16, bool)>> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => return Ok(None),
+        Err(e) if is_closed(&e) => return Ok(None),
+        result => result?,
+    };
+    let status = line
+        .split(' ')
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad status line"))?;
+
+    let mut length = 0;
+    let mut closes = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value
+                    .parse()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad Content-Length"))?;
+            } else if name.eq_ignore_ascii_case("Connection") {
+                closes = value.eq_ignore_ascii_case("close");
+            }
+        }
+    }
+
+    io::copy(&mut reader.take(length), &mut io::sink())?;
+    Ok(Some((status, closes)))
+}
+
+fn report(stats: Vec<Stats>, elapsed: Duration) {
+    let mut latencies = Vec::new();
+    let mut bad_status = 0;
+    let mut errors = 0;
+    let mut timeouts = 0;
+    for s in stats {
+        latencies.extend(s.latencies);
+        bad_status += s.bad_status;
+        errors += s.errors;
+        timeouts += s.timeouts;
+    }
+    latencies.sort();
+
+    let completed = latencies.len();
+    println!();
+    println!("Requests:    {completed} completed, {errors} failed ({timeouts} timed out), {bad_status} non-2xx");
+    println!("Elapsed:     {:.2}s", elapsed.as_secs_f64());
+    println!("Throughput:  {:.1} req/s", completed as f64 / elapsed.as_secs_f64());
+    if completed == 0 {
+        return;
+    }
+    println!("Latency p50: {:?}", percentile(&latencies, 50.0));
+    println!("Latency p90: {:?}", percentile(&latencies, 90.0));
+    println!("Latency p99: {:?}", percentile(&latencies, 99.0));
+    println!("Latency max: {:?}", latencies[completed - 1]);
+}
+
+/// Nearest-rank percentile of an already sorted, non-empty slice.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_urls() {
+        let parsed = parse_url;
+        assert_eq!(parsed("http://127.0.0.1:7878/"), Ok(("127.0.0.1".to_string(), 7878, "/".to_string())));
+        assert_eq!(parsed("http://localhost"), Ok(("localhost".to_string(), 80, "/".to_string())));
+        assert_eq!(parsed("http://example.com/a/b?c=d"), Ok(("example.com".to_string(), 80, "/a/b?c=d".to_string())));
+        assert!(parsed("https://example.com/").unwrap_err().contains("only http://"));
+        assert!(parsed("http://example.com:http/").unwrap_err().contains("bad port"));
+        assert!(parsed("http://example.com:70000/").unwrap_err().contains("bad port"));
+        assert!(parsed("http://:8080/").unwrap_err().contains("missing host"));
+    }
+
+    #[test]
+    fn spreads_the_rate_over_the_connections() {
+        assert_eq!(interval(8, 0), None);
+        assert_eq!(interval(8, 2000), Some(Duration::from_millis(4)));
+        assert_eq!(interval(1, 4), Some(Duration::from_millis(250)));
+        // Each connection sending on its interval adds up to the rate.
+        let each = interval(3, 600).unwrap();
+        assert_eq!(3.0 / each.as_secs_f64(), 600.0);
+    }
+
+    #[test]
+    fn backs_off_doubling_up_to_a_cap() {
+        assert_eq!(backoff(1), FIRST_BACKOFF);
+        assert_eq!(backoff(2), FIRST_BACKOFF * 2);
+        assert_eq!(backoff(4), FIRST_BACKOFF * 8);
+        assert_eq!(backoff(8), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn percentiles_are_nearest_rank() {
+        let sorted: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&sorted, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&sorted, 90.0), Duration::from_millis(90));
+        assert_eq!(percentile(&sorted, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&sorted, 100.0), Duration::from_millis(100));
+
+        let few: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&few, 50.0), Duration::from_millis(5));
+        assert_eq!(percentile(&few, 90.0), Duration::from_millis(9));
+        assert_eq!(percentile(&few, 99.0), Duration::from_millis(10));
+        assert_eq!(percentile(&few, 0.0), Duration::from_millis(1));
+
+        let one = [Duration::from_millis(7)];
+        assert_eq!(percentile(&one, 50.0), one[0]);
+        assert_eq!(percentile(&one, 99.0), one[0]);
+    }
+
+    #[test]
+    fn a_close_before_the_response_is_not_a_response() {
+        assert!(read_response(&mut &b""[..]).unwrap().is_none());
+        let response = b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n";
+        assert_eq!(read_response(&mut &response[..]).unwrap(), Some((204, true)));
+        let cut_short = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n";
+        assert_eq!(read_response(&mut &cut_short[..]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
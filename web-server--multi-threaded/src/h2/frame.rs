@@ -0,0 +1,136 @@
+//! HTTP/2 frame layout (RFC 9113, section 4).
+
+use std::io::{self, Read, Write};
+
+/// What a client sends first on a prior-knowledge connection.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+pub const HEADER_LEN: usize = 9;
+
+/// The frame size every peer must accept; we never announce a larger one.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+
+/// Largest value `SETTINGS_MAX_FRAME_SIZE` may take.
+pub const MAX_FRAME_SIZE_LIMIT: usize = (1 << 24) - 1;
+
+/// Largest flow-control window.
+pub const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+/// Initial window for connections and streams until settings say otherwise.
+pub const DEFAULT_WINDOW: i64 = 65_535;
+
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const PRIORITY: u8 = 0x2;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PUSH_PROMISE: u8 = 0x5;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+pub const FLAG_END_STREAM: u8 = 0x1;
+pub const FLAG_ACK: u8 = 0x1;
+pub const FLAG_END_HEADERS: u8 = 0x4;
+pub const FLAG_PADDED: u8 = 0x8;
+pub const FLAG_PRIORITY: u8 = 0x20;
+
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// Error codes carried by RST_STREAM and GOAWAY.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode(pub u32);
+
+impl ErrorCode {
+    pub const NO_ERROR: ErrorCode = ErrorCode(0x0);
+    pub const PROTOCOL_ERROR: ErrorCode = ErrorCode(0x1);
+    pub const INTERNAL_ERROR: ErrorCode = ErrorCode(0x2);
+    pub const FLOW_CONTROL_ERROR: ErrorCode = ErrorCode(0x3);
+    pub const STREAM_CLOSED: ErrorCode = ErrorCode(0x5);
+    pub const FRAME_SIZE_ERROR: ErrorCode = ErrorCode(0x6);
+    pub const REFUSED_STREAM: ErrorCode = ErrorCode(0x7);
+    pub const CANCEL: ErrorCode = ErrorCode(0x8);
+    pub const COMPRESSION_ERROR: ErrorCode = ErrorCode(0x9);
+    pub const ENHANCE_YOUR_CALM: ErrorCode = ErrorCode(0xb);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub length: usize,
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+}
+
+impl FrameHeader {
+    pub fn parse(bytes: &[u8; HEADER_LEN]) -> FrameHeader {
+        FrameHeader {
+            length: u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize,
+            kind: bytes[3],
+            flags: bytes[4],
+            // The high bit is reserved and must be ignored.
+            stream_id: u32::from_be_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]) & 0x7fff_ffff,
+        }
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+/// Reads a frame payload whose header has already been read.
+pub fn read_payload<R: Read>(reader: &mut R, header: &FrameHeader) -> io::Result<Vec<u8>> {
+    let mut payload = vec![0; header.length];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Writes one frame. Callers keep payloads within the peer's frame size.
+pub fn write<W: Write>(writer: &mut W, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> io::Result<()> {
+    let length = (payload.len() as u32).to_be_bytes();
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&length[1..]);
+    frame.push(kind);
+    frame.push(flags);
+    frame.extend_from_slice(&(stream_id & 0x7fff_ffff).to_be_bytes());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)
+}
+
+/// Strips the padding of a DATA or HEADERS payload with `FLAG_PADDED` set.
+///
+/// Returns `None` if the pad length runs past the end of the frame.
+pub fn unpad(header: &FrameHeader, payload: &[u8]) -> Option<std::ops::Range<usize>> {
+    if !header.has(FLAG_PADDED) {
+        return Some(0..payload.len());
+    }
+    let pad = *payload.first()? as usize;
+    let end = payload.len().checked_sub(pad)?;
+    if end < 1 {
+        return None;
+    }
+    Some(1..end)
+}
+
+/// Splits a SETTINGS payload into `(identifier, value)` pairs.
+pub fn settings(payload: &[u8]) -> impl Iterator<Item = (u16, u32)> + '_ {
+    payload.chunks_exact(6).map(|s| {
+        (
+            u16::from_be_bytes([s[0], s[1]]),
+            u32::from_be_bytes([s[2], s[3], s[4], s[5]]),
+        )
+    })
+}
+
+pub fn setting(id: u16, value: u32) -> [u8; 6] {
+    let mut bytes = [0; 6];
+    bytes[..2].copy_from_slice(&id.to_be_bytes());
+    bytes[2..].copy_from_slice(&value.to_be_bytes());
+    bytes
+}
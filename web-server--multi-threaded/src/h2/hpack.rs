@@ -0,0 +1,316 @@
+//! HPACK header compression (RFC 7541).
+//!
+//! The decoder is complete: it keeps the dynamic table in step with the peer
+//! and understands Huffman coded strings. The encoder only ever emits literals
+//! and static table references, which every decoder must accept and which
+//! means we never have to track what the peer has in its table.
+
+use std::{collections::VecDeque, fmt};
+
+use super::huffman;
+
+/// What a header list decodes to: raw name and value bytes, in order.
+pub type HeaderList = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Our `SETTINGS_HEADER_TABLE_SIZE`; we never announce anything else.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Bytes of bookkeeping the RFC charges for every dynamic table entry.
+const ENTRY_OVERHEAD: usize = 32;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpackError {
+    /// The block ended in the middle of a representation.
+    Truncated,
+    /// An integer does not fit in a `usize`.
+    IntegerOverflow,
+    /// An index points past the end of both tables.
+    BadIndex(usize),
+    /// A table size update is larger than we allow or came too late.
+    BadTableSize,
+    /// A Huffman string is malformed or padded incorrectly.
+    Huffman,
+    /// The decoded list is larger than the caller allows.
+    TooLarge,
+}
+
+impl fmt::Display for HpackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HpackError::Truncated => f.write_str("truncated header block"),
+            HpackError::IntegerOverflow => f.write_str("integer overflow"),
+            HpackError::BadIndex(i) => write!(f, "invalid table index {i}"),
+            HpackError::BadTableSize => f.write_str("invalid dynamic table size update"),
+            HpackError::Huffman => f.write_str("invalid Huffman string"),
+            HpackError::TooLarge => f.write_str("header list too large"),
+        }
+    }
+}
+
+impl std::error::Error for HpackError {}
+
+pub struct Decoder {
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+    size: usize,
+    /// Current limit, as set by the peer's size updates.
+    max_size: usize,
+    /// The most the peer may set `max_size` to.
+    allowed_size: usize,
+}
+
+impl Decoder {
+    pub fn new(allowed_size: usize) -> Decoder {
+        Decoder {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size: allowed_size,
+            allowed_size,
+        }
+    }
+
+    /// Decodes one complete header block.
+    ///
+    /// Fails once the decoded list would exceed `max_list_size`, counted the
+    /// way `SETTINGS_MAX_HEADER_LIST_SIZE` is.
+    pub fn decode(&mut self, mut block: &[u8], max_list_size: usize) -> Result<HeaderList, HpackError> {
+        let mut headers = Vec::new();
+        let mut list_size = 0;
+        let mut may_update_size = true;
+
+        while let Some(&first) = block.first() {
+            let (name, value) = if first & 0x80 != 0 {
+                // Indexed header field.
+                let index = decode_int(&mut block, 7)?;
+                self.get(index)?
+            } else if first & 0x40 != 0 {
+                // Literal with incremental indexing.
+                let (name, value) = self.decode_literal(&mut block, 6)?;
+                self.insert(name.clone(), value.clone());
+                (name, value)
+            } else if first & 0x20 != 0 {
+                // Dynamic table size update; only allowed before any field.
+                if !may_update_size {
+                    return Err(HpackError::BadTableSize);
+                }
+                let size = decode_int(&mut block, 5)?;
+                if size > self.allowed_size {
+                    return Err(HpackError::BadTableSize);
+                }
+                self.max_size = size;
+                self.evict(0);
+                continue;
+            } else {
+                // Literal without indexing, or never indexed. We are not a
+                // proxy, so the difference does not matter to us.
+                self.decode_literal(&mut block, 4)?
+            };
+            may_update_size = false;
+
+            list_size += name.len() + value.len() + ENTRY_OVERHEAD;
+            if list_size > max_list_size {
+                return Err(HpackError::TooLarge);
+            }
+            headers.push((name, value));
+        }
+
+        Ok(headers)
+    }
+
+    fn decode_literal(&self, block: &mut &[u8], prefix: u8) -> Result<(Vec<u8>, Vec<u8>), HpackError> {
+        let index = decode_int(block, prefix)?;
+        let name = if index == 0 {
+            decode_string(block)?
+        } else {
+            self.get(index)?.0
+        };
+        let value = decode_string(block)?;
+        Ok((name, value))
+    }
+
+    fn get(&self, index: usize) -> Result<(Vec<u8>, Vec<u8>), HpackError> {
+        if index == 0 {
+            return Err(HpackError::BadIndex(index));
+        }
+        if let Some((name, value)) = STATIC_TABLE.get(index - 1) {
+            return Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()));
+        }
+        self.entries
+            .get(index - STATIC_TABLE.len() - 1)
+            .cloned()
+            .ok_or(HpackError::BadIndex(index))
+    }
+
+    fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // An entry larger than the whole table empties it and is not stored.
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    /// Drops the oldest entries until `extra` more bytes fit.
+    fn evict(&mut self, extra: usize) {
+        while self.size + extra > self.max_size {
+            match self.entries.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Encodes header lists without touching the dynamic table.
+#[derive(Default)]
+pub struct Encoder;
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder
+    }
+
+    /// Appends the encoding of `headers` to `out`. Names must be lowercase.
+    pub fn encode<'h>(&mut self, headers: impl IntoIterator<Item = (&'h str, &'h str)>, out: &mut Vec<u8>) {
+        for (name, value) in headers {
+            let exact = STATIC_TABLE.iter().position(|&(n, v)| n == name && v == value);
+            if let Some(i) = exact {
+                encode_int(i + 1, 7, 0x80, out);
+                continue;
+            }
+            // Literal without indexing, reusing a static name where we can.
+            match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+                Some(i) => encode_int(i + 1, 4, 0x00, out),
+                None => {
+                    out.push(0x00);
+                    encode_string(name.as_bytes(), out);
+                }
+            }
+            encode_string(value.as_bytes(), out);
+        }
+    }
+}
+
+fn decode_int(block: &mut &[u8], prefix: u8) -> Result<usize, HpackError> {
+    let (&first, rest) = block.split_first().ok_or(HpackError::Truncated)?;
+    *block = rest;
+    let max_prefix = (1usize << prefix) - 1;
+    let mut value = first as usize & max_prefix;
+    if value < max_prefix {
+        return Ok(value);
+    }
+
+    let mut shift = 0u32;
+    loop {
+        let (&byte, rest) = block.split_first().ok_or(HpackError::Truncated)?;
+        *block = rest;
+        let add = ((byte & 0x7f) as usize)
+            .checked_shl(shift)
+            .filter(|_| shift < usize::BITS)
+            .ok_or(HpackError::IntegerOverflow)?;
+        value = value.checked_add(add).ok_or(HpackError::IntegerOverflow)?;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn decode_string(block: &mut &[u8]) -> Result<Vec<u8>, HpackError> {
+    let huffman = block.first().ok_or(HpackError::Truncated)? & 0x80 != 0;
+    let length = decode_int(block, 7)?;
+    if length > block.len() {
+        return Err(HpackError::Truncated);
+    }
+    let (raw, rest) = block.split_at(length);
+    *block = rest;
+    if huffman {
+        huffman::decode(raw)
+    } else {
+        Ok(raw.to_vec())
+    }
+}
+
+fn encode_int(mut value: usize, prefix: u8, flags: u8, out: &mut Vec<u8>) {
+    let max_prefix = (1usize << prefix) - 1;
+    if value < max_prefix {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max_prefix as u8);
+    value -= max_prefix;
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn encode_string(bytes: &[u8], out: &mut Vec<u8>) {
+    encode_int(bytes.len(), 7, 0x00, out);
+    out.extend_from_slice(bytes);
+}
@@ -0,0 +1,993 @@
+//! HTTP/2 over cleartext TCP ("h2c", RFC 9113).
+//!
+//! A connection switches to HTTP/2 either with prior knowledge, when the
+//! client opens with the connection preface, or through an HTTP/1.1
+//! `Upgrade: h2c` request. The connection then gets a thread of its own,
+//! which reads frames. Each complete request is handed to a [`ThreadPool`]
+//! kept for streams as a job of its own, which runs the same [`Handler`] as
+//! HTTP/1.1 and writes as much of the response as flow control allows, so
+//! slow handlers do not hold up other streams. Whatever does not fit is left
+//! for the connection thread to send when the client grants more window;
+//! jobs never wait on a client.
+//!
+//! When the server shuts down, the connection thread sends GOAWAY, refuses
+//! new streams and stops once the ones it has are answered.
+//!
+//! [`ThreadPool`]: crate::ThreadPool
+
+pub mod frame;
+pub mod hpack;
+mod huffman;
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Cursor, ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crate::{
+    Spawner,
+    http::{Headers, Request, Response},
+    middleware::Handler,
+};
+use frame::{ErrorCode, FrameHeader};
+
+/// Streams a client may have open at once.
+const MAX_CONCURRENT_STREAMS: usize = 100;
+
+/// Our `SETTINGS_MAX_HEADER_LIST_SIZE`.
+const MAX_HEADER_LIST_SIZE: usize = 64 * 1024;
+
+/// Largest request body we buffer for one stream.
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// The receive window we give each connection, and each stream in it. Credit
+/// only comes back once a handler is done with a body, so this bounds what
+/// one connection can make us buffer, whatever its number of streams.
+const RECV_WINDOW: i64 = MAX_BODY as i64;
+
+/// Headers that only make sense on an HTTP/1.1 connection.
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// True for the request line of the HTTP/2 preface, which the HTTP/1.1
+/// parser reads as a request for `*` with method `PRI`.
+pub fn is_preface(request: &Request) -> bool {
+    request.method == "PRI" && request.target == "*" && request.version == "HTTP/2.0"
+}
+
+/// An HTTP/1.1 request that asked to continue as HTTP/2.
+pub struct Upgrade {
+    request: Request<'static>,
+    settings: Vec<u8>,
+}
+
+/// Checks whether `request` asks to switch to h2c.
+///
+/// Requests with a body are served as HTTP/1.1 instead; the upgrade is
+/// optional and this way we never have to replay a body on stream 1.
+pub fn upgrade(request: &Request) -> Option<Upgrade> {
+    let has_token = |header: &str, token: &str| {
+        request
+            .header(header)
+            .is_some_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    };
+    if !has_token("Upgrade", "h2c")
+        || !has_token("Connection", "Upgrade")
+        || !has_token("Connection", "HTTP2-Settings")
+        || request.header("Transfer-Encoding").is_some()
+        || request.content_length().is_some_and(|len| len > 0)
+    {
+        return None;
+    }
+    let settings = base64url_decode(request.header("HTTP2-Settings")?)?;
+    if !settings.len().is_multiple_of(6) {
+        return None;
+    }
+
+    let mut upgraded = Request::new(request.method.clone(), request.target.clone());
+    upgraded.version = String::from("HTTP/2.0");
+    for (name, value) in request.headers.iter() {
+        let lower = name.to_ascii_lowercase();
+        if !CONNECTION_HEADERS.contains(&lower.as_str()) && lower != "http2-settings" {
+            upgraded.headers.append(lower, value);
+        }
+    }
+    Some(Upgrade {
+        request: upgraded,
+        settings,
+    })
+}
+
+/// Serves a connection that opened with the HTTP/2 preface.
+///
+/// The HTTP/1.1 parser has already consumed the preface's request line; the
+/// rest of it is still in `reader`.
+pub fn serve_prior_knowledge<R: BufRead>(
+    reader: &mut R,
+    stream: &TcpStream,
+    app: Arc<dyn Handler>,
+    spawner: Spawner,
+    shutdown: Arc<AtomicBool>,
+) {
+    let mut rest = [0; 6];
+    if reader.read_exact(&mut rest).is_err() || rest != frame::PREFACE[18..] {
+        return;
+    }
+    match Connection::new(stream, app, spawner, shutdown) {
+        Ok(connection) => connection.run(reader),
+        Err(e) => println!("Failed to start HTTP/2: {e}"),
+    }
+}
+
+/// Turns away a prior-knowledge connection because too many are open
+/// already. GOAWAY naming stream 0 tells the client nothing was processed.
+pub fn refuse<R: BufRead>(reader: &mut R, mut stream: &TcpStream) {
+    let mut payload = 0u32.to_be_bytes().to_vec();
+    payload.extend_from_slice(&ErrorCode::NO_ERROR.0.to_be_bytes());
+    let result = frame::write(&mut stream, frame::SETTINGS, 0, 0, &[])
+        .and_then(|()| frame::write(&mut stream, frame::GOAWAY, 0, 0, &payload))
+        .and_then(|()| stream.shutdown(Shutdown::Write));
+    if let Err(e) = result {
+        println!("Failed to refuse HTTP/2 connection: {e}");
+        return;
+    }
+    // Closing with the client's frames unread would reset the connection,
+    // and the client might lose the GOAWAY. The read timeout bounds this.
+    let _ = io::copy(&mut reader.take(MAX_HEADER_LIST_SIZE as u64), &mut io::sink());
+}
+
+/// Answers an upgrade request with `101 Switching Protocols` and serves the
+/// rest of the connection as HTTP/2, with the upgrade request as stream 1.
+pub fn serve_upgrade<R: BufRead>(
+    upgrade: Upgrade,
+    reader: &mut R,
+    mut stream: &TcpStream,
+    app: Arc<dyn Handler>,
+    spawner: Spawner,
+    shutdown: Arc<AtomicBool>,
+) {
+    let switching = b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+    if let Err(e) = stream.write_all(switching) {
+        println!("Failed to write response: {e}");
+        return;
+    }
+
+    let mut connection = match Connection::new(stream, app, spawner, shutdown) {
+        Ok(connection) => connection,
+        Err(e) => {
+            println!("Failed to start HTTP/2: {e}");
+            return;
+        }
+    };
+    // The settings in the header count as the client's first SETTINGS frame,
+    // and the 101 acknowledges them.
+    if let Err(error) = connection.apply_settings(&upgrade.settings) {
+        connection.fail(error);
+        return;
+    }
+    connection.last_stream_id = 1;
+    connection.out.open(1);
+    connection.dispatch(1, upgrade.request, Vec::new(), 0);
+
+    let mut preface = [0; 24];
+    if reader.read_exact(&mut preface).is_err() || preface != frame::PREFACE {
+        connection.fail(Error::Connection(ErrorCode::PROTOCOL_ERROR, "missing connection preface"));
+        return;
+    }
+    connection.run(reader);
+}
+
+/// Something that ends the connection.
+#[derive(Debug)]
+enum Error {
+    Connection(ErrorCode, &'static str),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// A stream whose request is still arriving.
+struct Incoming {
+    request: Request<'static>,
+    body: Vec<u8>,
+    content_length: Option<usize>,
+    /// How much more DATA the stream's window allows.
+    recv_window: i64,
+    /// Flow-controlled bytes received, padding included, that the client
+    /// gets back once the body is no longer held.
+    credit: i64,
+}
+
+/// A header block split over HEADERS and CONTINUATION frames.
+struct HeaderBlock {
+    stream_id: u32,
+    end_stream: bool,
+    fragment: Vec<u8>,
+}
+
+/// The reading side of a connection.
+struct Connection {
+    out: Arc<Output>,
+    app: Arc<dyn Handler>,
+    spawner: Spawner,
+    decoder: hpack::Decoder,
+    incoming: HashMap<u32, Incoming>,
+    last_stream_id: u32,
+    continuation: Option<HeaderBlock>,
+    got_settings: bool,
+    /// The client sent GOAWAY, or we did for shutdown; no new streams will
+    /// be accepted.
+    going_away: bool,
+    /// Set when the server shuts down.
+    shutdown: Arc<AtomicBool>,
+    /// We sent GOAWAY for shutdown and stop once the open streams are done.
+    shutting_down: bool,
+}
+
+impl Connection {
+    /// Sets up the connection and sends our SETTINGS.
+    fn new(
+        stream: &TcpStream,
+        app: Arc<dyn Handler>,
+        spawner: Spawner,
+        shutdown: Arc<AtomicBool>,
+    ) -> io::Result<Connection> {
+        // We always write whole frames, so there is nothing for Nagle's
+        // algorithm to coalesce; it would only delay small frames.
+        stream.set_nodelay(true)?;
+        let out = Arc::new(Output::new(stream.try_clone()?));
+
+        let mut settings = Vec::new();
+        settings.extend_from_slice(&frame::setting(
+            frame::SETTINGS_MAX_CONCURRENT_STREAMS,
+            MAX_CONCURRENT_STREAMS as u32,
+        ));
+        settings.extend_from_slice(&frame::setting(
+            frame::SETTINGS_MAX_HEADER_LIST_SIZE,
+            MAX_HEADER_LIST_SIZE as u32,
+        ));
+        settings.extend_from_slice(&frame::setting(frame::SETTINGS_INITIAL_WINDOW_SIZE, RECV_WINDOW as u32));
+        out.frame(frame::SETTINGS, 0, 0, &settings)?;
+        // The connection window can only be raised with an update.
+        out.window_update(0, (RECV_WINDOW - frame::DEFAULT_WINDOW) as u32)?;
+
+        Ok(Connection {
+            out,
+            app,
+            spawner,
+            decoder: hpack::Decoder::new(hpack::DEFAULT_TABLE_SIZE),
+            incoming: HashMap::new(),
+            last_stream_id: 0,
+            continuation: None,
+            got_settings: false,
+            going_away: false,
+            shutdown,
+            shutting_down: false,
+        })
+    }
+
+    fn run<R: BufRead>(mut self, reader: &mut R) {
+        if let Err(error) = self.read_frames(reader) {
+            self.fail(error);
+        }
+    }
+
+    /// Reports `error` to the client, if it is a protocol error, and stops.
+    fn fail(self, error: Error) {
+        match error {
+            Error::Connection(code, reason) => {
+                println!("HTTP/2 connection error: {reason}");
+                let _ = self.go_away(code);
+            }
+            Error::Io(e) => {
+                if !matches!(e.kind(), ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset) {
+                    println!("HTTP/2 connection failed: {e}");
+                }
+            }
+        }
+    }
+
+    fn read_frames<R: BufRead>(&mut self, reader: &mut R) -> Result<(), Error> {
+        loop {
+            // Checked between frames and whenever a read times out, so a
+            // quiet connection notices within the idle timeout.
+            if !self.shutting_down && self.continuation.is_none() && self.shutdown.load(Ordering::SeqCst) {
+                self.go_away(ErrorCode::NO_ERROR)?;
+                self.going_away = true;
+                self.shutting_down = true;
+            }
+            if self.shutting_down && self.out.open_streams() == 0 {
+                return Ok(());
+            }
+
+            match reader.fill_buf() {
+                Ok([]) => return Ok(()),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // Idle between frames. While a stream is open, whether its
+                // handler is still running or its response waits for the
+                // client to grant window, the WINDOW_UPDATEs it needs can
+                // only arrive here, so keep reading, unless we are shutting
+                // down and have already waited for them once. Otherwise say
+                // goodbye politely, so the client knows which requests were
+                // seen.
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) && self.continuation.is_none() => {
+                    if self.shutting_down {
+                        return Ok(());
+                    }
+                    if self.out.open_streams() > 0 {
+                        continue;
+                    }
+                    self.go_away(ErrorCode::NO_ERROR)?;
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            let mut head = [0; frame::HEADER_LEN];
+            reader.read_exact(&mut head)?;
+            let header = FrameHeader::parse(&head);
+            if header.length > frame::DEFAULT_MAX_FRAME_SIZE {
+                return Err(Error::Connection(ErrorCode::FRAME_SIZE_ERROR, "frame too large"));
+            }
+            let payload = frame::read_payload(reader, &header)?;
+            self.handle_frame(header, &payload)?;
+        }
+    }
+
+    fn handle_frame(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if !self.got_settings && header.kind != frame::SETTINGS {
+            return Err(protocol_error("first frame is not SETTINGS"));
+        }
+        if let Some(block) = &self.continuation
+            && (header.kind != frame::CONTINUATION || header.stream_id != block.stream_id)
+        {
+            return Err(protocol_error("header block interrupted"));
+        }
+
+        match header.kind {
+            frame::DATA => self.on_data(header, payload),
+            frame::HEADERS => self.on_headers(header, payload),
+            frame::CONTINUATION => self.on_continuation(header, payload),
+            frame::PRIORITY => {
+                if header.stream_id == 0 {
+                    return Err(protocol_error("PRIORITY on stream 0"));
+                }
+                if payload.len() != 5 {
+                    self.reset(header.stream_id, ErrorCode::FRAME_SIZE_ERROR)?;
+                }
+                Ok(())
+            }
+            frame::RST_STREAM => self.on_rst_stream(header, payload),
+            frame::SETTINGS => self.on_settings(header, payload),
+            frame::PUSH_PROMISE => Err(protocol_error("clients cannot push")),
+            frame::PING => {
+                if header.stream_id != 0 {
+                    return Err(protocol_error("PING on a stream"));
+                }
+                if payload.len() != 8 {
+                    return Err(Error::Connection(ErrorCode::FRAME_SIZE_ERROR, "bad PING size"));
+                }
+                if !header.has(frame::FLAG_ACK) {
+                    self.out.frame(frame::PING, frame::FLAG_ACK, 0, payload)?;
+                }
+                Ok(())
+            }
+            frame::GOAWAY => {
+                if header.stream_id != 0 {
+                    return Err(protocol_error("GOAWAY on a stream"));
+                }
+                self.going_away = true;
+                Ok(())
+            }
+            frame::WINDOW_UPDATE => self.on_window_update(header, payload),
+            // Unknown frame types must be ignored.
+            _ => Ok(()),
+        }
+    }
+
+    fn on_data(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        let id = header.stream_id;
+        if id == 0 {
+            return Err(protocol_error("DATA on stream 0"));
+        }
+        let data = frame::unpad(&header, payload).ok_or_else(|| protocol_error("bad padding"))?;
+
+        // Padding counts against flow control too.
+        let length = payload.len() as i64;
+        if !self.out.take_credit(length) {
+            return Err(Error::Connection(ErrorCode::FLOW_CONTROL_ERROR, "connection window exceeded"));
+        }
+
+        let Some(incoming) = self.incoming.get_mut(&id) else {
+            if id > self.last_stream_id {
+                return Err(protocol_error("DATA on idle stream"));
+            }
+            // Nothing holds on to this data, so its credit goes straight back.
+            self.out.release(length)?;
+            return self.reset(id, ErrorCode::STREAM_CLOSED);
+        };
+        incoming.credit += length;
+        incoming.recv_window -= length;
+        if incoming.recv_window < 0 {
+            return self.reset(id, ErrorCode::FLOW_CONTROL_ERROR);
+        }
+        if incoming.body.len() + data.len() > MAX_BODY {
+            return self.reset(id, ErrorCode::ENHANCE_YOUR_CALM);
+        }
+        incoming.body.extend_from_slice(&payload[data]);
+
+        if header.has(frame::FLAG_END_STREAM) {
+            self.finish(id)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn on_headers(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if header.stream_id == 0 {
+            return Err(protocol_error("HEADERS on stream 0"));
+        }
+        let mut range = frame::unpad(&header, payload).ok_or_else(|| protocol_error("bad padding"))?;
+        if header.has(frame::FLAG_PRIORITY) {
+            // Stream dependency and weight; we do not prioritize.
+            if range.len() < 5 {
+                return Err(Error::Connection(ErrorCode::FRAME_SIZE_ERROR, "short HEADERS"));
+            }
+            range.start += 5;
+        }
+
+        let block = HeaderBlock {
+            stream_id: header.stream_id,
+            end_stream: header.has(frame::FLAG_END_STREAM),
+            fragment: payload[range].to_vec(),
+        };
+        if header.has(frame::FLAG_END_HEADERS) {
+            self.on_header_block(block)
+        } else {
+            self.continuation = Some(block);
+            Ok(())
+        }
+    }
+
+    fn on_continuation(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        let mut block = self
+            .continuation
+            .take()
+            .ok_or_else(|| protocol_error("unexpected CONTINUATION"))?;
+        if block.fragment.len() + payload.len() > MAX_HEADER_LIST_SIZE {
+            return Err(Error::Connection(ErrorCode::ENHANCE_YOUR_CALM, "header block too large"));
+        }
+        block.fragment.extend_from_slice(payload);
+        if header.has(frame::FLAG_END_HEADERS) {
+            self.on_header_block(block)
+        } else {
+            self.continuation = Some(block);
+            Ok(())
+        }
+    }
+
+    fn on_header_block(&mut self, block: HeaderBlock) -> Result<(), Error> {
+        // Always decode, even for streams we refuse, to keep the table in sync.
+        let fields = self
+            .decoder
+            .decode(&block.fragment, MAX_HEADER_LIST_SIZE)
+            .map_err(|_| Error::Connection(ErrorCode::COMPRESSION_ERROR, "bad header block"))?;
+        let id = block.stream_id;
+
+        if self.incoming.contains_key(&id) {
+            // Trailers. They have to end the stream; we drop their contents.
+            if !block.end_stream {
+                return self.reset(id, ErrorCode::PROTOCOL_ERROR);
+            }
+            return self.finish(id);
+        }
+        if id.is_multiple_of(2) {
+            return Err(protocol_error("even stream id from client"));
+        }
+        if id <= self.last_stream_id {
+            return Err(Error::Connection(ErrorCode::STREAM_CLOSED, "HEADERS on closed stream"));
+        }
+        self.last_stream_id = id;
+
+        if self.going_away || self.out.open_streams() >= MAX_CONCURRENT_STREAMS {
+            return self.reset(id, ErrorCode::REFUSED_STREAM);
+        }
+        let (request, content_length) = match build_request(fields) {
+            Ok(parts) => parts,
+            Err(reason) => {
+                println!("Rejecting HTTP/2 stream {id}: {reason}");
+                return self.reset(id, ErrorCode::PROTOCOL_ERROR);
+            }
+        };
+
+        self.out.open(id);
+        self.incoming.insert(
+            id,
+            Incoming {
+                request,
+                body: Vec::new(),
+                content_length,
+                recv_window: RECV_WINDOW,
+                credit: 0,
+            },
+        );
+        if block.end_stream {
+            self.finish(id)?;
+        }
+        Ok(())
+    }
+
+    fn on_rst_stream(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if header.stream_id == 0 {
+            return Err(protocol_error("RST_STREAM on stream 0"));
+        }
+        if payload.len() != 4 {
+            return Err(Error::Connection(ErrorCode::FRAME_SIZE_ERROR, "bad RST_STREAM size"));
+        }
+        if header.stream_id > self.last_stream_id {
+            return Err(protocol_error("RST_STREAM on idle stream"));
+        }
+        self.discard(header.stream_id)?;
+        self.out.close(header.stream_id);
+        Ok(())
+    }
+
+    fn on_settings(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if header.stream_id != 0 {
+            return Err(protocol_error("SETTINGS on a stream"));
+        }
+        if header.has(frame::FLAG_ACK) {
+            if !payload.is_empty() {
+                return Err(Error::Connection(ErrorCode::FRAME_SIZE_ERROR, "SETTINGS ACK with payload"));
+            }
+            return Ok(());
+        }
+        if !payload.len().is_multiple_of(6) {
+            return Err(Error::Connection(ErrorCode::FRAME_SIZE_ERROR, "bad SETTINGS size"));
+        }
+        self.apply_settings(payload)?;
+        self.got_settings = true;
+        self.out.frame(frame::SETTINGS, frame::FLAG_ACK, 0, &[])?;
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), Error> {
+        for (id, value) in frame::settings(payload) {
+            match id {
+                frame::SETTINGS_ENABLE_PUSH if value > 1 => {
+                    return Err(protocol_error("bad SETTINGS_ENABLE_PUSH"));
+                }
+                frame::SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > frame::MAX_WINDOW {
+                        return Err(Error::Connection(ErrorCode::FLOW_CONTROL_ERROR, "initial window too large"));
+                    }
+                    self.out.set_initial_window(value as i64)?;
+                    self.out.flush()?;
+                }
+                frame::SETTINGS_MAX_FRAME_SIZE => {
+                    let size = value as usize;
+                    if !(frame::DEFAULT_MAX_FRAME_SIZE..=frame::MAX_FRAME_SIZE_LIMIT).contains(&size) {
+                        return Err(protocol_error("bad SETTINGS_MAX_FRAME_SIZE"));
+                    }
+                    self.out.state.lock().unwrap().max_frame_size = size;
+                }
+                // Our encoder never uses the dynamic table, so the table size
+                // does not matter, and nothing else affects how we respond.
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn on_window_update(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        let bytes: [u8; 4] = payload
+            .try_into()
+            .map_err(|_| Error::Connection(ErrorCode::FRAME_SIZE_ERROR, "bad WINDOW_UPDATE size"))?;
+        let increment = (u32::from_be_bytes(bytes) & 0x7fff_ffff) as i64;
+        let id = header.stream_id;
+
+        if id == 0 {
+            if increment == 0 {
+                return Err(protocol_error("zero WINDOW_UPDATE"));
+            }
+            if !self.out.grow_window(0, increment) {
+                return Err(Error::Connection(ErrorCode::FLOW_CONTROL_ERROR, "connection window overflow"));
+            }
+            self.out.flush()?;
+            return Ok(());
+        }
+        if id > self.last_stream_id {
+            return Err(protocol_error("WINDOW_UPDATE on idle stream"));
+        }
+        if increment == 0 {
+            return self.reset(id, ErrorCode::PROTOCOL_ERROR);
+        }
+        if !self.out.grow_window(id, increment) {
+            return self.reset(id, ErrorCode::FLOW_CONTROL_ERROR);
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// The client finished sending a request; hand it to the pool.
+    fn finish(&mut self, id: u32) -> Result<(), Error> {
+        let Some(incoming) = self.incoming.get(&id) else {
+            return Ok(());
+        };
+        if incoming.content_length.is_some_and(|len| len != incoming.body.len()) {
+            return self.reset(id, ErrorCode::PROTOCOL_ERROR);
+        }
+        let incoming = self.incoming.remove(&id).unwrap();
+        self.dispatch(id, incoming.request, incoming.body, incoming.credit);
+        Ok(())
+    }
+
+    /// Runs the handler on the stream pool. The body's credit goes back to
+    /// the client only once the handler is done with it.
+    fn dispatch(&self, id: u32, request: Request<'static>, body: Vec<u8>, credit: i64) {
+        let out = Arc::clone(&self.out);
+        let app = Arc::clone(&self.app);
+
+        self.spawner.execute(move || {
+            let mut request = request.with_body(Cursor::new(body));
+            let response = app.handle(&mut request);
+            drop(request);
+            let result = out.release(credit).and_then(|()| out.send_response(id, response));
+            if let Err(e) = result {
+                println!("Failed to write response: {e}");
+            }
+        });
+    }
+
+    /// Forgets a stream's partial request and gives back its credit.
+    fn discard(&mut self, id: u32) -> io::Result<()> {
+        match self.incoming.remove(&id) {
+            Some(incoming) => self.out.release(incoming.credit),
+            None => Ok(()),
+        }
+    }
+
+    fn reset(&mut self, id: u32, code: ErrorCode) -> Result<(), Error> {
+        self.discard(id)?;
+        self.out.close(id);
+        self.out.frame(frame::RST_STREAM, 0, id, &code.0.to_be_bytes())?;
+        Ok(())
+    }
+
+    fn go_away(&self, code: ErrorCode) -> io::Result<()> {
+        let mut payload = self.last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.0.to_be_bytes());
+        self.out.frame(frame::GOAWAY, 0, 0, &payload)
+    }
+}
+
+fn protocol_error(reason: &'static str) -> Error {
+    Error::Connection(ErrorCode::PROTOCOL_ERROR, reason)
+}
+
+/// Turns a decoded header list into a request, checking the rules RFC 9113
+/// section 8.2 and 8.3 set for requests.
+fn build_request(fields: hpack::HeaderList) -> Result<(Request<'static>, Option<usize>), &'static str> {
+    let mut method = None;
+    let mut path = None;
+    let mut scheme = None;
+    let mut authority = None;
+    let mut headers = Headers::new();
+
+    for (name, value) in fields {
+        let name = String::from_utf8(name).map_err(|_| "header name is not UTF-8")?;
+        let value = String::from_utf8(value).map_err(|_| "header value is not UTF-8")?;
+
+        if let Some(pseudo) = name.strip_prefix(':') {
+            if !headers.is_empty() {
+                return Err("pseudo-header after regular header");
+            }
+            let slot = match pseudo {
+                "method" => &mut method,
+                "path" => &mut path,
+                "scheme" => &mut scheme,
+                "authority" => &mut authority,
+                _ => return Err("unknown pseudo-header"),
+            };
+            if slot.replace(value).is_some() {
+                return Err("duplicate pseudo-header");
+            }
+            continue;
+        }
+
+        if name.is_empty() || name.bytes().any(|b| b.is_ascii_uppercase()) {
+            return Err("header name is not lowercase");
+        }
+        if CONNECTION_HEADERS.contains(&name.as_str()) {
+            return Err("connection-specific header");
+        }
+        if name == "te" && value != "trailers" {
+            return Err("TE other than trailers");
+        }
+        headers.append(name, value);
+    }
+
+    let method = method.ok_or("missing :method")?;
+    if method == "CONNECT" {
+        return Err("CONNECT is not supported");
+    }
+    let path = path.filter(|p| !p.is_empty()).ok_or("missing :path")?;
+    scheme.ok_or("missing :scheme")?;
+    if let Some(authority) = authority
+        && headers.get("host").is_none()
+    {
+        headers.append("host", authority);
+    }
+    let content_length = match headers.get("content-length") {
+        Some(value) => Some(value.parse().map_err(|_| "bad content-length")?),
+        None => None,
+    };
+
+    let mut request = Request::new(method, path);
+    request.version = String::from("HTTP/2.0");
+    request.headers = headers;
+    Ok((request, content_length))
+}
+
+/// The writing side of a connection, shared by the reader and every job
+/// that is sending a response.
+struct Output {
+    state: Mutex<OutputState>,
+}
+
+struct OutputState {
+    stream: TcpStream,
+    conn_window: i64,
+    /// How much more DATA the client may send on the connection.
+    recv_window: i64,
+    /// Streams whose response is not fully sent.
+    streams: HashMap<u32, SendStream>,
+    initial_window: i64,
+    max_frame_size: usize,
+}
+
+struct SendStream {
+    window: i64,
+    /// Response body still waiting for flow-control credit. The reader sends
+    /// it as WINDOW_UPDATEs arrive, so no job ever waits on the client.
+    pending: Option<Pending>,
+}
+
+struct Pending {
+    body: Vec<u8>,
+    sent: usize,
+}
+
+impl Output {
+    fn new(stream: TcpStream) -> Output {
+        Output {
+            state: Mutex::new(OutputState {
+                stream,
+                conn_window: frame::DEFAULT_WINDOW,
+                recv_window: RECV_WINDOW,
+                streams: HashMap::new(),
+                initial_window: frame::DEFAULT_WINDOW,
+                max_frame_size: frame::DEFAULT_MAX_FRAME_SIZE,
+            }),
+        }
+    }
+
+    fn frame(&self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        write(&mut state.stream, kind, flags, stream_id, payload)
+    }
+
+    fn window_update(&self, stream_id: u32, increment: u32) -> io::Result<()> {
+        self.frame(frame::WINDOW_UPDATE, 0, stream_id, &increment.to_be_bytes())
+    }
+
+    /// Accounts for `length` bytes of DATA received. Returns false if the
+    /// client sent more than the connection window allows.
+    fn take_credit(&self, length: i64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.recv_window -= length;
+        state.recv_window >= 0
+    }
+
+    /// Gives `length` bytes of connection window back to the client.
+    fn release(&self, length: i64) -> io::Result<()> {
+        if length == 0 {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        state.recv_window += length;
+        write(&mut state.stream, frame::WINDOW_UPDATE, 0, 0, &(length as u32).to_be_bytes())
+    }
+
+    fn open(&self, id: u32) {
+        let mut state = self.state.lock().unwrap();
+        let window = state.initial_window;
+        state.streams.insert(id, SendStream { window, pending: None });
+    }
+
+    fn close(&self, id: u32) {
+        self.state.lock().unwrap().streams.remove(&id);
+    }
+
+    fn open_streams(&self) -> usize {
+        self.state.lock().unwrap().streams.len()
+    }
+
+    /// Adds to the connection window (`id` 0) or a stream's window. Returns
+    /// false if the window would overflow.
+    fn grow_window(&self, id: u32, increment: i64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let window = if id == 0 {
+            &mut state.conn_window
+        } else {
+            match state.streams.get_mut(&id) {
+                Some(stream) => &mut stream.window,
+                // Already done with this stream; late updates are fine.
+                None => return true,
+            }
+        };
+        *window += increment;
+        *window <= frame::MAX_WINDOW
+    }
+
+    /// Applies a new `SETTINGS_INITIAL_WINDOW_SIZE` to every open stream.
+    fn set_initial_window(&self, size: i64) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let delta = size - state.initial_window;
+        state.initial_window = size;
+        for stream in state.streams.values_mut() {
+            stream.window += delta;
+            if stream.window > frame::MAX_WINDOW {
+                return Err(Error::Connection(ErrorCode::FLOW_CONTROL_ERROR, "stream window overflow"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends `response` on stream `id`, as much of the body as the windows
+    /// allow; the rest is queued for [`Output::flush`]. Does nothing if the
+    /// client has reset the stream.
+    fn send_response(&self, id: u32, response: Response) -> io::Result<()> {
+        let status = response.status.code().to_string();
+        let length = response.body.len().to_string();
+        let mut fields = vec![(String::from(":status"), status)];
+        for (name, value) in response.headers.iter() {
+            let name = name.to_ascii_lowercase();
+            if !CONNECTION_HEADERS.contains(&name.as_str()) && name != "content-length" {
+                fields.push((name, value.to_string()));
+            }
+        }
+        fields.push((String::from("content-length"), length));
+        let mut block = Vec::new();
+        hpack::Encoder::new().encode(fields.iter().map(|(n, v)| (n.as_str(), v.as_str())), &mut block);
+
+        let body = response.body;
+        let mut state = self.state.lock().unwrap();
+        if !state.streams.contains_key(&id) {
+            return Ok(());
+        }
+
+        let max = state.max_frame_size;
+        let mut chunks = block.chunks(max).peekable();
+        let mut kind = frame::HEADERS;
+        let mut flags = if body.is_empty() { frame::FLAG_END_STREAM } else { 0 };
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= frame::FLAG_END_HEADERS;
+            }
+            write(&mut state.stream, kind, flags, id, chunk)?;
+            kind = frame::CONTINUATION;
+            flags = 0;
+        }
+
+        if body.is_empty() {
+            state.streams.remove(&id);
+            return Ok(());
+        }
+        if let Some(stream) = state.streams.get_mut(&id) {
+            stream.pending = Some(Pending { body, sent: 0 });
+        }
+        state.send_pending(id)
+    }
+
+    /// Sends queued DATA after the windows have grown, lowest stream first,
+    /// until the connection window runs out.
+    fn flush(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut ids: Vec<u32> = state
+            .streams
+            .iter()
+            .filter(|(_, stream)| stream.pending.is_some())
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        for id in ids {
+            if state.conn_window <= 0 {
+                break;
+            }
+            state.send_pending(id)?;
+        }
+        Ok(())
+    }
+}
+
+impl OutputState {
+    /// Sends as much of stream `id`'s queued body as the windows allow and
+    /// forgets the stream once all of it is out.
+    fn send_pending(&mut self, id: u32) -> io::Result<()> {
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return Ok(());
+        };
+        let Some(pending) = &mut stream.pending else {
+            return Ok(());
+        };
+        while pending.sent < pending.body.len() {
+            let window = self.conn_window.min(stream.window);
+            if window <= 0 {
+                return Ok(());
+            }
+            let (sent, len) = (pending.sent, pending.body.len());
+            let n = (len - sent).min(self.max_frame_size).min(window as usize);
+            let flags = if sent + n == len { frame::FLAG_END_STREAM } else { 0 };
+            write(&mut self.stream, frame::DATA, flags, id, &pending.body[sent..sent + n])?;
+            pending.sent += n;
+            self.conn_window -= n as i64;
+            stream.window -= n as i64;
+        }
+        self.streams.remove(&id);
+        Ok(())
+    }
+}
+
+/// Writes one frame. Every writer holds the lock while it writes, so a
+/// client that stops reading would hold up every job sending on this
+/// connection; once a write times out the connection is reset instead,
+/// which fails the writes behind it straight away and ends the reader.
+fn write(stream: &mut TcpStream, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> io::Result<()> {
+    let result = frame::write(stream, kind, flags, stream_id, payload);
+    if let Err(e) = &result
+        && matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+    {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    result
+}
+
+/// Decodes unpadded base64url, as used by the `HTTP2-Settings` header.
+fn base64url_decode(input: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in input.trim().trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,501 @@
+//! Just enough HTTP/1.1 to serve pages and accept uploads.
+//!
+//! A [`Request`] is parsed from anything implementing [`BufRead`]. Its body is
+//! not read up front: it is exposed as a [`Body`] that streams the bytes the
+//! client announced with `Content-Length` or `Transfer-Encoding: chunked`.
+
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+};
+
+/// Longest request line or header line we accept, in bytes.
+const MAX_LINE: u64 = 8 * 1024;
+
+/// Most header fields we accept in one request.
+const MAX_HEADERS: usize = 100;
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// The peer closed the connection before sending a request line.
+    Closed,
+    /// The request line is not `METHOD TARGET HTTP/x.y`.
+    BadRequestLine,
+    /// A header line is malformed or a framing header has a bad value.
+    BadHeader,
+    /// A line or the header section is larger than we are willing to buffer.
+    TooLarge,
+    Io(io::Error),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Closed => f.write_str("connection closed"),
+            ParseError::BadRequestLine => f.write_str("malformed request line"),
+            ParseError::BadHeader => f.write_str("malformed header"),
+            ParseError::TooLarge => f.write_str("request head too large"),
+            ParseError::Io(e) => write!(f, "i/o error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> Self {
+        ParseError::Io(e)
+    }
+}
+
+/// An ordered list of header fields with case-insensitive lookup.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers(Vec::new())
+    }
+
+    /// Returns the first value of the named header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns every value of the named header, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Adds a value, keeping any existing values of the same name.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.0.push((name.into(), value.into()));
+    }
+
+    /// Replaces every value of the named header with `value`.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.0.push((name, value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The body of a request, read lazily from the connection.
+pub struct Body<'a> {
+    inner: Box<dyn Read + Send + 'a>,
+}
+
+impl<'a> Body<'a> {
+    pub fn new(reader: impl Read + Send + 'a) -> Body<'a> {
+        Body {
+            inner: Box::new(reader),
+        }
+    }
+
+    pub fn empty() -> Body<'a> {
+        Body::new(io::empty())
+    }
+
+    /// Reads and discards whatever is left of the body.
+    pub fn drain(&mut self) -> io::Result<u64> {
+        io::copy(self, &mut io::sink())
+    }
+}
+
+impl Read for Body<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl fmt::Debug for Body<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Body { .. }")
+    }
+}
+
+#[derive(Debug)]
+pub struct Request<'a> {
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub headers: Headers,
+    body: Body<'a>,
+}
+
+impl<'a> Request<'a> {
+    /// Builds a request with no headers and an empty body.
+    pub fn new(method: impl Into<String>, target: impl Into<String>) -> Request<'a> {
+        Request {
+            method: method.into(),
+            target: target.into(),
+            version: String::from("HTTP/1.1"),
+            headers: Headers::new(),
+            body: Body::empty(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Request<'a> {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Read + Send + 'a) -> Request<'a> {
+        self.body = Body::new(body);
+        self
+    }
+
+    /// Parses the request line and headers from `reader`.
+    ///
+    /// The returned request borrows `reader` for its body, so nothing past the
+    /// end of this request is consumed.
+    pub fn read_from<R: BufRead + Send>(reader: &'a mut R) -> Result<Request<'a>, ParseError> {
+        let request_line = match read_line(reader)? {
+            Some(line) => line,
+            None => return Err(ParseError::Closed),
+        };
+
+        let mut parts = request_line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(m), Some(t), Some(v), None) if !m.is_empty() && !t.is_empty() && v.starts_with("HTTP/") => {
+                (m.to_string(), t.to_string(), v.to_string())
+            }
+            _ => return Err(ParseError::BadRequestLine),
+        };
+
+        let mut headers = Headers::new();
+        loop {
+            let line = read_line(reader)?.ok_or(ParseError::BadHeader)?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(ParseError::TooLarge);
+            }
+            let (name, value) = line.split_once(':').ok_or(ParseError::BadHeader)?;
+            if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace()) {
+                return Err(ParseError::BadHeader);
+            }
+            headers.append(name, value.trim());
+        }
+
+        let body = match (is_chunked(&headers)?, content_length(&headers)?) {
+            // Something in front of us may have picked the other framing, and
+            // then the two disagree on where the next request starts.
+            (true, Some(_)) => return Err(ParseError::BadHeader),
+            (true, None) => Body::new(ChunkedReader::new(reader)),
+            (false, Some(length)) => Body::new(FixedLengthReader::new(reader, length)),
+            (false, None) => Body::empty(),
+        };
+
+        Ok(Request {
+            method,
+            target,
+            version,
+            headers,
+            body,
+        })
+    }
+
+    /// The target without its query string.
+    pub fn path(&self) -> &str {
+        match self.target.split_once('?') {
+            Some((path, _)) => path,
+            None => &self.target,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Whether the client wants to send another request on this connection.
+    ///
+    /// HTTP/1.1 connections stay open unless the client says `close`; HTTP/1.0
+    /// ones close unless it says `keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection").unwrap_or("");
+        let has = |token: &str| connection.split(',').any(|t| t.trim().eq_ignore_ascii_case(token));
+        if self.version == "HTTP/1.0" {
+            has("keep-alive")
+        } else {
+            !has("close")
+        }
+    }
+
+    /// The declared body length, if the client sent a valid `Content-Length`.
+    pub fn content_length(&self) -> Option<u64> {
+        content_length(&self.headers).ok().flatten()
+    }
+
+    pub fn body(&mut self) -> &mut Body<'a> {
+        &mut self.body
+    }
+}
+
+/// The body length from every `Content-Length` field. Repeats of the same
+/// value are allowed; different values are an error.
+fn content_length(headers: &Headers) -> Result<Option<u64>, ParseError> {
+    let mut length = None;
+    for value in headers.get_all("Content-Length").flat_map(|v| v.split(',')) {
+        let value: u64 = value.trim().parse().map_err(|_| ParseError::BadHeader)?;
+        if length.is_some_and(|length| length != value) {
+            return Err(ParseError::BadHeader);
+        }
+        length = Some(value);
+    }
+    Ok(length)
+}
+
+fn is_chunked(headers: &Headers) -> Result<bool, ParseError> {
+    let mut codings = headers.get_all("Transfer-Encoding").flat_map(|v| v.split(','));
+    match (codings.next(), codings.next()) {
+        (Some(coding), None) if coding.trim().eq_ignore_ascii_case("chunked") => Ok(true),
+        // We do not implement any other coding, so refuse rather than guess
+        // where the body ends.
+        (Some(_), _) => Err(ParseError::BadHeader),
+        (None, _) => Ok(false),
+    }
+}
+
+/// Reads one CRLF (or bare LF) terminated line, without the terminator.
+///
+/// Returns `None` on a clean end of stream before any byte was read.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let read = reader.by_ref().take(MAX_LINE + 1).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(if read as u64 > MAX_LINE {
+            ParseError::TooLarge
+        } else {
+            ParseError::Io(io::ErrorKind::UnexpectedEof.into())
+        });
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::BadHeader)
+}
+
+/// Reads a `Content-Length` body.
+///
+/// Unlike [`Read::take`] this fails with `UnexpectedEof` if the connection
+/// ends early, so a truncated body is not mistaken for a complete one.
+struct FixedLengthReader<R> {
+    reader: R,
+    remaining: u64,
+}
+
+impl<R: Read> FixedLengthReader<R> {
+    fn new(reader: R, length: u64) -> Self {
+        FixedLengthReader {
+            reader,
+            remaining: length,
+        }
+    }
+}
+
+impl<R: Read> Read for FixedLengthReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let max = buf.len().min(self.remaining as usize);
+        let n = self.reader.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body.
+struct ChunkedReader<R> {
+    reader: R,
+    /// Bytes left in the current chunk.
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(reader: R) -> Self {
+        ChunkedReader {
+            reader,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    fn next_chunk_size(&mut self) -> io::Result<u64> {
+        let line = read_line(&mut self.reader)
+            .map_err(invalid_data)?
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        // Chunk extensions follow a ';' and carry nothing we need.
+        let size = line.split(';').next().unwrap_or("").trim();
+        u64::from_str_radix(size, 16).map_err(invalid_data)
+    }
+
+    fn expect_crlf(&mut self) -> io::Result<()> {
+        match read_line(&mut self.reader).map_err(invalid_data)? {
+            Some(line) if line.is_empty() => Ok(()),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "missing CRLF after chunk")),
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.remaining = self.next_chunk_size()?;
+            if self.remaining == 0 {
+                // Skip trailer fields up to the terminating empty line.
+                while let Some(line) = read_line(&mut self.reader).map_err(invalid_data)? {
+                    if line.is_empty() {
+                        break;
+                    }
+                }
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let max = buf.len().min(self.remaining as usize);
+        let n = self.reader.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u64;
+        if self.remaining == 0 {
+            self.expect_crlf()?;
+        }
+        Ok(n)
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    code: u16,
+    reason: &'static str,
+}
+
+impl Status {
+    pub const OK: Status = Status::new(200, "OK");
+    pub const CREATED: Status = Status::new(201, "Created");
+    pub const BAD_REQUEST: Status = Status::new(400, "Bad Request");
+    pub const NOT_FOUND: Status = Status::new(404, "Not Found");
+    pub const METHOD_NOT_ALLOWED: Status = Status::new(405, "Method Not Allowed");
+    pub const PAYLOAD_TOO_LARGE: Status = Status::new(413, "Payload Too Large");
+    pub const UNSUPPORTED_MEDIA_TYPE: Status = Status::new(415, "Unsupported Media Type");
+    pub const HEADER_FIELDS_TOO_LARGE: Status = Status::new(431, "Request Header Fields Too Large");
+    pub const INTERNAL_SERVER_ERROR: Status = Status::new(500, "Internal Server Error");
+
+    pub const fn new(code: u16, reason: &'static str) -> Status {
+        Status { code, reason }
+    }
+
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+
+    pub fn reason(&self) -> &'static str {
+        self.reason
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.code, self.reason)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: Status,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: Status) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn with_body(status: Status, content_type: &str, body: impl Into<Vec<u8>>) -> Response {
+        let mut response = Response::new(status);
+        response.headers.set("Content-Type", content_type);
+        response.body = body.into();
+        response
+    }
+
+    pub fn text(status: Status, body: impl Into<String>) -> Response {
+        Response::with_body(status, "text/plain; charset=utf-8", body.into())
+    }
+
+    pub fn html(status: Status, body: impl Into<String>) -> Response {
+        Response::with_body(status, "text/html; charset=utf-8", body.into())
+    }
+
+    /// True if the response asks for the connection to be closed after it.
+    pub fn closes_connection(&self) -> bool {
+        self.headers
+            .get("Connection")
+            .is_some_and(|v| v.eq_ignore_ascii_case("close"))
+    }
+
+    /// Serializes the response as HTTP/1.1, filling in `Content-Length`.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+
+        // One write, so Nagle's algorithm does not hold the body back waiting
+        // for the ACK of the head on a keep-alive connection.
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        writer.write_all(&bytes)?;
+        writer.flush()
+    }
+}
@@ -1,8 +1,20 @@
+// The package name has a double dash, which Cargo turns into a crate name
+// that is not snake case.
+#![allow(non_snake_case)]
+
 use std::{
     sync::{Arc, Mutex, mpsc},
     thread,
 };
 
+pub mod app;
+pub mod h2;
+pub mod http;
+pub mod middleware;
+pub mod multipart;
+pub mod server;
+pub mod upload;
+
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: Option<mpsc::Sender<Job>>,
@@ -48,6 +60,33 @@ impl ThreadPool {
 
         self.sender.as_ref().unwrap().send(job).unwrap();
     }
+
+    /// Returns a handle that can submit jobs to this pool from anywhere,
+    /// including from a job already running on it.
+    ///
+    /// Dropping the pool waits for its workers, and they keep running for as
+    /// long as any `Spawner` is alive.
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            sender: self.sender.as_ref().unwrap().clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Spawner {
+    sender: mpsc::Sender<Job>,
+}
+
+impl Spawner {
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Box::new(f);
+
+        self.sender.send(job).unwrap();
+    }
 }
 
 impl Drop for ThreadPool {
@@ -0,0 +1,136 @@
+//! Handlers and the middleware that wraps them.
+//!
+//! A [`Chain`] runs its middleware in the order they were added, outermost
+//! first, and ends at a single [`Handler`]. Each middleware gets the request
+//! and a [`Next`] for the rest of the chain; it may change the request before
+//! calling `next.run`, change the response afterwards, or answer on its own
+//! and never call `next` at all.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use crate::http::{Request, Response};
+
+/// Something that turns a request into a response.
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &mut Request<'_>) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&mut Request<'_>) -> Response + Send + Sync,
+{
+    fn handle(&self, request: &mut Request<'_>) -> Response {
+        self(request)
+    }
+}
+
+/// A layer around a [`Handler`].
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &mut Request<'_>, next: Next<'_>) -> Response;
+}
+
+/// The part of a [`Chain`] that comes after the current middleware.
+pub struct Next<'a> {
+    middleware: &'a [Box<dyn Middleware>],
+    endpoint: &'a dyn Handler,
+}
+
+impl Next<'_> {
+    /// Passes the request on to the next middleware, or to the handler at the
+    /// end of the chain.
+    pub fn run(self, request: &mut Request<'_>) -> Response {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(
+                request,
+                Next {
+                    middleware: rest,
+                    endpoint: self.endpoint,
+                },
+            ),
+            None => self.endpoint.handle(request),
+        }
+    }
+}
+
+pub struct Chain {
+    middleware: Vec<Box<dyn Middleware>>,
+    endpoint: Box<dyn Handler>,
+}
+
+impl Chain {
+    /// Creates a chain with no middleware in front of `endpoint`.
+    pub fn new(endpoint: impl Handler + 'static) -> Chain {
+        Chain {
+            middleware: Vec::new(),
+            endpoint: Box::new(endpoint),
+        }
+    }
+
+    /// Adds a layer inside the ones already added, so the first layer added
+    /// sees the request first and the response last.
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Chain {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+}
+
+impl Handler for Chain {
+    fn handle(&self, request: &mut Request<'_>) -> Response {
+        Next {
+            middleware: &self.middleware,
+            endpoint: self.endpoint.as_ref(),
+        }
+        .run(request)
+    }
+}
+
+/// Prints one line per request with its status and how long it took.
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle(&self, request: &mut Request<'_>, next: Next<'_>) -> Response {
+        let start = Instant::now();
+        let line = format!("{} {}", request.method, request.target);
+
+        let response = next.run(request);
+
+        println!("{line} -> {} ({:?})", response.status, start.elapsed());
+        response
+    }
+}
+
+/// Tags every request and its response with an `X-Request-Id`.
+///
+/// An id sent by the client is kept; otherwise a new one is generated.
+#[derive(Default)]
+pub struct RequestId {
+    counter: AtomicU64,
+}
+
+impl RequestId {
+    pub const HEADER: &'static str = "X-Request-Id";
+
+    pub fn new() -> RequestId {
+        RequestId::default()
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, request: &mut Request<'_>, next: Next<'_>) -> Response {
+        let id = match request.header(Self::HEADER) {
+            Some(id) => id.to_string(),
+            None => {
+                let id = format!("{:x}", self.counter.fetch_add(1, Ordering::Relaxed) + 1);
+                request.headers.set(Self::HEADER, id.as_str());
+                id
+            }
+        };
+
+        let mut response = next.run(request);
+        response.headers.set(Self::HEADER, id);
+        response
+    }
+}
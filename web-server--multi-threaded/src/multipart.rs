@@ -0,0 +1,228 @@
+//! Streaming `multipart/form-data` parser (RFC 7578).
+//!
+//! Parts are handed out one at a time and their contents are read straight
+//! from the underlying body, so a large file never has to fit in memory.
+
+use std::io::{self, Read};
+
+use crate::http::Headers;
+
+/// How much of the body we pull in per read.
+const CHUNK: usize = 8 * 1024;
+
+/// Longest part header line we accept, in bytes.
+const MAX_LINE: usize = 8 * 1024;
+
+/// Most header fields we accept on one part.
+const MAX_HEADERS: usize = 16;
+
+/// Extracts the `boundary` parameter from a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<&str> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, v)| v.trim().trim_matches('"'))
+        .filter(|b| !b.is_empty() && b.len() <= 70)
+}
+
+pub struct Multipart<R> {
+    reader: R,
+    /// `\r\n--boundary`; the leading CRLF belongs to the delimiter, not the data.
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    pos: usize,
+    /// The current part's data has been read up to the next delimiter.
+    at_delimiter: bool,
+    done: bool,
+}
+
+impl<R: Read> Multipart<R> {
+    pub fn new(reader: R, boundary: &str) -> Multipart<R> {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+        Multipart {
+            reader,
+            delimiter,
+            // The first delimiter has no CRLF in front of it; pretend it does so
+            // every delimiter looks the same. Anything before it is preamble,
+            // which we read and throw away like the data of a part.
+            buf: b"\r\n".to_vec(),
+            pos: 0,
+            at_delimiter: false,
+            done: false,
+        }
+    }
+
+    /// Advances to the next part, skipping whatever is left of the current one.
+    ///
+    /// Returns `None` after the closing delimiter.
+    pub fn next_part(&mut self) -> io::Result<Option<Part<'_, R>>> {
+        if self.done {
+            return Ok(None);
+        }
+        while !self.at_delimiter {
+            let mut scratch = [0; CHUNK];
+            self.read_data(&mut scratch)?;
+        }
+
+        self.pos += self.delimiter.len();
+        self.fill(2)?;
+        if &self.buf[self.pos..self.pos + 2] == b"--" {
+            self.done = true;
+            return Ok(None);
+        }
+
+        // Transport padding may follow the boundary before its line break.
+        let padding = self.read_line()?;
+        if !padding.trim().is_empty() {
+            return Err(invalid("garbage after multipart boundary"));
+        }
+
+        let mut headers = Headers::new();
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(invalid("too many part headers"));
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("malformed part header"))?;
+            headers.append(name.trim(), value.trim());
+        }
+
+        self.at_delimiter = false;
+        Ok(Some(Part {
+            headers,
+            multipart: self,
+        }))
+    }
+
+    /// Reads data of the current part, stopping at the next delimiter.
+    fn read_data(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.at_delimiter || out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let available = &self.buf[self.pos..];
+            let safe = match find(available, &self.delimiter) {
+                Some(0) => {
+                    self.at_delimiter = true;
+                    return Ok(0);
+                }
+                Some(i) => i,
+                // Hold back a tail that could be the start of a delimiter.
+                None => available.len().saturating_sub(self.delimiter.len() - 1),
+            };
+            if safe > 0 {
+                let n = safe.min(out.len());
+                out[..n].copy_from_slice(&available[..n]);
+                self.pos += n;
+                return Ok(n);
+            }
+            if self.read_more()? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "multipart body ended before the closing boundary",
+                ));
+            }
+        }
+    }
+
+    /// Reads one CRLF terminated line out of the buffer.
+    fn read_line(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(i) = find(&self.buf[self.pos..], b"\r\n") {
+                let line = String::from_utf8(self.buf[self.pos..self.pos + i].to_vec())
+                    .map_err(|_| invalid("part header is not UTF-8"))?;
+                self.pos += i + 2;
+                return Ok(line);
+            }
+            if self.buf.len() - self.pos > MAX_LINE {
+                return Err(invalid("part header line too long"));
+            }
+            if self.read_more()? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    /// Makes sure at least `n` unread bytes are buffered.
+    fn fill(&mut self, n: usize) -> io::Result<()> {
+        while self.buf.len() - self.pos < n {
+            if self.read_more()? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn read_more(&mut self) -> io::Result<usize> {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        let start = self.buf.len();
+        self.buf.resize(start + CHUNK, 0);
+        let result = loop {
+            match self.reader.read(&mut self.buf[start..]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+        self.buf.truncate(start + result.as_ref().copied().unwrap_or(0));
+        result
+    }
+}
+
+/// One part of a multipart body. Reading from it yields the part's contents.
+pub struct Part<'m, R> {
+    pub headers: Headers,
+    multipart: &'m mut Multipart<R>,
+}
+
+impl<R: Read> Part<'_, R> {
+    /// The form field name from `Content-Disposition`.
+    pub fn name(&self) -> Option<&str> {
+        self.disposition_param("name")
+    }
+
+    /// The client supplied file name, present on file fields only.
+    pub fn file_name(&self) -> Option<&str> {
+        self.disposition_param("filename")
+    }
+
+    fn disposition_param(&self, key: &str) -> Option<&str> {
+        let disposition = self.headers.get("Content-Disposition")?;
+        disposition
+            .split(';')
+            .skip(1)
+            .filter_map(|p| p.split_once('='))
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case(key))
+            .map(|(_, v)| {
+                let v = v.trim();
+                v.strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(v)
+            })
+    }
+}
+
+impl<R: Read> Read for Part<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.multipart.read_data(buf)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
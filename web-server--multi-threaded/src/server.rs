@@ -0,0 +1,321 @@
+//! Accepting connections and speaking HTTP/1.1 on them.
+
+use std::{
+    io::{self, BufReader, ErrorKind},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc,
+        Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    Spawner, ThreadPool, h2,
+    http::{ParseError, Request, Response, Status},
+    middleware::Handler,
+};
+
+/// How long an idle keep-alive connection may hold on to a worker, unless
+/// set with [`Server::idle_timeout`].
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a write may wait on a client that is not reading, unless set
+/// with [`Server::write_timeout`].
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many HTTP/2 connections may be open at once, unless set with
+/// [`Server::h2_connections`].
+pub const DEFAULT_H2_CONNECTIONS: usize = 64;
+
+pub struct Server {
+    listener: TcpListener,
+    threads: usize,
+    h2_connections: usize,
+    idle_timeout: Duration,
+    write_timeout: Duration,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Server {
+    /// Binds the listening socket. Bind to port 0 to get any free port and
+    /// look it up with [`Server::local_addr`].
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            threads: 4,
+            h2_connections: DEFAULT_H2_CONNECTIONS,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            shutdown: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Sets the number of worker threads. There are this many for
+    /// connections and as many again for HTTP/2 streams.
+    ///
+    /// # Panics
+    ///
+    /// [`Server::run`] will panic if this is zero.
+    pub fn threads(mut self, threads: usize) -> Server {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets how many HTTP/2 connections may be open at once. Each has a
+    /// thread of its own; past the limit, prior-knowledge connections are
+    /// sent GOAWAY and upgrade requests are answered as HTTP/1.1.
+    pub fn h2_connections(mut self, max: usize) -> Server {
+        self.h2_connections = max;
+        self
+    }
+
+    pub fn idle_timeout(mut self, timeout: Duration) -> Server {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a write may make no progress before the connection is
+    /// dropped, so a client that stops reading cannot keep a worker.
+    pub fn write_timeout(mut self, timeout: Duration) -> Server {
+        self.write_timeout = timeout;
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Returns a handle that stops [`Server::run`] from another thread.
+    pub fn shutdown_handle(&self) -> io::Result<ShutdownHandle> {
+        Ok(ShutdownHandle {
+            flag: Arc::clone(&self.shutdown),
+            addr: self.local_addr()?,
+        })
+    }
+
+    /// Serves connections with `app` until shut down.
+    ///
+    /// After shutdown no new connections are accepted; the ones already open
+    /// finish their current request and this returns once they are done.
+    /// HTTP/2 connections are sent GOAWAY and finish the streams they have.
+    pub fn run(self, app: Arc<dyn Handler>) {
+        let pool = ThreadPool::new(self.threads);
+        // HTTP/2 streams get workers of their own, so they never wait behind
+        // connections that are idling in keep-alive.
+        let streams = ThreadPool::new(self.threads);
+        let spawner = streams.spawner();
+        let h2_slots = H2Slots {
+            open: Arc::new(AtomicUsize::new(0)),
+            max: self.h2_connections,
+            threads: Arc::new(Mutex::new(Vec::new())),
+        };
+
+        for stream in self.listener.incoming() {
+            if self.shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("Failed to accept connection: {e}");
+                    continue;
+                }
+            };
+            let app = Arc::clone(&app);
+            let connection_spawner = spawner.clone();
+            let h2_slots = h2_slots.clone();
+            let shutdown = Arc::clone(&self.shutdown);
+            let timeouts = Timeouts {
+                idle: self.idle_timeout,
+                write: self.write_timeout,
+            };
+
+            pool.execute(move || {
+                handle_connection(stream, app, connection_spawner, &h2_slots, shutdown, timeouts);
+            });
+        }
+
+        // Once the connection workers are done no more HTTP/2 connections
+        // start, so the ones running are all there is to wait for. The
+        // stream workers only stop once every job submitter is gone, and
+        // each HTTP/2 connection holds on to one until it closes.
+        drop(pool);
+        h2_slots.join();
+        drop(spawner);
+        drop(streams);
+    }
+}
+
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    flag: Arc<AtomicBool>,
+    addr: SocketAddr,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+        // The accept loop only looks at the flag when a connection comes in,
+        // so make one.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+/// Counts the open HTTP/2 connections, which each hold a thread, and keeps
+/// those threads so that shutdown can wait for them.
+#[derive(Clone)]
+struct H2Slots {
+    open: Arc<AtomicUsize>,
+    max: usize,
+    threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl H2Slots {
+    fn acquire(&self) -> Option<H2Slot> {
+        self.open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| (open < self.max).then_some(open + 1))
+            .ok()?;
+        Some(H2Slot(Arc::clone(&self.open)))
+    }
+
+    /// Serves an HTTP/2 connection on a thread of its own, which gives up
+    /// `slot` when it is done.
+    fn spawn(&self, slot: H2Slot, serve: impl FnOnce() + Send + 'static) {
+        let thread = thread::spawn(move || {
+            serve();
+            drop(slot);
+        });
+        let mut threads = self.threads.lock().unwrap();
+        threads.retain(|thread| !thread.is_finished());
+        threads.push(thread);
+    }
+
+    /// Waits for every HTTP/2 connection thread to finish.
+    fn join(&self) {
+        let threads = std::mem::take(&mut *self.threads.lock().unwrap());
+        for thread in threads {
+            if thread.join().is_err() {
+                println!("An HTTP/2 connection thread panicked");
+            }
+        }
+    }
+}
+
+/// One open HTTP/2 connection; frees its slot when dropped.
+struct H2Slot(Arc<AtomicUsize>);
+
+impl Drop for H2Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// How long a connection may wait on its client.
+#[derive(Clone, Copy)]
+struct Timeouts {
+    idle: Duration,
+    write: Duration,
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    app: Arc<dyn Handler>,
+    spawner: Spawner,
+    h2_slots: &H2Slots,
+    shutdown: Arc<AtomicBool>,
+    timeouts: Timeouts,
+) {
+    if let Err(e) = stream.set_read_timeout(Some(timeouts.idle)) {
+        println!("Failed to set read timeout: {e}");
+        return;
+    }
+    // This also covers the clones HTTP/2 writes through.
+    if let Err(e) = stream.set_write_timeout(Some(timeouts.write)) {
+        println!("Failed to set write timeout: {e}");
+        return;
+    }
+    // Owned rather than borrowed, so that an HTTP/2 connection can take it
+    // to a thread of its own.
+    let mut buf_reader = match stream.try_clone() {
+        Ok(reader) => BufReader::new(reader),
+        Err(e) => {
+            println!("Failed to clone connection: {e}");
+            return;
+        }
+    };
+
+    loop {
+        let mut request = match Request::read_from(&mut buf_reader) {
+            Ok(request) => request,
+            Err(ParseError::Closed) => return,
+            Err(ParseError::Io(e)) => {
+                if !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+                    println!("Failed to read request: {e}");
+                }
+                return;
+            }
+            Err(e) => {
+                let status = match e {
+                    ParseError::TooLarge => Status::HEADER_FIELDS_TOO_LARGE,
+                    _ => Status::BAD_REQUEST,
+                };
+                // We cannot tell where a malformed request ends, so this is
+                // the last response on the connection.
+                let mut response = Response::text(status, format!("{e}\n"));
+                response.headers.set("Connection", "close");
+                if let Err(e) = response.write_to(&mut &stream) {
+                    println!("Failed to write response: {e}");
+                }
+                return;
+            }
+        };
+
+        // An HTTP/2 connection reads frames until the client goes away, so
+        // it gets its own thread, as long as there are slots left, and this
+        // worker goes back to the pool.
+        if h2::is_preface(&request) {
+            drop(request);
+            match h2_slots.acquire() {
+                Some(slot) => h2_slots.spawn(slot, move || {
+                    h2::serve_prior_knowledge(&mut buf_reader, &stream, app, spawner, shutdown);
+                }),
+                None => h2::refuse(&mut buf_reader, &stream),
+            }
+            return;
+        }
+        // The upgrade is optional, so without a slot the request is simply
+        // served as HTTP/1.1.
+        if let Some(upgrade) = h2::upgrade(&request)
+            && let Some(slot) = h2_slots.acquire()
+        {
+            drop(request);
+            h2_slots.spawn(slot, move || {
+                h2::serve_upgrade(upgrade, &mut buf_reader, &stream, app, spawner, shutdown);
+            });
+            return;
+        }
+
+        let mut response = app.handle(&mut request);
+        if !request.keep_alive() || shutdown.load(Ordering::SeqCst) {
+            response.headers.set("Connection", "close");
+        } else if request.version == "HTTP/1.0" {
+            response.headers.set("Connection", "keep-alive");
+        }
+        // The next request starts after this one's body, so whatever the
+        // handler left unread has to go.
+        if !response.closes_connection() && request.body().drain().is_err() {
+            response.headers.set("Connection", "close");
+        }
+
+        if let Err(e) = response.write_to(&mut &stream) {
+            println!("Failed to write response: {e}");
+            return;
+        }
+        if response.closes_connection() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Saving uploaded files to disk.
+//!
+//! Every upload is streamed into a hidden temporary file in the upload
+//! directory and only renamed to its final name once all bytes arrived, so a
+//! reader never sees a half-written file and a failed upload leaves nothing
+//! behind.
+
+use std::{
+    env, fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Used when `UPLOAD_MAX_BYTES` is not set: 10 MiB.
+pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Longest file name we keep, in bytes.
+const MAX_FILE_NAME: usize = 255;
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    /// Where finished uploads are stored.
+    pub dir: PathBuf,
+    /// Largest request body we accept for an upload, in bytes.
+    pub max_size: u64,
+}
+
+impl UploadConfig {
+    pub fn new(dir: impl Into<PathBuf>, max_size: u64) -> UploadConfig {
+        UploadConfig {
+            dir: dir.into(),
+            max_size,
+        }
+    }
+
+    /// Reads `UPLOAD_DIR` and `UPLOAD_MAX_BYTES`, falling back to `uploads`
+    /// and [`DEFAULT_MAX_SIZE`].
+    pub fn from_env() -> UploadConfig {
+        let dir = env::var_os("UPLOAD_DIR").unwrap_or_else(|| "uploads".into());
+        let max_size = env::var("UPLOAD_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_SIZE);
+        UploadConfig::new(dir, max_size)
+    }
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    /// The body is larger than [`UploadConfig::max_size`].
+    TooLarge,
+    /// No usable file name is left after sanitizing.
+    BadFileName,
+    Io(io::Error),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UploadError::TooLarge => f.write_str("upload exceeds the size limit"),
+            UploadError::BadFileName => f.write_str("invalid file name"),
+            UploadError::Io(e) => write!(f, "i/o error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+impl From<io::Error> for UploadError {
+    fn from(e: io::Error) -> Self {
+        if e.get_ref().is_some_and(|inner| inner.is::<LimitExceeded>()) {
+            UploadError::TooLarge
+        } else {
+            UploadError::Io(e)
+        }
+    }
+}
+
+/// A file that was written and renamed into place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedFile {
+    pub name: String,
+    pub size: u64,
+    /// `false` if an existing file of the same name was replaced.
+    pub created: bool,
+}
+
+/// Turns a client supplied name into one that is safe to create in the upload
+/// directory.
+///
+/// Any directory part is dropped, characters outside `[A-Za-z0-9._-]` become
+/// `_` and leading dots are removed so the result can neither escape the
+/// directory nor be hidden.
+pub fn sanitize_file_name(name: &str) -> Option<String> {
+    // Browsers on Windows may send the full path, so split on both separators.
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let cleaned: String = base
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let cleaned = cleaned.trim_start_matches('.');
+    if cleaned.is_empty() {
+        return None;
+    }
+    Some(cleaned.chars().take(MAX_FILE_NAME).collect())
+}
+
+/// Streams `contents` to `config.dir/name`.
+///
+/// `name` is sanitized first. Nothing is left on disk if reading fails or the
+/// data turns out to be larger than the configured limit.
+pub fn save<R: Read>(config: &UploadConfig, name: &str, contents: R) -> Result<SavedFile, UploadError> {
+    let name = sanitize_file_name(name).ok_or(UploadError::BadFileName)?;
+    fs::create_dir_all(&config.dir)?;
+
+    let temp_path = config.dir.join(format!(
+        ".upload-{}-{}.part",
+        process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)?;
+
+    match write_temp(file, contents, config.max_size) {
+        Ok(size) => {
+            let final_path = config.dir.join(&name);
+            let created = !final_path.exists();
+            if let Err(e) = fs::rename(&temp_path, &final_path) {
+                let _ = fs::remove_file(&temp_path);
+                return Err(e.into());
+            }
+            Ok(SavedFile { name, size, created })
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+fn write_temp<R: Read>(mut file: File, contents: R, max_size: u64) -> Result<u64, UploadError> {
+    let mut limited = Limited::new(contents, max_size);
+    let size = io::copy(&mut limited, &mut file)?;
+    file.flush()?;
+    file.sync_all()?;
+    Ok(size)
+}
+
+/// Marker carried inside the [`io::Error`] a [`Limited`] reader returns.
+#[derive(Debug)]
+struct LimitExceeded;
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("body exceeds the size limit")
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// A reader that fails once more than `limit` bytes came through.
+///
+/// Unlike [`Read::take`] this does not silently truncate, so an oversized
+/// upload is rejected instead of being stored cut short.
+pub struct Limited<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Limited<R> {
+    pub fn new(inner: R, limit: u64) -> Limited<R> {
+        Limited {
+            inner,
+            remaining: limit,
+        }
+    }
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Allow one byte past the limit so we can tell "exactly at the limit"
+        // from "over it".
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining.saturating_add(1)).unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..max])?;
+        if n as u64 > self.remaining {
+            return Err(io::Error::other(LimitExceeded));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
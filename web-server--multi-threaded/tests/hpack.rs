@@ -0,0 +1,229 @@
+//! HPACK against the examples in RFC 7541 Appendix C.
+
+use web_server__multi_threaded::h2::hpack::{DEFAULT_TABLE_SIZE, Decoder, Encoder, HeaderList, HpackError};
+
+const NO_LIMIT: usize = usize::MAX;
+
+/// Bytes from the RFC's hex dumps, which are split into groups with spaces.
+fn hex(dump: &str) -> Vec<u8> {
+    let digits: Vec<u8> = dump.bytes().filter(u8::is_ascii_hexdigit).collect();
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
+
+fn list(headers: &[(&str, &str)]) -> HeaderList {
+    headers.iter().map(|(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec())).collect()
+}
+
+fn encode(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    Encoder::new().encode(headers.iter().copied(), &mut out);
+    out
+}
+
+/// Decodes each block in turn on one decoder, as one connection would,
+/// then checks our encoder's take on each list decodes to the same thing.
+fn check_sequence(table_size: usize, blocks: &[(&str, &[(&str, &str)])]) {
+    let mut decoder = Decoder::new(table_size);
+    for (i, (dump, expected)) in blocks.iter().enumerate() {
+        let decoded = decoder.decode(&hex(dump), NO_LIMIT);
+        assert_eq!(decoded, Ok(list(expected)), "block {}", i + 1);
+    }
+    // The encoder leaves the dynamic table alone, so any decoder will do.
+    let mut fresh = Decoder::new(0);
+    for (i, (_, expected)) in blocks.iter().enumerate() {
+        assert_eq!(fresh.decode(&encode(expected), NO_LIMIT), Ok(list(expected)), "block {} re-encoded", i + 1);
+    }
+}
+
+const REQUEST_1: &[(&str, &str)] = &[
+    (":method", "GET"),
+    (":scheme", "http"),
+    (":path", "/"),
+    (":authority", "www.example.com"),
+];
+
+const REQUEST_2: &[(&str, &str)] = &[
+    (":method", "GET"),
+    (":scheme", "http"),
+    (":path", "/"),
+    (":authority", "www.example.com"),
+    ("cache-control", "no-cache"),
+];
+
+const REQUEST_3: &[(&str, &str)] = &[
+    (":method", "GET"),
+    (":scheme", "https"),
+    (":path", "/index.html"),
+    (":authority", "www.example.com"),
+    ("custom-key", "custom-value"),
+];
+
+const RESPONSE_1: &[(&str, &str)] = &[
+    (":status", "302"),
+    ("cache-control", "private"),
+    ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+    ("location", "https://www.example.com"),
+];
+
+const RESPONSE_2: &[(&str, &str)] = &[
+    (":status", "307"),
+    ("cache-control", "private"),
+    ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+    ("location", "https://www.example.com"),
+];
+
+const RESPONSE_3: &[(&str, &str)] = &[
+    (":status", "200"),
+    ("cache-control", "private"),
+    ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+    ("location", "https://www.example.com"),
+    ("content-encoding", "gzip"),
+    ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1"),
+];
+
+#[test]
+fn requests_without_huffman_c3() {
+    check_sequence(
+        DEFAULT_TABLE_SIZE,
+        &[
+            ("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d", REQUEST_1),
+            ("8286 84be 5808 6e6f 2d63 6163 6865", REQUEST_2),
+            (
+                "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+                REQUEST_3,
+            ),
+        ],
+    );
+}
+
+#[test]
+fn requests_with_huffman_c4() {
+    check_sequence(
+        DEFAULT_TABLE_SIZE,
+        &[
+            ("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff", REQUEST_1),
+            ("8286 84be 5886 a8eb 1064 9cbf", REQUEST_2),
+            ("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf", REQUEST_3),
+        ],
+    );
+}
+
+#[test]
+fn responses_without_huffman_evict_c5() {
+    // A 256 byte table, so each response pushes out the oldest entries and
+    // the later ones still find the fields they index.
+    check_sequence(
+        256,
+        &[
+            (
+                "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a \
+                 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+                RESPONSE_1,
+            ),
+            ("4803 3330 37c1 c0bf", RESPONSE_2),
+            (
+                "88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3220 474d 54c0 5a04 \
+                 677a 6970 7738 666f 6f3d 4153 444a 4b48 514b 425a 584f 5157 454f 5049 5541 5851 5745 4f49 \
+                 553b 206d 6178 2d61 6765 3d33 3630 303b 2076 6572 7369 6f6e 3d31",
+                RESPONSE_3,
+            ),
+        ],
+    );
+}
+
+#[test]
+fn responses_with_huffman_evict_c6() {
+    check_sequence(
+        256,
+        &[
+            (
+                "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6 2d1b ff6e \
+                 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+                RESPONSE_1,
+            ),
+            ("4883 640e ffc1 c0bf", RESPONSE_2),
+            (
+                "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab 77ad 94e7 \
+                 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f 9587 3160 65c0 03ed \
+                 4ee5 b106 3d50 07",
+                RESPONSE_3,
+            ),
+        ],
+    );
+}
+
+#[test]
+fn evicted_entries_cannot_be_indexed() {
+    let mut decoder = Decoder::new(256);
+    let first = hex(
+        "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a \
+         3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+    );
+    decoder.decode(&first, NO_LIMIT).unwrap();
+    // Four entries of 222 bytes in all: 62 to 65, and nothing past them.
+    assert_eq!(decoder.decode(&hex("c1"), NO_LIMIT), Ok(list(&[(":status", "302")])));
+    assert_eq!(decoder.decode(&hex("c2"), NO_LIMIT), Err(HpackError::BadIndex(66)));
+
+    // `:status: 307` needs 42 bytes, so the oldest, `:status: 302`, goes.
+    decoder.decode(&hex("4803 3330 37"), NO_LIMIT).unwrap();
+    assert_eq!(decoder.decode(&hex("be"), NO_LIMIT), Ok(list(&[(":status", "307")])));
+    assert_eq!(decoder.decode(&hex("c1"), NO_LIMIT), Ok(list(&[("cache-control", "private")])));
+    assert_eq!(decoder.decode(&hex("c2"), NO_LIMIT), Err(HpackError::BadIndex(66)));
+
+    // An entry larger than the whole table empties it: `x` and a 300 byte
+    // value, its length past the 7 bit prefix.
+    let mut big = hex("4001 787f ad01");
+    big.extend([b'y'; 300]);
+    decoder.decode(&big, NO_LIMIT).unwrap();
+    assert_eq!(decoder.decode(&hex("be"), NO_LIMIT), Err(HpackError::BadIndex(62)));
+}
+
+#[test]
+fn size_updates_shrink_the_table_and_are_checked() {
+    let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE);
+    decoder.decode(&hex("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d"), NO_LIMIT).unwrap();
+    assert_eq!(decoder.decode(&hex("be"), NO_LIMIT), Ok(list(&[(":authority", "www.example.com")])));
+
+    // Down to 0 and back up: the update empties the table.
+    assert_eq!(decoder.decode(&hex("20 3fe1 1f 82"), NO_LIMIT), Ok(list(&[(":method", "GET")])));
+    assert_eq!(decoder.decode(&hex("be"), NO_LIMIT), Err(HpackError::BadIndex(62)));
+
+    // Nothing above what we announced, and only at the start of a block.
+    assert_eq!(decoder.decode(&hex("3fe2 1f"), NO_LIMIT), Err(HpackError::BadTableSize));
+    assert_eq!(decoder.decode(&hex("82 20"), NO_LIMIT), Err(HpackError::BadTableSize));
+}
+
+#[test]
+fn bad_huffman_strings_are_refused() {
+    let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE);
+    // `:path` with a one byte Huffman value of `/` (011000) padded with 00
+    // rather than 1s.
+    assert_eq!(decoder.decode(&hex("0481 60"), NO_LIMIT), Err(HpackError::Huffman));
+    // Correctly padded, it is fine.
+    assert_eq!(decoder.decode(&hex("0481 63"), NO_LIMIT), Ok(list(&[(":path", "/")])));
+    // Another byte of 1s makes more than the 7 bits of padding allowed.
+    assert_eq!(decoder.decode(&hex("0482 63ff"), NO_LIMIT), Err(HpackError::Huffman));
+    // Thirty 1s is the end-of-string symbol, which may not appear.
+    assert_eq!(decoder.decode(&hex("0484 ffff ffff"), NO_LIMIT), Err(HpackError::Huffman));
+}
+
+#[test]
+fn encodes_like_the_rfc_where_it_can() {
+    // C.2.2, a literal without indexing on a static name, and C.2.4, an
+    // indexed field, are exactly what the encoder emits.
+    assert_eq!(encode(&[(":path", "/sample/path")]), hex("040c 2f73 616d 706c 652f 7061 7468"));
+    assert_eq!(encode(&[(":method", "GET")]), hex("82"));
+    // C.3.1 without adding `:authority` to the dynamic table.
+    assert_eq!(encode(REQUEST_1), hex("8286 8401 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d"));
+    // C.2.3's never-indexed literal name, sent without indexing instead.
+    assert_eq!(encode(&[("password", "secret")]), hex("0008 7061 7373 776f 7264 0673 6563 7265 74"));
+
+    // A length that needs more than the 7 bit prefix, as in C.1.2.
+    let long = "v".repeat(1337);
+    let encoded = encode(&[("x-long", &long)]);
+    assert_eq!(encoded[..11], hex("0006 782d 6c6f 6e67 7fba 09"));
+    assert_eq!(Decoder::new(0).decode(&encoded, NO_LIMIT), Ok(list(&[("x-long", &long)])));
+}
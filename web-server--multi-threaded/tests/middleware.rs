@@ -0,0 +1,91 @@
+use std::sync::{Arc, Mutex};
+
+use web_server__multi_threaded::{
+    http::{Request, Response, Status},
+    middleware::{Chain, Handler, Middleware, Next, RequestId},
+};
+
+/// Records its name on the way in and on the way out.
+struct Trace {
+    name: &'static str,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl Middleware for Trace {
+    fn handle(&self, request: &mut Request<'_>, next: Next<'_>) -> Response {
+        self.log.lock().unwrap().push(format!("{} in", self.name));
+        let response = next.run(request);
+        self.log.lock().unwrap().push(format!("{} out", self.name));
+        response
+    }
+}
+
+/// Answers 401 unless the request carries an `Authorization` header.
+struct RequireAuth;
+
+impl Middleware for RequireAuth {
+    fn handle(&self, request: &mut Request<'_>, next: Next<'_>) -> Response {
+        if request.header("Authorization").is_none() {
+            return Response::new(Status::new(401, "Unauthorized"));
+        }
+        next.run(request)
+    }
+}
+
+fn ok(_: &mut Request) -> Response {
+    Response::text(Status::OK, "hi")
+}
+
+#[test]
+fn middleware_runs_in_the_order_added() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let endpoint_log = Arc::clone(&log);
+    let chain = Chain::new(move |_: &mut Request| {
+        endpoint_log.lock().unwrap().push("handler".to_string());
+        Response::new(Status::OK)
+    })
+    .with(Trace { name: "outer", log: Arc::clone(&log) })
+    .with(Trace { name: "inner", log: Arc::clone(&log) });
+
+    chain.handle(&mut Request::new("GET", "/"));
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        ["outer in", "inner in", "handler", "inner out", "outer out"]
+    );
+}
+
+#[test]
+fn middleware_can_stop_the_chain() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let chain = Chain::new(ok)
+        .with(RequireAuth)
+        .with(Trace { name: "inner", log: Arc::clone(&log) });
+
+    let response = chain.handle(&mut Request::new("GET", "/"));
+    assert_eq!(response.status.code(), 401);
+    assert!(log.lock().unwrap().is_empty());
+
+    let response = chain.handle(&mut Request::new("GET", "/").with_header("Authorization", "yes"));
+    assert_eq!(response.status, Status::OK);
+    assert_eq!(*log.lock().unwrap(), ["inner in", "inner out"]);
+}
+
+#[test]
+fn request_id_is_generated_or_kept() {
+    let chain = Chain::new(|request: &mut Request| {
+        let id = request.header(RequestId::HEADER).unwrap_or("missing").to_string();
+        Response::text(Status::OK, id)
+    })
+    .with(RequestId::new());
+
+    let first = chain.handle(&mut Request::new("GET", "/"));
+    let second = chain.handle(&mut Request::new("GET", "/"));
+    assert_eq!(first.headers.get(RequestId::HEADER), Some("1"));
+    assert_eq!(first.body, b"1");
+    assert_eq!(second.headers.get(RequestId::HEADER), Some("2"));
+
+    let response = chain.handle(&mut Request::new("GET", "/").with_header("x-request-id", "abc"));
+    assert_eq!(response.headers.get(RequestId::HEADER), Some("abc"));
+    assert_eq!(response.body, b"abc");
+}
@@ -0,0 +1,926 @@
+//! Runs the real server on an ephemeral port and talks to it over TCP.
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    path::PathBuf,
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use web_server__multi_threaded::{
+    app,
+    h2::{
+        frame::{self, FrameHeader},
+        hpack,
+    },
+    http::{Request, Response, Status},
+    server::{Server, ShutdownHandle},
+    upload::UploadConfig,
+};
+
+const MAX_UPLOAD: u64 = 1024;
+
+struct TestServer {
+    addr: SocketAddr,
+    upload_dir: PathBuf,
+    shutdown: ShutdownHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    fn start() -> TestServer {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let upload_dir = env::temp_dir().join(format!(
+            "web-server-test-{}-{}",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let server = Server::bind("127.0.0.1:0")
+            .unwrap()
+            .idle_timeout(Duration::from_millis(500));
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle().unwrap();
+        let app = Arc::new(app::app(UploadConfig::new(&upload_dir, MAX_UPLOAD)));
+        let thread = thread::spawn(move || server.run(app));
+
+        TestServer {
+            addr,
+            upload_dir,
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    }
+
+    /// Sends `raw`, closes our side and returns everything the server sent.
+    fn exchange(&self, raw: &[u8]) -> String {
+        let mut stream = self.connect();
+        stream.write_all(raw).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        out
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+        let _ = fs::remove_dir_all(&self.upload_dir);
+    }
+}
+
+struct TestResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl TestResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn read_response<R: BufRead>(reader: &mut R) -> TestResponse {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let status = line.split(' ').nth(1).unwrap().parse().unwrap();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').unwrap();
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+
+    let mut response = TestResponse {
+        status,
+        headers,
+        body: Vec::new(),
+    };
+    let length: usize = response.header("Content-Length").unwrap().parse().unwrap();
+    response.body = vec![0; length];
+    reader.read_exact(&mut response.body).unwrap();
+    response
+}
+
+fn status_of(raw_response: &str) -> u16 {
+    raw_response.split(' ').nth(1).unwrap().parse().unwrap()
+}
+
+#[test]
+fn serves_the_hello_page() {
+    let server = TestServer::start();
+    let out = server.exchange(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let response = read_response(&mut out.as_bytes());
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("text/html; charset=utf-8"));
+    assert_eq!(response.body, fs::read("hello.html").unwrap());
+}
+
+#[test]
+fn unknown_path_is_404() {
+    let server = TestServer::start();
+    let out = server.exchange(b"GET /nope HTTP/1.1\r\n\r\n");
+    assert_eq!(status_of(&out), 404);
+}
+
+#[test]
+fn query_string_is_not_part_of_the_path() {
+    let server = TestServer::start();
+    let out = server.exchange(b"GET /?a=1 HTTP/1.1\r\n\r\n");
+    assert_eq!(status_of(&out), 200);
+}
+
+#[test]
+fn malformed_requests_get_400_and_the_connection_closes() {
+    let server = TestServer::start();
+    let cases: [&[u8]; 9] = [
+        b"GET /\r\n\r\n",
+        b"GET / HTTP/1.1 extra\r\n\r\n",
+        b"GET / FTP/1.0\r\n\r\n",
+        b"GET / HTTP/1.1\r\nno colon here\r\n\r\n",
+        b"GET / HTTP/1.1\r\nBad Name: x\r\n\r\n",
+        b"PUT /uploads/a HTTP/1.1\r\nContent-Length: ten\r\n\r\n",
+        b"PUT /uploads/a HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab",
+        b"PUT /uploads/a HTTP/1.1\r\nContent-Length: 1, 2\r\n\r\nab",
+        b"PUT /uploads/a HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n0\r\n\r\n",
+    ];
+    for raw in cases {
+        let out = server.exchange(raw);
+        let response = read_response(&mut out.as_bytes());
+        assert_eq!(response.status, 400, "{}", String::from_utf8_lossy(raw));
+        assert_eq!(response.header("Connection"), Some("close"));
+    }
+}
+
+#[test]
+fn unknown_transfer_coding_is_rejected() {
+    let server = TestServer::start();
+    let out = server.exchange(b"PUT /uploads/a HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n");
+    assert_eq!(status_of(&out), 400);
+}
+
+#[test]
+fn oversized_header_line_is_431() {
+    let server = TestServer::start();
+    let mut raw = b"GET / HTTP/1.1\r\nX-Big: ".to_vec();
+    raw.extend(std::iter::repeat_n(b'a', 10_000));
+    raw.extend_from_slice(b"\r\n\r\n");
+    assert_eq!(status_of(&server.exchange(&raw)), 431);
+}
+
+#[test]
+fn too_many_headers_is_431() {
+    let server = TestServer::start();
+    let mut raw = b"GET / HTTP/1.1\r\n".to_vec();
+    for i in 0..101 {
+        raw.extend_from_slice(format!("X-{i}: y\r\n").as_bytes());
+    }
+    raw.extend_from_slice(b"\r\n");
+    assert_eq!(status_of(&server.exchange(&raw)), 431);
+}
+
+#[test]
+fn closing_without_a_request_is_fine() {
+    let server = TestServer::start();
+    assert_eq!(server.exchange(b""), "");
+    // The worker is still alive and serving.
+    assert_eq!(status_of(&server.exchange(b"GET / HTTP/1.1\r\n\r\n")), 200);
+}
+
+#[test]
+fn keep_alive_serves_several_requests_on_one_connection() {
+    let server = TestServer::start();
+    let mut stream = server.connect();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    for _ in 0..3 {
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let response = read_response(&mut reader);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Connection"), None);
+    }
+
+    stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let response = read_response(&mut reader);
+    assert_eq!(response.header("Connection"), Some("close"));
+    let mut rest = Vec::new();
+    assert_eq!(reader.read_to_end(&mut rest).unwrap(), 0);
+}
+
+#[test]
+fn pipelined_requests_are_answered_in_order() {
+    let server = TestServer::start();
+    let out = server.exchange(b"GET /nope HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\nGET /nope HTTP/1.1\r\n\r\n");
+    let mut reader = out.as_bytes();
+    let statuses: Vec<u16> = (0..3).map(|_| read_response(&mut reader).status).collect();
+    assert_eq!(statuses, [404, 200, 404]);
+}
+
+#[test]
+fn unread_body_is_skipped_before_the_next_request() {
+    let server = TestServer::start();
+    let out = server.exchange(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n");
+    let mut reader = out.as_bytes();
+    assert_eq!(read_response(&mut reader).status, 404);
+    assert_eq!(read_response(&mut reader).status, 200);
+}
+
+#[test]
+fn http_1_0_closes_unless_asked_to_keep_alive() {
+    let server = TestServer::start();
+    let out = server.exchange(b"GET / HTTP/1.0\r\n\r\nGET / HTTP/1.0\r\n\r\n");
+    let mut reader = out.as_bytes();
+    assert_eq!(read_response(&mut reader).header("Connection"), Some("close"));
+    assert!(reader.is_empty());
+
+    let out = server.exchange(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+    assert_eq!(read_response(&mut out.as_bytes()).header("Connection"), Some("keep-alive"));
+}
+
+#[test]
+fn idle_connections_are_closed() {
+    let server = TestServer::start();
+    let mut stream = server.connect();
+    let mut buf = [0; 1];
+    // Nothing sent: the server gives up after its idle timeout.
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn put_stores_the_body() {
+    let server = TestServer::start();
+    let out = server.exchange(b"PUT /uploads/note.txt HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+    assert_eq!(status_of(&out), 201);
+    assert_eq!(fs::read(server.upload_dir.join("note.txt")).unwrap(), b"hello");
+
+    let out = server.exchange(b"PUT /uploads/note.txt HTTP/1.1\r\nContent-Length: 3\r\n\r\nbye");
+    assert_eq!(status_of(&out), 200);
+    assert_eq!(fs::read(server.upload_dir.join("note.txt")).unwrap(), b"bye");
+}
+
+#[test]
+fn truncated_upload_is_400_and_leaves_nothing() {
+    let server = TestServer::start();
+    // The client goes away after 10 of the 100 bytes it announced.
+    let out = server.exchange(b"PUT /uploads/cut.txt HTTP/1.1\r\nContent-Length: 100\r\n\r\n0123456789");
+    assert_eq!(status_of(&out), 400);
+    let leftovers = fs::read_dir(&server.upload_dir).unwrap().count();
+    assert_eq!(leftovers, 0);
+}
+
+#[test]
+fn chunked_bodies_are_decoded() {
+    let server = TestServer::start();
+    let out = server.exchange(
+        b"PUT /uploads/c.txt HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+          5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nTrailer: x\r\n\r\n",
+    );
+    assert_eq!(status_of(&out), 201);
+    assert_eq!(fs::read(server.upload_dir.join("c.txt")).unwrap(), b"hello world");
+}
+
+#[test]
+fn upload_names_cannot_escape_the_directory() {
+    let server = TestServer::start();
+    let out = server.exchange(b"PUT /uploads/..%2F..%2Fx HTTP/1.1\r\nContent-Length: 1\r\n\r\nx");
+    let response = read_response(&mut out.as_bytes());
+    assert_eq!(response.status, 201);
+    assert_eq!(response.body, b"_2F.._2Fx\n");
+    assert!(server.upload_dir.join("_2F.._2Fx").exists());
+
+    let out = server.exchange(b"PUT /uploads/... HTTP/1.1\r\nContent-Length: 1\r\n\r\nx");
+    assert_eq!(status_of(&out), 400);
+}
+
+#[test]
+fn declared_oversized_upload_is_413_without_reading_it() {
+    let server = TestServer::start();
+    let mut stream = server.connect();
+    // Only the head is sent; the server must answer without waiting for the body.
+    write!(stream, "PUT /uploads/big HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_UPLOAD + 1).unwrap();
+    let response = read_response(&mut BufReader::new(stream));
+    assert_eq!(response.status, 413);
+    assert_eq!(response.header("Connection"), Some("close"));
+}
+
+#[test]
+fn chunked_upload_over_the_limit_is_413_and_leaves_nothing() {
+    let server = TestServer::start();
+    let chunk = "a".repeat(1000);
+    let raw = format!("PUT /uploads/big HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3e8\r\n{chunk}\r\n3e8\r\n{chunk}\r\n0\r\n\r\n");
+    assert_eq!(status_of(&server.exchange(raw.as_bytes())), 413);
+    let leftovers = fs::read_dir(&server.upload_dir).unwrap().count();
+    assert_eq!(leftovers, 0);
+}
+
+#[test]
+fn multipart_upload_stores_every_file() {
+    let server = TestServer::start();
+    let body = "--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        not a file\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"a\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        first\r\nnot --XyZ a boundary\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"b\"; filename=\"C:\\\\docs\\\\b.txt\"\r\n\r\n\
+        second\r\n\
+        --XyZ--\r\n";
+    let raw = format!(
+        "POST /uploads HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XyZ\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+
+    let out = server.exchange(raw.as_bytes());
+    let response = read_response(&mut out.as_bytes());
+    assert_eq!(response.status, 201);
+    assert_eq!(response.body, b"a.txt\nb.txt\n");
+    assert_eq!(
+        fs::read(server.upload_dir.join("a.txt")).unwrap(),
+        b"first\r\nnot --XyZ a boundary"
+    );
+    assert_eq!(fs::read(server.upload_dir.join("b.txt")).unwrap(), b"second");
+}
+
+#[test]
+fn multipart_errors() {
+    let server = TestServer::start();
+
+    let out = server.exchange(b"POST /uploads HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 0\r\n\r\n");
+    assert_eq!(status_of(&out), 415);
+
+    let body = "--b\r\nContent-Disposition: form-data; name=\"x\"\r\n\r\ny\r\n--b--\r\n";
+    let raw = format!(
+        "POST /uploads HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    assert_eq!(status_of(&server.exchange(raw.as_bytes())), 400);
+
+    let body = "--b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"f\"\r\n\r\ntruncated";
+    let raw = format!(
+        "POST /uploads HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    assert_eq!(status_of(&server.exchange(raw.as_bytes())), 400);
+    assert!(!server.upload_dir.join("f").exists());
+}
+
+#[test]
+fn responses_carry_a_request_id() {
+    let server = TestServer::start();
+    let out = server.exchange(b"GET / HTTP/1.1\r\nX-Request-Id: trace-me\r\n\r\n");
+    assert_eq!(read_response(&mut out.as_bytes()).header("X-Request-Id"), Some("trace-me"));
+}
+
+#[test]
+fn shutdown_stops_accepting_and_returns() {
+    let server = Server::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle().unwrap();
+    let app = Arc::new(app::app(UploadConfig::new(env::temp_dir(), MAX_UPLOAD)));
+    let thread = thread::spawn(move || server.run(app));
+
+    // A connection that is mid keep-alive when shutdown starts gets its
+    // answer and is then closed.
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut reader).status, 200);
+
+    handle.shutdown();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let response = read_response(&mut reader);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Connection"), Some("close"));
+
+    thread.join().unwrap();
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+fn write_frame(stream: &mut TcpStream, kind: u8, flags: u8, id: u32, payload: &[u8]) {
+    frame::write(stream, kind, flags, id, payload).unwrap();
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> (FrameHeader, Vec<u8>) {
+    let mut head = [0; frame::HEADER_LEN];
+    reader.read_exact(&mut head).unwrap();
+    let header = FrameHeader::parse(&head);
+    let payload = frame::read_payload(reader, &header).unwrap();
+    (header, payload)
+}
+
+#[test]
+fn http2_prior_knowledge_multiplexes_streams() {
+    let server = TestServer::start();
+    let mut stream = server.connect();
+    stream.write_all(frame::PREFACE).unwrap();
+    write_frame(&mut stream, frame::SETTINGS, 0, 0, &[]);
+
+    let mut encoder = hpack::Encoder::new();
+    for (id, path) in [(1, "/"), (3, "/nope")] {
+        let mut block = Vec::new();
+        encoder.encode(
+            [(":method", "GET"), (":scheme", "http"), (":path", path), (":authority", "localhost")],
+            &mut block,
+        );
+        write_frame(&mut stream, frame::HEADERS, frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM, id, &block);
+    }
+
+    let mut decoder = hpack::Decoder::new(hpack::DEFAULT_TABLE_SIZE);
+    let mut statuses = Vec::new();
+    let mut bodies = [Vec::new(), Vec::new()];
+    let mut saw_settings_ack = false;
+    let mut finished = 0;
+    while finished < 2 {
+        let (header, payload) = read_frame(&mut stream);
+        match header.kind {
+            frame::SETTINGS if header.has(frame::FLAG_ACK) => saw_settings_ack = true,
+            frame::HEADERS => {
+                let fields = decoder.decode(&payload, usize::MAX).unwrap();
+                assert_eq!(fields[0].0, b":status");
+                statuses.push((header.stream_id, String::from_utf8(fields[0].1.clone()).unwrap()));
+            }
+            frame::DATA => bodies[header.stream_id as usize / 2].extend_from_slice(&payload),
+            _ => {}
+        }
+        if header.has(frame::FLAG_END_STREAM) && matches!(header.kind, frame::HEADERS | frame::DATA) {
+            finished += 1;
+        }
+    }
+
+    statuses.sort();
+    assert_eq!(statuses, [(1, "200".to_string()), (3, "404".to_string())]);
+    assert!(saw_settings_ack);
+    assert_eq!(bodies[0], fs::read("hello.html").unwrap());
+    assert_eq!(bodies[1], fs::read("404.html").unwrap());
+}
+
+#[test]
+fn http2_upgrade_answers_on_stream_1() {
+    let server = TestServer::start();
+    let mut stream = server.connect();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAoAAA\r\n\r\n")
+        .unwrap();
+
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "HTTP/1.1 101 Switching Protocols\r\n");
+    while line != "\r\n" {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+    }
+
+    stream.write_all(frame::PREFACE).unwrap();
+    write_frame(&mut stream, frame::SETTINGS, 0, 0, &[]);
+
+    let (header, _) = read_frame(&mut reader);
+    assert_eq!(header.kind, frame::SETTINGS);
+    let mut body = Vec::new();
+    loop {
+        let (header, payload) = read_frame(&mut reader);
+        if header.kind == frame::DATA {
+            assert_eq!(header.stream_id, 1);
+            body.extend_from_slice(&payload);
+            if header.has(frame::FLAG_END_STREAM) {
+                break;
+            }
+        }
+    }
+    assert_eq!(body, fs::read("hello.html").unwrap());
+}
+
+#[test]
+fn http2_protocol_errors_end_in_goaway() {
+    let server = TestServer::start();
+    let mut stream = server.connect();
+    stream.write_all(frame::PREFACE).unwrap();
+    // The first frame has to be SETTINGS.
+    write_frame(&mut stream, frame::PING, 0, 0, &[0; 8]);
+
+    loop {
+        let (header, payload) = read_frame(&mut stream);
+        if header.kind == frame::GOAWAY {
+            assert_eq!(&payload[4..8], &frame::ErrorCode::PROTOCOL_ERROR.0.to_be_bytes());
+            break;
+        }
+    }
+}
+
+#[test]
+fn http2_streams_are_served_with_every_worker_holding_a_connection() {
+    const THREADS: usize = 2;
+    let server = Server::bind("127.0.0.1:0")
+        .unwrap()
+        .threads(THREADS)
+        .idle_timeout(Duration::from_secs(30));
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle().unwrap();
+    let app = Arc::new(app::app(UploadConfig::new(env::temp_dir(), MAX_UPLOAD)));
+    let thread = thread::spawn(move || server.run(app));
+    let connect = || {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    };
+
+    // A keep-alive connection that sits idle on a worker.
+    let mut idle = connect();
+    let mut idle_reader = BufReader::new(idle.try_clone().unwrap());
+    idle.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut idle_reader).status, 200);
+
+    let mut connections: Vec<TcpStream> = (0..THREADS).map(|_| connect()).collect();
+    for stream in &mut connections {
+        stream.write_all(frame::PREFACE).unwrap();
+        write_frame(stream, frame::SETTINGS, 0, 0, &[]);
+    }
+    for stream in &mut connections {
+        let mut block = Vec::new();
+        hpack::Encoder::new().encode(
+            [(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "localhost")],
+            &mut block,
+        );
+        write_frame(stream, frame::HEADERS, frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM, 1, &block);
+        loop {
+            // Times out, and fails, if the stream never gets a worker.
+            let (header, _) = read_frame(stream);
+            if header.kind == frame::HEADERS {
+                assert_eq!(header.stream_id, 1);
+                break;
+            }
+        }
+    }
+
+    drop(connections);
+    drop((idle, idle_reader));
+    handle.shutdown();
+    thread.join().unwrap();
+}
+
+#[test]
+fn http2_zero_window_client_does_not_hold_up_others() {
+    const THREADS: usize = 2;
+    let server = Server::bind("127.0.0.1:0").unwrap().threads(THREADS);
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle().unwrap();
+    let app = Arc::new(app::app(UploadConfig::new(env::temp_dir(), MAX_UPLOAD)));
+    let thread = thread::spawn(move || server.run(app));
+    let connect = || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(frame::PREFACE).unwrap();
+        stream
+    };
+    let get = |stream: &mut TcpStream, id| {
+        let mut block = Vec::new();
+        hpack::Encoder::new().encode(
+            [(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "localhost")],
+            &mut block,
+        );
+        write_frame(stream, frame::HEADERS, frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM, id, &block);
+    };
+    let read_body = |stream: &mut TcpStream, id| {
+        let mut body = Vec::new();
+        loop {
+            // Times out, and fails, if the response never arrives.
+            let (header, payload) = read_frame(stream);
+            if header.kind == frame::DATA && header.stream_id == id {
+                body.extend_from_slice(&payload);
+                if header.has(frame::FLAG_END_STREAM) {
+                    return body;
+                }
+            }
+        }
+    };
+
+    // Grants no window to any stream, with more streams than there are workers.
+    let mut stalled = connect();
+    let zero_window = frame::setting(frame::SETTINGS_INITIAL_WINDOW_SIZE, 0);
+    write_frame(&mut stalled, frame::SETTINGS, 0, 0, &zero_window);
+    for id in (1..).step_by(2).take(THREADS * 2) {
+        get(&mut stalled, id);
+    }
+
+    let mut normal = connect();
+    write_frame(&mut normal, frame::SETTINGS, 0, 0, &[]);
+    get(&mut normal, 1);
+    assert_eq!(read_body(&mut normal, 1), fs::read("hello.html").unwrap());
+
+    // The stalled responses were queued, not dropped.
+    write_frame(&mut stalled, frame::WINDOW_UPDATE, 0, 3, &0x10000u32.to_be_bytes());
+    assert_eq!(read_body(&mut stalled, 3), fs::read("hello.html").unwrap());
+
+    drop((stalled, normal));
+    handle.shutdown();
+    thread.join().unwrap();
+}
+
+#[test]
+fn http2_connections_past_the_limit_are_turned_away() {
+    let server = Server::bind("127.0.0.1:0").unwrap().h2_connections(1);
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle().unwrap();
+    let app = Arc::new(app::app(UploadConfig::new(env::temp_dir(), MAX_UPLOAD)));
+    let thread = thread::spawn(move || server.run(app));
+    let connect = || {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    };
+
+    let mut first = connect();
+    first.write_all(frame::PREFACE).unwrap();
+    write_frame(&mut first, frame::SETTINGS, 0, 0, &[]);
+    assert_eq!(read_frame(&mut first).0.kind, frame::SETTINGS);
+
+    let mut second = connect();
+    second.write_all(frame::PREFACE).unwrap();
+    write_frame(&mut second, frame::SETTINGS, 0, 0, &[]);
+    assert_eq!(read_frame(&mut second).0.kind, frame::SETTINGS);
+    let (header, payload) = read_frame(&mut second);
+    assert_eq!(header.kind, frame::GOAWAY);
+    assert_eq!(&payload[..4], &0u32.to_be_bytes());
+
+    // An upgrade is optional, so it is declined and served as HTTP/1.1.
+    let mut upgrade = connect();
+    upgrade
+        .write_all(b"GET / HTTP/1.1\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: \r\n\r\n")
+        .unwrap();
+    assert_eq!(read_response(&mut BufReader::new(upgrade)).status, 200);
+
+    drop((first, second));
+    handle.shutdown();
+    thread.join().unwrap();
+}
+
+#[test]
+fn http2_body_credit_comes_back_once_the_request_is_handled() {
+    let server = TestServer::start();
+    let mut stream = server.connect();
+    stream.write_all(frame::PREFACE).unwrap();
+    write_frame(&mut stream, frame::SETTINGS, 0, 0, &[]);
+
+    let mut block = Vec::new();
+    hpack::Encoder::new().encode(
+        [(":method", "POST"), (":scheme", "http"), (":path", "/nope"), (":authority", "localhost")],
+        &mut block,
+    );
+    write_frame(&mut stream, frame::HEADERS, frame::FLAG_END_HEADERS, 1, &block);
+    write_frame(&mut stream, frame::DATA, 0, 1, b"0123456789");
+    // Frames are handled in order, so by the PING ACK the DATA has been seen.
+    write_frame(&mut stream, frame::PING, 0, 0, &[0; 8]);
+
+    let mut updates = Vec::new();
+    loop {
+        let (header, payload) = read_frame(&mut stream);
+        if header.kind == frame::WINDOW_UPDATE {
+            updates.push((header.stream_id, u32::from_be_bytes(payload.try_into().unwrap())));
+        }
+        if header.kind == frame::PING {
+            break;
+        }
+    }
+    // Only the initial raise of the connection window.
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].0, 0);
+
+    updates.clear();
+    write_frame(&mut stream, frame::DATA, frame::FLAG_END_STREAM, 1, &[]);
+    loop {
+        let (header, payload) = read_frame(&mut stream);
+        if header.kind == frame::WINDOW_UPDATE {
+            updates.push((header.stream_id, u32::from_be_bytes(payload.try_into().unwrap())));
+        }
+        if header.kind == frame::HEADERS {
+            break;
+        }
+    }
+    assert_eq!(updates, [(0, 10)]);
+}
+
+#[test]
+fn http2_idle_timeout_waits_for_responses_short_of_window() {
+    let server = TestServer::start();
+    let mut stream = server.connect();
+    stream.write_all(frame::PREFACE).unwrap();
+    let zero_window = frame::setting(frame::SETTINGS_INITIAL_WINDOW_SIZE, 0);
+    write_frame(&mut stream, frame::SETTINGS, 0, 0, &zero_window);
+
+    let mut block = Vec::new();
+    hpack::Encoder::new().encode(
+        [(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "localhost")],
+        &mut block,
+    );
+    write_frame(&mut stream, frame::HEADERS, frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM, 1, &block);
+
+    // Several idle timeouts pass with the response stuck behind a window of 0.
+    thread::sleep(Duration::from_millis(1500));
+    write_frame(&mut stream, frame::WINDOW_UPDATE, 0, 1, &0x10000u32.to_be_bytes());
+
+    let mut body = Vec::new();
+    loop {
+        let (header, payload) = read_frame(&mut stream);
+        assert_ne!(header.kind, frame::GOAWAY, "went away with a response still queued");
+        if header.kind == frame::DATA && header.stream_id == 1 {
+            body.extend_from_slice(&payload);
+            if header.has(frame::FLAG_END_STREAM) {
+                break;
+            }
+        }
+    }
+    assert_eq!(body, fs::read("hello.html").unwrap());
+
+    // With nothing left to send, idling ends the connection as usual.
+    loop {
+        let (header, payload) = read_frame(&mut stream);
+        if header.kind == frame::GOAWAY {
+            assert_eq!(&payload[..4], &1u32.to_be_bytes());
+            assert_eq!(&payload[4..8], &frame::ErrorCode::NO_ERROR.0.to_be_bytes());
+            break;
+        }
+    }
+}
+
+/// A server with one worker of each kind whose `/big` answers with more than
+/// socket buffers hold, so a client that does not read stalls the write.
+fn start_big_server() -> (SocketAddr, ShutdownHandle, JoinHandle<()>) {
+    let server = Server::bind("127.0.0.1:0")
+        .unwrap()
+        .threads(1)
+        .write_timeout(Duration::from_millis(300));
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle().unwrap();
+    let app = Arc::new(|request: &mut Request| match request.path() {
+        "/big" => Response::with_body(Status::OK, "application/octet-stream", vec![b'x'; 64 << 20]),
+        _ => Response::text(Status::OK, "small\n"),
+    });
+    let thread = thread::spawn(move || server.run(app));
+    (addr, handle, thread)
+}
+
+#[test]
+fn a_client_that_stops_reading_loses_its_connection_not_the_worker() {
+    let (addr, handle, thread) = start_big_server();
+
+    let mut stalled = TcpStream::connect(addr).unwrap();
+    stalled.write_all(b"GET /big HTTP/1.1\r\n\r\n").unwrap();
+
+    // The only connection worker comes back once the write times out.
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut BufReader::new(stream)).status, 200);
+
+    drop(stalled);
+    handle.shutdown();
+    thread.join().unwrap();
+}
+
+#[test]
+fn http2_client_that_stops_reading_loses_its_connection_not_the_stream_worker() {
+    let (addr, handle, thread) = start_big_server();
+    let get = |stream: &mut TcpStream, path: &str| {
+        let mut block = Vec::new();
+        hpack::Encoder::new().encode(
+            [(":method", "GET"), (":scheme", "http"), (":path", path), (":authority", "localhost")],
+            &mut block,
+        );
+        write_frame(stream, frame::HEADERS, frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM, 1, &block);
+    };
+
+    // Grants all the window there is, then never reads.
+    let mut stalled = TcpStream::connect(addr).unwrap();
+    stalled.write_all(frame::PREFACE).unwrap();
+    let max_window = frame::setting(frame::SETTINGS_INITIAL_WINDOW_SIZE, frame::MAX_WINDOW as u32);
+    write_frame(&mut stalled, frame::SETTINGS, 0, 0, &max_window);
+    let increment = (frame::MAX_WINDOW - frame::DEFAULT_WINDOW) as u32;
+    write_frame(&mut stalled, frame::WINDOW_UPDATE, 0, 0, &increment.to_be_bytes());
+    get(&mut stalled, "/big");
+
+    // The only stream worker is writing `/big` until the write times out.
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(frame::PREFACE).unwrap();
+    write_frame(&mut stream, frame::SETTINGS, 0, 0, &[]);
+    get(&mut stream, "/");
+    loop {
+        let (header, payload) = read_frame(&mut stream);
+        if header.kind == frame::DATA {
+            assert_eq!(payload, b"small\n");
+            break;
+        }
+    }
+
+    // And the stalled connection was reset rather than left half written.
+    stalled.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut rest = Vec::new();
+    let ended = stalled.read_to_end(&mut rest);
+    assert!(ended.is_ok() || ended.unwrap_err().kind() == std::io::ErrorKind::ConnectionReset);
+    assert!(rest.len() < 64 << 20);
+
+    drop(stream);
+    handle.shutdown();
+    thread.join().unwrap();
+}
+
+#[test]
+fn http2_shutdown_sends_goaway_and_waits_for_open_streams() {
+    let server = Server::bind("127.0.0.1:0")
+        .unwrap()
+        .idle_timeout(Duration::from_millis(500));
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle().unwrap();
+    let app = Arc::new(app::app(UploadConfig::new(env::temp_dir(), MAX_UPLOAD)));
+    let thread = thread::spawn(move || server.run(app));
+    let get = |stream: &mut TcpStream, id| {
+        let mut block = Vec::new();
+        hpack::Encoder::new().encode(
+            [(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "localhost")],
+            &mut block,
+        );
+        write_frame(stream, frame::HEADERS, frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM, id, &block);
+    };
+
+    // A response held back by a window of 0 while shutdown starts.
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(frame::PREFACE).unwrap();
+    let zero_window = frame::setting(frame::SETTINGS_INITIAL_WINDOW_SIZE, 0);
+    write_frame(&mut stream, frame::SETTINGS, 0, 0, &zero_window);
+    get(&mut stream, 1);
+    loop {
+        if read_frame(&mut stream).0.kind == frame::HEADERS {
+            break;
+        }
+    }
+
+    handle.shutdown();
+    loop {
+        let (header, payload) = read_frame(&mut stream);
+        if header.kind == frame::GOAWAY {
+            assert_eq!(&payload[..4], &1u32.to_be_bytes());
+            assert_eq!(&payload[4..8], &frame::ErrorCode::NO_ERROR.0.to_be_bytes());
+            break;
+        }
+    }
+    assert!(!thread.is_finished(), "returned with a stream still open");
+
+    // New streams are refused; the open one still gets its body.
+    get(&mut stream, 3);
+    write_frame(&mut stream, frame::WINDOW_UPDATE, 0, 1, &0x10000u32.to_be_bytes());
+    let mut refused = false;
+    let mut body = Vec::new();
+    loop {
+        let (header, payload) = read_frame(&mut stream);
+        match (header.kind, header.stream_id) {
+            (frame::RST_STREAM, 3) => {
+                assert_eq!(payload, frame::ErrorCode::REFUSED_STREAM.0.to_be_bytes());
+                refused = true;
+            }
+            (frame::DATA, 1) => body.extend_from_slice(&payload),
+            _ => {}
+        }
+        if header.kind == frame::DATA && header.has(frame::FLAG_END_STREAM) {
+            break;
+        }
+    }
+    assert!(refused);
+    assert_eq!(body, fs::read("hello.html").unwrap());
+
+    // With nothing left the connection closes and the server returns, even
+    // though the client keeps its end open.
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    thread.join().unwrap();
+}
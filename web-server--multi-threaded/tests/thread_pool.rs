@@ -0,0 +1,95 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use web_server__multi_threaded::ThreadPool;
+
+fn counters(n: usize) -> Arc<Vec<AtomicUsize>> {
+    Arc::new((0..n).map(|_| AtomicUsize::new(0)).collect())
+}
+
+fn assert_each_ran_once(counts: &[AtomicUsize]) {
+    for (i, count) in counts.iter().enumerate() {
+        assert_eq!(count.load(Ordering::SeqCst), 1, "job {i}");
+    }
+}
+
+#[test]
+fn every_job_runs_exactly_once() {
+    const JOBS: usize = 10_000;
+    let counts = counters(JOBS);
+
+    let pool = ThreadPool::new(8);
+    for i in 0..JOBS {
+        let counts = Arc::clone(&counts);
+        pool.execute(move || {
+            counts[i].fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    // Dropping the pool waits for the queue to drain.
+    drop(pool);
+
+    assert_each_ran_once(&counts);
+}
+
+#[test]
+fn jobs_submitted_from_many_threads_run_exactly_once() {
+    const THREADS: usize = 8;
+    const PER_THREAD: usize = 2_000;
+    let counts = counters(THREADS * PER_THREAD);
+
+    let pool = ThreadPool::new(4);
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let spawner = pool.spawner();
+            let counts = Arc::clone(&counts);
+            s.spawn(move || {
+                for i in t * PER_THREAD..(t + 1) * PER_THREAD {
+                    let counts = Arc::clone(&counts);
+                    spawner.execute(move || {
+                        counts[i].fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            });
+        }
+    });
+    drop(pool);
+
+    assert_each_ran_once(&counts);
+}
+
+#[test]
+fn jobs_can_queue_more_jobs() {
+    const PARENTS: usize = 500;
+    const CHILDREN: usize = 4;
+    let counts = counters(PARENTS * (CHILDREN + 1));
+
+    let pool = ThreadPool::new(3);
+    for p in 0..PARENTS {
+        let spawner = pool.spawner();
+        let counts = Arc::clone(&counts);
+        pool.execute(move || {
+            let base = p * (CHILDREN + 1);
+            counts[base].fetch_add(1, Ordering::SeqCst);
+            for c in 1..=CHILDREN {
+                let counts = Arc::clone(&counts);
+                spawner.execute(move || {
+                    counts[base + c].fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        });
+    }
+    drop(pool);
+
+    assert_each_ran_once(&counts);
+}
+
+#[test]
+#[should_panic]
+fn zero_threads_is_rejected() {
+    ThreadPool::new(0);
+}